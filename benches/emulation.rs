@@ -0,0 +1,44 @@
+//! Measures how many frames per second the emulator runs without a window or audio.
+//! Throughput is reported in frames, so "elem/s" is emulated frames per second.
+//!
+//! cargo bench --bench emulation
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nesrs::emu::{cartridge::Cartridge, nes::Nes, palette::Palette};
+
+// Frames run before measuring, to get past the ROM's startup code
+const WARMUP_FRAMES: u32 = 30;
+
+const ROMS: [(&str, &str); 2] = [
+    // Tests every instruction with little on screen, so it mostly measures the CPU
+    ("cpu", "assets/test_roms/instr_test-v5/01-basics.nes"),
+    // Waits in a loop while showing its menu, so it mostly measures the PPU
+    ("ppu", "assets/test_roms/nestest.nes"),
+];
+
+fn booted_nes(path: &str) -> Nes {
+    let cartridge = Cartridge::new(path).unwrap();
+    let mut nes = Nes::new(
+        Palette::load("assets/palettes/2C02G.pal").unwrap(),
+        cartridge.region(),
+    );
+    nes.load_cartridge(cartridge);
+    nes.power_on();
+    nes.run_frames(WARMUP_FRAMES);
+    nes
+}
+
+fn advance_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("advance_frame");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, path) in ROMS {
+        let mut nes = booted_nes(path);
+        group.bench_function(name, |b| b.iter(|| nes.advance_frame()));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, advance_frame);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Runs a ROM without a window or audio and saves the last frame as a PNG,
+//! for checking rendering output in CI.
+//!
+//! cargo run --release --example headless -- <rom> --frames 60 --out frame.png
+
+use std::{fs::File, io::BufWriter};
+
+use anyhow::Result;
+use clap::Parser;
+use nesrs::{
+    emu::{cartridge::Cartridge, nes::Nes, palette::Palette},
+    utils::encode_png,
+};
+
+#[derive(Parser, Debug)]
+#[command(about = "Runs a ROM headlessly and saves the final frame as a PNG")]
+struct Args {
+    /// Path to the iNES ROM to run
+    rom_path: String,
+
+    /// Number of frames to run before saving
+    #[arg(long, default_value_t = 60)]
+    frames: u32,
+
+    /// Where to write the PNG
+    #[arg(long, default_value = "frame.png")]
+    out: String,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let cartridge = Cartridge::new(&args.rom_path)?;
+    let mut nes = Nes::new(
+        Palette::load("assets/palettes/2C02G.pal")?,
+        cartridge.region(),
+    );
+    nes.load_cartridge(cartridge);
+    nes.power_on();
+    nes.run_frames(args.frames);
+
+    encode_png(nes.screen(), BufWriter::new(File::create(&args.out)?))?;
+    println!("Wrote frame {} to {}", args.frames, args.out);
+
+    Ok(())
+}
@@ -1,26 +1,99 @@
-use super::emu::consts::CLOCK_SPEED;
+use super::emu::{apu::FilterChain, region::Region};
+use super::utils::WavWriter;
+use anyhow::Result;
 use ringbuf::{storage::Heap, traits::*, wrap::caching::Caching, HeapRb, SharedRb};
-use std::sync::Arc;
-
-const TIME_PER_CLOCK: f64 = 1.0 / CLOCK_SPEED as f64;
+use std::{fs::File, io::BufWriter, path::Path, sync::Arc};
 
 pub type AudioBufferProducer = Caching<Arc<SharedRb<Heap<f32>>>, true, false>;
 pub type AudioBufferConsumer = Caching<Arc<SharedRb<Heap<f32>>>, false, true>;
 
+/// Scales samples by the loudest recent peak, so quiet games play at full volume without clipping.
+/// Muted APU channels are left out before this, so muting a loud channel makes the rest louder.
+struct PeakNormalizer {
+    peak: f32,
+}
+
+impl PeakNormalizer {
+    /// How much the tracked peak falls each sample, about a 2 second release at 48 kHz
+    const DECAY: f32 = 0.99999;
+    /// Limits the gain to 10x, so silence and noise aren't blown up
+    const MIN_PEAK: f32 = 0.1;
+
+    fn new() -> Self {
+        PeakNormalizer {
+            peak: Self::MIN_PEAK,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        self.peak = (self.peak * Self::DECAY)
+            .max(sample.abs())
+            .max(Self::MIN_PEAK);
+        (sample / self.peak).clamp(-1.0, 1.0)
+    }
+}
+
+/// Samples up to this loud are left alone by `soft_clip`.
+const SOFT_CLIP_THRESHOLD: f32 = 0.8;
+
+/// Squeezes samples louder than `SOFT_CLIP_THRESHOLD` into what's left of the -1.0 to 1.0 range,
+/// so peaks are rounded off instead of cut flat. The curve starts out with the same slope as the
+/// samples below the threshold, so there's no kink where it takes over.
+fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_CLIP_THRESHOLD {
+        return sample;
+    }
+
+    let headroom = 1.0 - SOFT_CLIP_THRESHOLD;
+    let clipped =
+        SOFT_CLIP_THRESHOLD + headroom * ((magnitude - SOFT_CLIP_THRESHOLD) / headroom).tanh();
+    clipped.copysign(sample)
+}
+
+/// How full the audio buffer is, so the frontend can tell whether it's keeping up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioQueueStats {
+    /// Samples waiting to be played.
+    pub queued: usize,
+    pub capacity: usize,
+    /// Samples thrown away because the buffer was full, since the output was created.
+    pub dropped: u64,
+}
+
 pub struct AudioOutput {
+    sample_rate: u32,
     acc: f64,
+    clock_speed: f64,
+    /// Set by `set_speed`, kept when the region changes.
+    speed: f64,
+    time_per_clock: f64,
     time_between_samples: f64,
+    /// The sample from the clock before an output sample falls, to interpolate from.
+    previous_sample: f32,
+    filter: FilterChain,
     producer: AudioBufferProducer,
     buffer: Vec<f32>,
     buffer_sample_index: usize,
+    dropped: u64,
+    /// Output silence, and don't complain when the buffer fills up.
+    muted: bool,
+    normalizer: Option<PeakNormalizer>,
+    soft_clip: bool,
+    /// Every sample pushed while not muted is also written here, see `start_recording`.
+    recording: Option<WavWriter<BufWriter<File>>>,
 }
 
 impl AudioOutput {
-    pub fn new(sample_rate: usize) -> (Self, AudioBufferConsumer) {
+    pub const DEFAULT_LATENCY_MS: u32 = 100;
+
+    /// The buffer holds `latency_ms` worth of samples and starts half full.
+    /// Lower latencies make the sound respond sooner, but underrun and pop more easily.
+    pub fn new(sample_rate: usize, latency_ms: u32, region: Region) -> (Self, AudioBufferConsumer) {
         let sample_rate = sample_rate as f64;
 
-        let latency_frames = (100.0 / 1000.0) * sample_rate;
-        let latency_samples = latency_frames as usize;
+        let latency_frames = (latency_ms as f64 / 1000.0) * sample_rate;
+        let latency_samples = (latency_frames as usize).max(2);
 
         let rb = HeapRb::<f32>::new(latency_samples);
 
@@ -33,34 +106,306 @@ impl AudioOutput {
 
         (
             AudioOutput {
+                sample_rate: sample_rate as u32,
                 acc: 0.0,
+                clock_speed: region.clock_speed() as f64,
+                speed: 1.0,
+                time_per_clock: 1.0 / region.clock_speed() as f64,
                 time_between_samples: 1.0 / sample_rate,
+                previous_sample: 0.0,
+                filter: FilterChain::new(sample_rate as usize),
                 producer: prod,
-                buffer: vec![0.0; 256],
+                // Pushed in chunks, which have to be small enough for short buffers to keep up
+                buffer: vec![0.0; (latency_samples / 4).clamp(1, 256)],
                 buffer_sample_index: 0,
+                dropped: 0,
+                muted: false,
+                normalizer: None,
+                soft_clip: false,
+                recording: None,
             },
             cons,
         )
     }
 
-    pub fn try_push_sample(&mut self, sample: f32) {
-        self.acc += TIME_PER_CLOCK;
+    /// Samples at the PPU clock speed of `region` from now on, at the speed set by `set_speed`.
+    pub fn set_region(&mut self, region: Region) {
+        self.clock_speed = region.clock_speed() as f64;
+        self.time_per_clock = 1.0 / (self.clock_speed * self.speed);
+    }
+
+    /// While muted, samples aren't computed and silence is pushed instead, up to the buffer's
+    /// usual fill so there's no backlog of it once unmuted.
+    /// Used when running faster than real time, where the samples would overflow the buffer.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Stretches each clock over `1 / speed` of its normal time, for running slower or faster
+    /// than real time. The pitch changes along with the speed, but the buffer stays as full
+    /// as at normal speed, so slow motion doesn't underrun.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.time_per_clock = 1.0 / (self.clock_speed * speed);
+    }
+
+    /// Samples are mono, and are duplicated to every channel of the output device by `start_audio`.
+    /// Normalizing scales them to fill the device's -1.0 to 1.0 range, see `PeakNormalizer`.
+    pub fn set_normalized(&mut self, normalized: bool) {
+        self.normalizer = normalized.then(PeakNormalizer::new);
+    }
+
+    /// Rounds off peaks that would go past the device's range, see `soft_clip`.
+    /// Off by default, so the output is exactly what the console would make.
+    pub fn set_soft_clip(&mut self, soft_clip: bool) {
+        self.soft_clip = soft_clip;
+    }
+
+    /// Starts writing the output to a WAV file at the output sample rate,
+    /// replacing any recording already in progress.
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.stop_recording()?;
+        self.recording = Some(WavWriter::create(path, self.sample_rate)?);
+        Ok(())
+    }
+
+    /// Writes out the samples that haven't been pushed yet and finalizes the WAV file.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        if let Some(mut recording) = self.recording.take() {
+            if !self.muted {
+                recording.write_samples(&self.buffer[..self.buffer_sample_index])?;
+            }
+            recording.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn queue_stats(&self) -> AudioQueueStats {
+        AudioQueueStats {
+            queued: self.producer.occupied_len(),
+            capacity: self.producer.capacity().get(),
+            dropped: self.dropped,
+        }
+    }
+
+    /// Advances the output by one clock, pushing a sample whenever enough time has passed for
+    /// the output sample rate. Output samples fall between two clocks, so they're linearly
+    /// interpolated from the samples produced by `sample` on either side, which is only called
+    /// on the clocks around an output sample.
+    pub fn try_push_sample<F: FnMut() -> f32>(&mut self, mut sample: F) {
+        self.acc += self.time_per_clock;
+
+        let mut current = None;
         while self.acc >= self.time_between_samples {
-            self.buffer[self.buffer_sample_index] = sample;
+            self.acc -= self.time_between_samples;
+
+            self.buffer[self.buffer_sample_index] = if self.muted {
+                0.0
+            } else {
+                let current = *current.get_or_insert_with(&mut sample);
+                // How far the output sample is from the last clock towards this one
+                let t = 1.0 - (self.acc / self.time_per_clock) as f32;
+                let filtered = self
+                    .filter
+                    .process(self.previous_sample + (current - self.previous_sample) * t);
+                let normalized = match &mut self.normalizer {
+                    Some(normalizer) => normalizer.process(filtered),
+                    None => filtered,
+                };
+                if self.soft_clip {
+                    soft_clip(normalized)
+                } else {
+                    normalized
+                }
+            };
             self.buffer_sample_index += 1;
 
             if self.buffer_sample_index == self.buffer.len() {
-                let pushed = self.producer.push_slice(&self.buffer);
-                if pushed != self.buffer.len() {
-                    log::warn!(
-                        "Audio buffer is full, dropped {} samples",
-                        self.buffer.len() - pushed
-                    );
+                self.record_buffer();
+
+                // Silence only has to keep the buffer at the fill it started with. Any more
+                // would all have to play out before the sound comes back when unmuted.
+                let pushed = if self.muted && self.producer.occupied_len() >= self.target_fill() {
+                    0
+                } else {
+                    self.producer.push_slice(&self.buffer)
+                };
+                if pushed != self.buffer.len() && !self.muted {
+                    let dropped = self.buffer.len() - pushed;
+                    self.dropped += dropped as u64;
+                    log::warn!("Audio buffer is full, dropped {} samples", dropped);
                 }
                 self.buffer_sample_index = 0;
             }
+        }
 
-            self.acc -= self.time_between_samples;
+        if !self.muted && self.acc + self.time_per_clock >= self.time_between_samples {
+            self.previous_sample = *current.get_or_insert_with(&mut sample);
+        }
+    }
+
+    /// How many samples the buffer holds when the output is created, half of its capacity.
+    fn target_fill(&self) -> usize {
+        self.producer.capacity().get() / 2
+    }
+
+    fn record_buffer(&mut self) {
+        if self.muted {
+            return;
+        }
+        if let Some(recording) = &mut self.recording {
+            if let Err(err) = recording.write_samples(&self.buffer) {
+                log::error!("Failed to record audio, stopping: {}", err);
+                self.recording = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalizer_fills_range_without_clipping() {
+        let mut normalizer = PeakNormalizer::new();
+        let quiet: Vec<_> = (0..48000)
+            .map(|i| normalizer.process(0.2 * (i as f32 / 10.0).sin()))
+            .collect();
+        let peak = quiet.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.99 && peak <= 1.0, "{}", peak);
+
+        // A sudden loud sample is clamped instead of clipping
+        assert_eq!(normalizer.process(3.0), 1.0);
+        assert!(normalizer.process(-3.0) >= -1.0);
+    }
+
+    #[test]
+    fn soft_clip_stays_in_range() {
+        assert_eq!(soft_clip(0.5), 0.5);
+        assert_eq!(soft_clip(-SOFT_CLIP_THRESHOLD), -SOFT_CLIP_THRESHOLD);
+
+        // A sine wave 3 times too loud, which would be cut flat at the top and bottom
+        let input: Vec<_> = (0..4800).map(|i| 3.0 * (i as f32 / 100.0).sin()).collect();
+        let output: Vec<_> = input.iter().map(|&s| soft_clip(s)).collect();
+        assert!(output.iter().all(|s| (-1.0..=1.0).contains(s)));
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.95, "{}", peak);
+
+        // Never steeper than the input, so there are no jumps
+        for (i, o) in input.windows(2).zip(output.windows(2)) {
+            assert!((o[1] - o[0]).abs() <= (i[1] - i[0]).abs() + 1e-6);
+        }
+    }
+
+    #[test]
+    fn latency_sets_buffer_size() {
+        let (mut output, mut consumer) = AudioOutput::new(48000, 20, Region::Ntsc);
+        let stats = output.queue_stats();
+        assert_eq!(stats.capacity, 960);
+        assert_eq!(stats.queued, 480);
+
+        // A tenth of a second of audio with nothing playing it overflows the buffer
+        for _ in 0..Region::Ntsc.clock_speed() / 10 {
+            output.try_push_sample(|| 0.5);
+        }
+        let stats = output.queue_stats();
+        assert_eq!(stats.queued, stats.capacity);
+        assert!(stats.dropped > 0);
+
+        consumer.clear();
+        assert_eq!(output.queue_stats().queued, 0);
+    }
+
+    #[test]
+    fn resampling_preserves_frequency() {
+        const FREQUENCY: f64 = 1000.0;
+
+        for sample_rate in [44100, 48000, 96000] {
+            let (mut output, mut consumer) = AudioOutput::new(sample_rate, 1000, Region::Ntsc);
+            consumer.clear();
+
+            let clock_speed = Region::Ntsc.clock_speed() as f64;
+            let mut samples = Vec::new();
+            for i in 0..Region::Ntsc.clock_speed() {
+                let t = i as f64 / clock_speed;
+                output
+                    .try_push_sample(|| (2.0 * std::f64::consts::PI * FREQUENCY * t).sin() as f32);
+                samples.extend(consumer.pop_iter());
+            }
+
+            // One second of audio, give or take the last partly filled chunk
+            assert!(
+                samples.len().abs_diff(sample_rate) <= 256,
+                "{}",
+                samples.len()
+            );
+
+            // Skip the filters settling, then count the rising zero crossings
+            let settled = &samples[sample_rate / 10..];
+            let crossings = settled
+                .windows(2)
+                .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+                .count() as f64;
+            let measured = crossings * sample_rate as f64 / settled.len() as f64;
+            assert!(
+                (measured - FREQUENCY).abs() < 2.0,
+                "{} Hz at {} Hz",
+                measured,
+                sample_rate
+            );
+        }
+    }
+
+    #[test]
+    fn speed_scales_sample_count() {
+        let count_samples = |speed| {
+            let (mut output, mut consumer) = AudioOutput::new(48000, 1000, Region::Ntsc);
+            output.set_speed(speed);
+            consumer.clear();
+
+            let mut count = 0;
+            for _ in 0..Region::Ntsc.clock_speed() / 10 {
+                output.try_push_sample(|| 0.0);
+                count += consumer.pop_iter().count();
+            }
+            count
+        };
+
+        // A tenth of a second of emulation, at half speed it fills a fifth of a second
+        assert!(count_samples(1.0).abs_diff(4800) <= 256);
+        assert!(count_samples(0.5).abs_diff(9600) <= 256);
+    }
+
+    #[test]
+    fn muted_keeps_usual_fill() {
+        let (mut output, _consumer) = AudioOutput::new(48000, 100, Region::Ntsc);
+        let usual = output.queue_stats().queued;
+
+        // A second of fast-forward with nothing playing the silence
+        output.set_muted(true);
+        for _ in 0..Region::Ntsc.clock_speed() {
+            output.try_push_sample(|| 0.5);
         }
+        output.set_muted(false);
+
+        let stats = output.queue_stats();
+        assert_eq!(stats.queued, usual);
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn region_keeps_speed() {
+        let (mut output, _consumer) = AudioOutput::new(48000, 1000, Region::Ntsc);
+        output.set_speed(0.5);
+        output.set_region(Region::Pal);
+        assert_eq!(
+            output.time_per_clock,
+            1.0 / (Region::Pal.clock_speed() as f64 * 0.5)
+        );
     }
 }
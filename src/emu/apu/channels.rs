@@ -1,4 +1,4 @@
-use crate::emu::bits::IntoBit;
+use crate::emu::{bits::IntoBit, region::Region};
 
 use super::components::{Divider, Envelope, LengthCounter, LinearCounter, Sweep};
 
@@ -133,7 +133,16 @@ impl TriangleChannel {
     }
 
     pub fn clock(&mut self) {
-        if self.timer.clock() && !self.length_counter.silenced() && !self.linear_counter.silenced()
+        // Periods below 2 make a tone too high to hear, which the TV's filters turn into a
+        // level halfway up the sequence, so the output would pop to it and back. Holding the
+        // current step instead avoids that, like most emulators do, though it isn't what
+        // the hardware does. Mega Man 2 uses period 0 to silence the channel.
+        // See: https://www.nesdev.org/wiki/APU_Triangle
+        let ultrasonic = self.timer.reload < 2;
+        if self.timer.clock()
+            && !self.length_counter.silenced()
+            && !self.linear_counter.silenced()
+            && !ultrasonic
         {
             self.sequence_position = (self.sequence_position + 1) % 32;
         }
@@ -147,6 +156,9 @@ impl TriangleChannel {
 const NOISE_PERIOD_LOOKUP: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
+const NOISE_PERIOD_LOOKUP_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
 
 pub(crate) struct NoiseChannel {
     pub length_counter: LengthCounter,
@@ -154,6 +166,7 @@ pub(crate) struct NoiseChannel {
     mode: bool,
     timer: Divider<u16>,
     shift_register: u16,
+    region: Region,
 }
 
 impl Default for NoiseChannel {
@@ -164,13 +177,24 @@ impl Default for NoiseChannel {
             mode: false,
             timer: Divider::default(),
             shift_register: 1,
+            region: Region::default(),
         }
     }
 }
 
 impl NoiseChannel {
-    pub fn new() -> Self {
-        NoiseChannel::default()
+    pub fn new(region: Region) -> Self {
+        NoiseChannel {
+            region,
+            ..Default::default()
+        }
+    }
+
+    fn period(&self, index: u8) -> u16 {
+        match self.region {
+            Region::Ntsc => NOISE_PERIOD_LOOKUP[index as usize],
+            Region::Pal => NOISE_PERIOD_LOOKUP_PAL[index as usize],
+        }
     }
 
     pub fn sample(&self) -> u8 {
@@ -189,7 +213,7 @@ impl NoiseChannel {
 
     pub fn write_reg2(&mut self, data: u8) {
         self.mode = data & 0b1000_0000 != 0;
-        self.timer.reload = NOISE_PERIOD_LOOKUP[(data & 0x0F) as usize];
+        self.timer.reload = self.period(data & 0x0F);
     }
 
     pub fn write_reg3(&mut self, data: u8) {
@@ -216,18 +240,161 @@ impl NoiseChannel {
     }
 }
 
-// TODO: Implement this channel
-#[derive(Default)]
+// https://www.nesdev.org/wiki/APU_DMC
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATES_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Plays 1 bit delta-encoded samples, which the CPU fetches from memory with DMA.
+#[derive(Default, Debug)]
 pub(crate) struct DCPMChannel {
-    enabled: bool,
+    region: Region,
+    irq_enabled: bool,
+    loop_flag: bool,
+    timer: Divider<u16>,
+    interrupt: bool,
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
 }
 
 impl DCPMChannel {
-    pub fn new() -> Self {
-        DCPMChannel::default()
+    pub fn new(region: Region) -> Self {
+        let mut channel = DCPMChannel {
+            region,
+            bits_remaining: 8,
+            silence: true,
+            ..Default::default()
+        };
+        channel.timer.reload = channel.rate(0) - 1;
+        channel
+    }
+
+    fn rate(&self, index: u8) -> u16 {
+        match self.region {
+            Region::Ntsc => DMC_RATES[index as usize],
+            Region::Pal => DMC_RATES_PAL[index as usize],
+        }
+    }
+
+    pub fn sample(&self) -> u8 {
+        self.output_level
+    }
+
+    pub fn write_reg1(&mut self, data: u8) {
+        self.irq_enabled = data & 0x80 != 0;
+        if !self.irq_enabled {
+            self.interrupt = false;
+        }
+        self.loop_flag = data & 0x40 != 0;
+        self.timer.reload = self.rate(data & 0x0F) - 1;
+    }
+
+    pub fn write_reg2(&mut self, data: u8) {
+        self.output_level = data & 0x7F;
+    }
+
+    pub fn write_reg3(&mut self, data: u8) {
+        self.sample_address = 0xC000 | ((data as u16) << 6);
     }
 
+    pub fn write_reg4(&mut self, data: u8) {
+        self.sample_length = ((data as u16) << 4) + 1;
+    }
+
+    /// Any write to $4015 acknowledges the interrupt. Enabling the channel only restarts the
+    /// sample once the last one has finished, so a sample that's still playing carries on.
     pub fn set_enabled(&mut self, enabled: bool) {
-        self.enabled = enabled;
+        self.interrupt = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Set when a sample that doesn't loop runs out of bytes, if IRQs are enabled in $4010.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    /// Whether there are sample bytes left to play, as reported by $4015.
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn bytes_remaining(&self) -> u16 {
+        self.bytes_remaining
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// The address of the next sample byte, if the sample buffer needs to be refilled.
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Receives the sample byte fetched by the CPU from `dma_address`.
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps around to $8000 instead of $0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
+            }
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.timer.clock() {
+            return;
+        }
+
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
     }
 }
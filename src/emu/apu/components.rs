@@ -49,6 +49,14 @@ impl LengthCounter {
     pub fn silenced(&self) -> bool {
         self.counter == 0
     }
+
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
 }
 
 #[derive(Default, Debug)]
@@ -0,0 +1,119 @@
+use std::f32::consts::PI;
+
+/// First order high-pass or low-pass filter.
+/// See: https://en.wikipedia.org/wiki/High-pass_filter#Discrete-time_realization
+#[derive(Debug)]
+enum Filter {
+    HighPass {
+        alpha: f32,
+        prev_input: f32,
+        prev_output: f32,
+    },
+    LowPass {
+        alpha: f32,
+        prev_output: f32,
+    },
+}
+
+impl Filter {
+    fn high_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        Filter::HighPass {
+            alpha: rc / (rc + dt),
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    fn low_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        Filter::LowPass {
+            alpha: dt / (rc + dt),
+            prev_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        match self {
+            Filter::HighPass {
+                alpha,
+                prev_input,
+                prev_output,
+            } => {
+                let output = *alpha * (*prev_output + input - *prev_input);
+                *prev_input = input;
+                *prev_output = output;
+                output
+            }
+            Filter::LowPass { alpha, prev_output } => {
+                let output = *prev_output + *alpha * (input - *prev_output);
+                *prev_output = output;
+                output
+            }
+        }
+    }
+}
+
+/// The filters applied to the NES's audio output before it reaches the TV.
+/// See: https://www.nesdev.org/wiki/APU_Mixer
+#[derive(Debug)]
+pub struct FilterChain {
+    filters: [Filter; 3],
+}
+
+impl FilterChain {
+    pub fn new(sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        FilterChain {
+            filters: [
+                Filter::high_pass(sample_rate, 90.0),
+                Filter::high_pass(sample_rate, 440.0),
+                Filter::low_pass(sample_rate, 14000.0),
+            ],
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn high_pass_removes_dc_offset() {
+        let mut chain = FilterChain::new(44100);
+
+        let first = chain.process(0.5);
+        assert!(first > 0.1);
+
+        let mut last = first;
+        for _ in 0..44100 {
+            last = chain.process(0.5);
+        }
+        assert!(last.abs() < 1e-3, "DC offset remained: {}", last);
+    }
+
+    #[test]
+    fn low_pass_smooths_step() {
+        let mut filter = Filter::low_pass(44100.0, 14000.0);
+
+        let first = filter.process(1.0);
+        assert!(first > 0.0 && first < 1.0);
+
+        let mut last = first;
+        for _ in 0..100 {
+            last = filter.process(1.0);
+        }
+        assert!((last - 1.0).abs() < 1e-3);
+    }
+}
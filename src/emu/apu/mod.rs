@@ -1,15 +1,59 @@
 use channels::{DCPMChannel, NoiseChannel, PulseChannel, TriangleChannel};
 
 use self::channels::PulseChannelNumber;
+pub use self::filters::FilterChain;
+use super::region::Region;
 
 mod channels;
 mod components;
+mod filters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApuChannel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+/// Whether a channel is enabled in $4015, and how much longer it will play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStatus {
+    pub enabled: bool,
+    /// The length counter, or the bytes of sample left for the DMC.
+    pub length: u16,
+}
+
+impl ApuChannel {
+    pub const ALL: [ApuChannel; 5] = [
+        ApuChannel::Pulse1,
+        ApuChannel::Pulse2,
+        ApuChannel::Triangle,
+        ApuChannel::Noise,
+        ApuChannel::Dmc,
+    ];
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
 
 enum SequenceMode {
     FourStep,
     FiveStep,
 }
 
+/// CPU cycles at which the frame counter clocks the channels. The last two are the
+/// final steps of the 4 and 5 step sequences.
+/// See: https://www.nesdev.org/wiki/APU_Frame_Counter
+fn frame_counter_steps(region: Region) -> [u64; 5] {
+    match region {
+        Region::Ntsc => [7457, 14913, 22371, 29829, 37281],
+        Region::Pal => [8313, 16627, 24939, 33253, 41565],
+    }
+}
+
 pub struct Apu {
     pulse1: PulseChannel,
     pulse2: PulseChannel,
@@ -18,41 +62,124 @@ pub struct Apu {
     dcpm: DCPMChannel,
 
     // Frame sequencer
+    region: Region,
     cycle: u64,
     mode: SequenceMode,
     frame_interrupt: bool,
+    // Set if the frame interrupt was raised on the last clock, reading $4015 then doesn't clear it
+    frame_interrupt_raised: bool,
     irq_disable: bool,
     // Counts clock cycles until the timer reset/quarter + half frame clocks
     // are executed after a write to 0x4017
     status_write_effect_timer: u8,
+
+    // Channels left out of the mix, one bit per ApuChannel
+    muted_channels: u8,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
 }
 
 impl Apu {
-    pub fn new() -> Apu {
+    pub fn new(region: Region) -> Apu {
         Apu {
             pulse1: PulseChannel::new(PulseChannelNumber::One),
             pulse2: PulseChannel::new(PulseChannelNumber::Two),
             triangle: TriangleChannel::new(),
-            noise: NoiseChannel::new(),
-            dcpm: DCPMChannel::new(),
+            noise: NoiseChannel::new(region),
+            dcpm: DCPMChannel::new(region),
 
+            region,
             cycle: 0,
             mode: SequenceMode::FourStep,
             frame_interrupt: false,
+            frame_interrupt_raised: false,
             irq_disable: false,
             status_write_effect_timer: 0,
+
+            muted_channels: 0,
+        }
+    }
+
+    /// Takes effect at the next power on, which rebuilds the channels for the region.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Clears all of the APU's state. Channel mutes are a frontend setting, so they're kept.
+    pub fn power_on(&mut self) {
+        *self = Apu {
+            muted_channels: self.muted_channels,
+            ..Apu::new(self.region)
+        };
+    }
+
+    /// The reset button silences every channel, like writing 0 to $4015,
+    /// and restarts the frame counter without changing its mode.
+    /// A $4017 write that hadn't taken effect yet is dropped.
+    /// See: https://www.nesdev.org/wiki/CPU_power_up_state
+    pub fn reset(&mut self) {
+        self.write(0x4015, 0x00);
+        self.frame_interrupt = false;
+        self.frame_interrupt_raised = false;
+        self.status_write_effect_timer = 0;
+        self.cycle = 0;
+    }
+
+    /// Muting a channel only silences its output, so it keeps running in the background.
+    pub fn set_channel_enabled(&mut self, channel: ApuChannel, enabled: bool) {
+        if enabled {
+            self.muted_channels &= !channel.mask();
+        } else {
+            self.muted_channels |= channel.mask();
+        }
+    }
+
+    pub fn is_channel_enabled(&self, channel: ApuChannel) -> bool {
+        self.muted_channels & channel.mask() == 0
+    }
+
+    /// For debugging, see `ChannelStatus`. Unlike a $4015 read, this has no side effects.
+    pub fn channel_status(&self, channel: ApuChannel) -> ChannelStatus {
+        let length_counter = match channel {
+            ApuChannel::Pulse1 => &self.pulse1.length_counter,
+            ApuChannel::Pulse2 => &self.pulse2.length_counter,
+            ApuChannel::Triangle => &self.triangle.length_counter,
+            ApuChannel::Noise => &self.noise.length_counter,
+            ApuChannel::Dmc => {
+                return ChannelStatus {
+                    enabled: self.dcpm.active(),
+                    length: self.dcpm.bytes_remaining(),
+                }
+            }
+        };
+
+        ChannelStatus {
+            enabled: length_counter.enabled(),
+            length: length_counter.counter() as u16,
+        }
+    }
+
+    fn channel_output(&self, channel: ApuChannel, output: u8) -> f32 {
+        if self.is_channel_enabled(channel) {
+            output as f32
+        } else {
+            0.0
         }
     }
 
     pub fn sample(&self) -> f32 {
         // See: https://www.nesdev.org/wiki/APU_Mixer
-        let p1 = self.pulse1.sample() as f32;
-        let p2 = self.pulse2.sample() as f32;
+        let p1 = self.channel_output(ApuChannel::Pulse1, self.pulse1.sample());
+        let p2 = self.channel_output(ApuChannel::Pulse2, self.pulse2.sample());
         let pulse_out = 95.88 / ((8128.0 / (p1 + p2)) + 100.0);
 
-        let triangle = self.triangle.sample() as f32;
-        let noise = self.noise.sample() as f32;
-        let dmc = 0 as f32;
+        let triangle = self.channel_output(ApuChannel::Triangle, self.triangle.sample());
+        let noise = self.channel_output(ApuChannel::Noise, self.noise.sample());
+        let dmc = self.channel_output(ApuChannel::Dmc, self.dcpm.sample());
 
         let tnd = 1.0 / ((triangle / 8227.0) + (noise / 12241.0) + (dmc / 22638.0));
         let tnd_out = 159.79 / (tnd + 100.0);
@@ -62,13 +189,15 @@ impl Apu {
 
     pub fn clock(&mut self) {
         self.cycle += 1;
+        self.frame_interrupt_raised = false;
 
-        if self.cycle % 2 == 0 {
+        if self.cycle.is_multiple_of(2) {
             self.pulse1.clock();
             self.pulse2.clock();
         }
         self.triangle.clock();
         self.noise.clock();
+        self.dcpm.clock();
 
         if self.status_write_effect_timer > 0 {
             self.status_write_effect_timer -= 1;
@@ -81,33 +210,31 @@ impl Apu {
         }
 
         // See: https://www.nesdev.org/wiki/APU_Frame_Counter
+        let [step1, step2, step3, four_step_end, five_step_end] = frame_counter_steps(self.region);
         let (quarter, half) = match self.mode {
             SequenceMode::FourStep => match self.cycle {
-                7457 => (true, false),
-                14913 => (true, true),
-                22371 => (true, false),
-                29828 => {
-                    self.frame_interrupt = !self.irq_disable;
+                c if c == step1 || c == step3 => (true, false),
+                c if c == step2 => (true, true),
+                c if c == four_step_end - 1 => {
+                    self.raise_frame_interrupt();
 
                     (false, false)
                 }
-                29829 => {
-                    self.frame_interrupt = !self.irq_disable;
+                c if c == four_step_end => {
+                    self.raise_frame_interrupt();
                     (true, true)
                 }
-                29830 => {
-                    self.frame_interrupt = !self.irq_disable;
+                c if c == four_step_end + 1 => {
+                    self.raise_frame_interrupt();
                     self.cycle = 0;
                     (false, false)
                 }
                 _ => (false, false),
             },
             SequenceMode::FiveStep => match self.cycle {
-                7457 => (true, false),
-                14913 => (true, true),
-                22371 => (true, false),
-                37281 => (true, true),
-                37282 => {
+                c if c == step1 || c == step3 => (true, false),
+                c if c == step2 || c == five_step_end => (true, true),
+                c if c == five_step_end + 1 => {
                     self.cycle = 0;
                     (false, false)
                 }
@@ -124,6 +251,27 @@ impl Apu {
         }
     }
 
+    /// Whether the APU is asserting the CPU's IRQ line.
+    pub fn irq_line(&self) -> bool {
+        self.frame_interrupt || self.dcpm.interrupt()
+    }
+
+    fn raise_frame_interrupt(&mut self) {
+        if !self.irq_disable {
+            self.frame_interrupt = true;
+            self.frame_interrupt_raised = true;
+        }
+    }
+
+    /// The address the DMC needs its next sample byte from, which the CPU fetches with DMA.
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dcpm.dma_address()
+    }
+
+    pub fn dmc_dma_complete(&mut self, data: u8) {
+        self.dcpm.fill_sample_buffer(data);
+    }
+
     fn clock_quarter_frame(&mut self) {
         self.pulse1.envelope.clock();
         self.pulse2.envelope.clock();
@@ -145,6 +293,39 @@ impl Apu {
         }
     }
 
+    /// Only $4015 can be read, every other address is open bus.
+    /// See: https://www.nesdev.org/wiki/APU#Status_($4015)
+    pub fn read(&mut self, addr: u16, open_bus: u8) -> u8 {
+        if addr != 0x4015 {
+            return open_bus;
+        }
+
+        let mut status = open_bus & 0x20;
+        let channels = [
+            !self.pulse1.length_counter.silenced(),
+            !self.pulse2.length_counter.silenced(),
+            !self.triangle.length_counter.silenced(),
+            !self.noise.length_counter.silenced(),
+            self.dcpm.active(),
+        ];
+        for (i, active) in channels.into_iter().enumerate() {
+            status |= (active as u8) << i;
+        }
+        if self.frame_interrupt {
+            status |= 0x40;
+        }
+        if self.dcpm.interrupt() {
+            status |= 0x80;
+        }
+
+        // Reading clears the frame interrupt, unless it's being set at the same time
+        if !self.frame_interrupt_raised {
+            self.frame_interrupt = false;
+        }
+
+        status
+    }
+
     pub fn write(&mut self, addr: u16, data: u8) {
         match addr {
             0x4000 => self.pulse1.write_reg1(data),
@@ -167,10 +348,10 @@ impl Apu {
             0x400D => {}
             0x400E => self.noise.write_reg2(data),
             0x400F => self.noise.write_reg3(data),
-            0x4010 => {}
-            0x4011 => {}
-            0x4012 => {}
-            0x4013 => {}
+            0x4010 => self.dcpm.write_reg1(data),
+            0x4011 => self.dcpm.write_reg2(data),
+            0x4012 => self.dcpm.write_reg3(data),
+            0x4013 => self.dcpm.write_reg4(data),
             0x4015 => {
                 self.pulse1.set_enabled((data & 0x01) != 0);
                 self.pulse2.set_enabled((data & 0x02) != 0);
@@ -199,3 +380,206 @@ impl Apu {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Plays a constant volume square wave on pulse 1, with everything else muted.
+    fn playing_pulse() -> Apu {
+        let mut apu = Apu::new(Region::Ntsc);
+        // The triangle idles at a non-zero level
+        apu.set_channel_enabled(ApuChannel::Triangle, false);
+        apu.write(0x4015, 0x01);
+        apu.write(0x4000, 0b1011_1111);
+        apu.write(0x4002, 0xFF);
+        apu.write(0x4003, 0x00);
+        apu
+    }
+
+    #[test]
+    fn frame_irq() {
+        // Raised on the last 3 cycles of the four-step sequence
+        let mut apu = Apu::new(Region::Ntsc);
+        for _ in 0..29827 {
+            apu.clock();
+        }
+        assert!(!apu.irq_line());
+        apu.clock();
+        assert!(apu.irq_line());
+
+        // Held until acknowledged
+        for _ in 0..100 {
+            apu.clock();
+        }
+        assert!(apu.irq_line());
+        apu.read(0x4015, 0x00);
+        assert!(!apu.irq_line());
+
+        // Never raised with the IRQ inhibit flag set, or in five-step mode
+        for mode in [0x40, 0x80] {
+            let mut apu = Apu::new(Region::Ntsc);
+            apu.write(0x4017, mode);
+            for _ in 0..40000 {
+                apu.clock();
+            }
+            assert!(!apu.irq_line());
+        }
+    }
+
+    #[test]
+    fn status_read() {
+        let mut apu = playing_pulse();
+        assert_eq!(apu.read(0x4015, 0x00), 0x01);
+        // Only bit 5 is open bus
+        assert_eq!(apu.read(0x4015, 0xFF), 0x21);
+        assert_eq!(apu.read(0x4016, 0xAB), 0xAB);
+
+        while !apu.frame_interrupt {
+            apu.clock();
+        }
+        // Reading on the cycle the flag is set doesn't clear it
+        assert_eq!(apu.read(0x4015, 0x00) & 0x40, 0x40);
+        assert!(apu.frame_interrupt);
+
+        // The flag is set for 3 cycles at the end of the sequence
+        for _ in 0..3 {
+            apu.clock();
+        }
+        assert_eq!(apu.read(0x4015, 0x00) & 0x40, 0x40);
+        assert_eq!(apu.read(0x4015, 0x00) & 0x40, 0x00);
+    }
+
+    #[test]
+    fn dmc_fetches_sample_bytes() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.set_channel_enabled(ApuChannel::Triangle, false);
+        // Loop a 65 byte sample at $FFC0, at the fastest rate
+        apu.write(0x4010, 0x4F);
+        apu.write(0x4012, 0xFF);
+        apu.write(0x4013, 0x04);
+        apu.write(0x4015, 0x10);
+
+        let mut addresses = Vec::new();
+        for _ in 0..100_000 {
+            if let Some(addr) = apu.dmc_dma_request() {
+                addresses.push(addr);
+                apu.dmc_dma_complete(0xFF);
+            }
+            apu.clock();
+        }
+
+        // Wraps around to $8000, then loops back to the start
+        assert_eq!(addresses[0], 0xFFC0);
+        assert_eq!(addresses[63], 0xFFFF);
+        assert_eq!(addresses[64], 0x8000);
+        assert_eq!(addresses[65], 0xFFC0);
+        // Every bit is set, so the output climbs until it saturates
+        assert_eq!(apu.dcpm.sample(), 126);
+    }
+
+    #[test]
+    fn dmc_irq_and_status_write() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4017, 0x40);
+        // Play a 17 byte sample with IRQs on, at the fastest rate
+        apu.write(0x4010, 0x8F);
+        apu.write(0x4013, 0x01);
+        apu.write(0x4015, 0x10);
+
+        let mut fetches = 0;
+        while !apu.irq_line() {
+            if apu.dmc_dma_request().is_some() {
+                apu.dmc_dma_complete(0x00);
+                fetches += 1;
+            }
+            apu.clock();
+
+            // Enabling the channel again partway through doesn't restart the sample
+            if fetches == 8 {
+                let remaining = apu.dcpm.bytes_remaining();
+                apu.write(0x4015, 0x10);
+                assert_eq!(apu.dcpm.bytes_remaining(), remaining);
+            }
+        }
+        assert_eq!(fetches, 17);
+        assert_eq!(apu.read(0x4015, 0x00) & 0x90, 0x80);
+
+        // Reading doesn't acknowledge it, but any write to $4015 does
+        assert!(apu.irq_line());
+        apu.write(0x4015, 0x00);
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn reset_silences_channels() {
+        let mut apu = playing_pulse();
+        assert_eq!(apu.read(0x4015, 0x00), 0x01);
+        for _ in 0..29830 {
+            apu.clock();
+        }
+        assert!(apu.irq_line());
+
+        apu.write(0x4017, 0x00);
+        apu.reset();
+        assert!(!apu.irq_line());
+        assert_eq!(apu.read(0x4015, 0x00), 0x00);
+        assert_eq!(apu.status_write_effect_timer, 0);
+
+        // The frame counter starts over, so the frame interrupt is a full sequence away
+        for _ in 0..29000 {
+            apu.clock();
+        }
+        assert!(!apu.irq_line());
+    }
+
+    #[test]
+    fn triangle_holds_at_ultrasonic_periods() {
+        let mut apu = Apu::new(Region::Ntsc);
+        apu.write(0x4015, 0x04);
+        apu.write(0x4008, 0xFF);
+        apu.write(0x400A, 0x01);
+        apu.write(0x400B, 0x00);
+
+        // Held past the first quarter frame too, which loads the linear counter
+        let held = apu.triangle.sample();
+        for _ in 0..10_000 {
+            apu.clock();
+            assert_eq!(apu.triangle.sample(), held);
+        }
+
+        apu.write(0x400A, 0x02);
+        let mut changed = false;
+        for _ in 0..100 {
+            apu.clock();
+            changed |= apu.triangle.sample() != held;
+        }
+        assert!(changed);
+    }
+
+    #[test]
+    fn muted_channel_keeps_running() {
+        let mut apu = playing_pulse();
+        let mut reference = playing_pulse();
+
+        apu.set_channel_enabled(ApuChannel::Pulse1, false);
+        assert!(!apu.is_channel_enabled(ApuChannel::Pulse1));
+
+        let mut heard = false;
+        for _ in 0..10_000 {
+            apu.clock();
+            reference.clock();
+            assert_eq!(apu.sample(), 0.0);
+            heard |= reference.sample() > 0.0;
+        }
+        assert!(heard);
+
+        // Unmuting picks up exactly where the unmuted channel would be
+        apu.set_channel_enabled(ApuChannel::Pulse1, true);
+        for _ in 0..10_000 {
+            apu.clock();
+            reference.clock();
+            assert_eq!(apu.sample(), reference.sample());
+        }
+    }
+}
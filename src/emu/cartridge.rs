@@ -1,12 +1,19 @@
 use anyhow::{anyhow, Result};
 use bitflags::bitflags;
 use std::fmt::Display;
-use std::fs::File;
 use std::io::prelude::*;
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io::Cursor;
+#[cfg(feature = "io")]
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "io")]
+use zip::ZipArchive;
 
+use super::game_genie::GameGenieCode;
 use super::mappers::*;
+use super::region::Region;
 
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -34,43 +41,255 @@ bitflags! {
     }
 }
 
+const PRG_ROM_CHUNK_SIZE: usize = 16 * 1024;
+const CHR_ROM_CHUNK_SIZE: usize = 8 * 1024;
+// Code for copier hardware that some hacked ROMs have between the header and PRG ROM
+const TRAINER_SIZE: usize = 512;
+const INES_MAGIC: &[u8; 4] = b"NES\x1A";
+/// The CHR bank size used by `Cartridge::read_chr_bank`, the same 1 KB units as `chr_bank_map`.
+pub const CHR_BANK_SIZE: usize = 1024;
+
+/// The iNES mapper number set aside for the Famicom Disk System.
+const FDS_MAPPER_NUM: u16 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
 /// The iNES format file header
+/// See: https://www.nesdev.org/wiki/INES and https://www.nesdev.org/wiki/NES_2.0
 #[derive(Debug)]
-#[allow(dead_code)]
-struct Header {
-    name: [u8; 4],
-    prg_rom_chunks: u8,
-    chr_rom_chunks: u8,
+pub struct Header {
+    format: HeaderFormat,
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    /// Size of the CHR RAM to allocate when there is no CHR ROM
+    chr_ram_size: usize,
+    /// Volatile and battery-backed PRG RAM together
+    prg_ram_size: usize,
     flags6: Flags6,
-    flags7: Flags7,
-    mapper_num: u8,
-    prg_ram_size: u8,
-    tv_system1: u8,
-    tv_system2: u8,
+    mapper_num: u16,
+    submapper: u8,
+    /// 0: NTSC; 1: PAL; 2: Multi-region; 3: Dendy
+    timing: u8,
 }
 
 impl Header {
-    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+    /// Parses the header at the start of a ROM file, without loading the rest of it.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if is_fds(bytes) {
+            return Err(anyhow!("Disk System images don't have an iNES header"));
+        }
+        match bytes.first_chunk::<16>() {
+            Some(header) if header.starts_with(INES_MAGIC) => Header::from_bytes(*header),
+            _ => Err(anyhow!("Not an iNES ROM")),
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Result<Self> {
+        let flags7 = Flags7::from_bits_truncate(bytes[7]);
+        if flags7.contains(Flags7::FlagFormatBit1) && !flags7.contains(Flags7::FlagFormatBit0) {
+            Header::from_ines2(bytes)
+        } else {
+            Ok(Header::from_ines1(bytes))
+        }
+    }
+
+    fn from_ines1(bytes: [u8; 16]) -> Self {
         Header {
-            name: [bytes[0], bytes[1], bytes[2], bytes[3]],
-            prg_rom_chunks: bytes[4],
-            chr_rom_chunks: bytes[5],
+            format: HeaderFormat::INes,
+            prg_rom_size: bytes[4] as usize * PRG_ROM_CHUNK_SIZE,
+            chr_rom_size: bytes[5] as usize * CHR_ROM_CHUNK_SIZE,
+            chr_ram_size: 8 * 1024,
+            // A value of 0 infers 8 KB for compatibility
+            prg_ram_size: (bytes[8].max(1) as usize) * 8 * 1024,
             flags6: Flags6::from_bits_truncate(bytes[6]),
-            flags7: Flags7::from_bits_truncate(bytes[7]),
-            mapper_num: (bytes[7] & 0xF0) | (bytes[6] >> 4),
-            prg_ram_size: bytes[8],
-            tv_system1: bytes[9],
-            tv_system2: bytes[10],
+            mapper_num: ((bytes[7] & 0xF0) | (bytes[6] >> 4)) as u16,
+            submapper: 0,
+            timing: bytes[9] & 0x01,
+        }
+    }
+
+    fn from_ines2(bytes: [u8; 16]) -> Result<Self> {
+        let mapper_low = ((bytes[7] & 0xF0) | (bytes[6] >> 4)) as u16;
+        let mapper_high = (bytes[8] & 0x0F) as u16;
+
+        Ok(Header {
+            format: HeaderFormat::Nes2,
+            prg_rom_size: nes2_rom_size("PRG", bytes[4], bytes[9] & 0x0F, PRG_ROM_CHUNK_SIZE)?,
+            chr_rom_size: nes2_rom_size("CHR", bytes[5], bytes[9] >> 4, CHR_ROM_CHUNK_SIZE)?,
+            // The high nibbles are the battery-backed part of the RAM
+            chr_ram_size: nes2_ram_size(bytes[11] & 0x0F) + nes2_ram_size(bytes[11] >> 4),
+            prg_ram_size: nes2_ram_size(bytes[10] & 0x0F) + nes2_ram_size(bytes[10] >> 4),
+            flags6: Flags6::from_bits_truncate(bytes[6]),
+            mapper_num: (mapper_high << 8) | mapper_low,
+            submapper: bytes[8] >> 4,
+            timing: bytes[12] & 0x03,
+        })
+    }
+
+    pub fn is_nes2(&self) -> bool {
+        self.format == HeaderFormat::Nes2
+    }
+
+    pub fn mapper_num(&self) -> u16 {
+        self.mapper_num
+    }
+
+    /// Always 0 for iNES 1.0 headers, which don't have submappers.
+    pub fn submapper(&self) -> u8 {
+        self.submapper
+    }
+
+    pub fn prg_rom_size(&self) -> usize {
+        self.prg_rom_size
+    }
+
+    /// 0 if the cartridge uses CHR RAM.
+    pub fn chr_rom_size(&self) -> usize {
+        self.chr_rom_size
+    }
+
+    pub fn chr_ram_size(&self) -> usize {
+        self.chr_ram_size
+    }
+
+    pub fn prg_ram_size(&self) -> usize {
+        self.prg_ram_size
+    }
+
+    pub fn is_battery_backed(&self) -> bool {
+        self.flags6.contains(Flags6::BatteryBacked)
+    }
+
+    pub fn has_trainer(&self) -> bool {
+        self.flags6.contains(Flags6::HasTrainer)
+    }
+
+    /// The mirroring soldered on the board, which some mappers override.
+    pub fn mirroring(&self) -> Mirroring {
+        if self.flags6.contains(Flags6::IgnoreMirroring) {
+            Mirroring::FourScreen
+        } else if self.flags6.contains(Flags6::Mirroring) {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// The region the game was made for, from the TV system.
+    /// Multi-region games run as NTSC, and Dendy clones are closest to PAL.
+    pub fn region(&self) -> Region {
+        match self.timing {
+            1 | 3 => Region::Pal,
+            _ => Region::Ntsc,
         }
     }
+
+    /// Number of 16 KB PRG ROM banks, as used by the mappers.
+    /// The mappers index whole banks, so the ROM can't end partway through one.
+    fn prg_rom_chunks(&self) -> Result<u16> {
+        rom_chunks("PRG", self.prg_rom_size, PRG_ROM_CHUNK_SIZE).and_then(|chunks| match chunks {
+            0 => Err(anyhow!("No PRG ROM")),
+            _ => Ok(chunks),
+        })
+    }
+
+    /// Number of 8 KB CHR ROM banks, as used by the mappers
+    fn chr_rom_chunks(&self) -> Result<u16> {
+        rom_chunks("CHR", self.chr_rom_size, CHR_ROM_CHUNK_SIZE)
+    }
 }
 
-#[derive(Clone, Copy)]
+/// A summary to paste into bug reports, one field per line.
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        let format = if self.is_nes2() { "NES 2.0" } else { "iNES" };
+        writeln!(f, "Format: {}", format)?;
+        writeln!(f, "Mapper: {}", self.mapper_num)?;
+        if self.is_nes2() {
+            writeln!(f, "Submapper: {}", self.submapper)?;
+        }
+        writeln!(f, "PRG ROM: {} KB", self.prg_rom_size / 1024)?;
+        if self.chr_rom_size == 0 {
+            writeln!(f, "CHR RAM: {} KB", self.chr_ram_size / 1024)?;
+        } else {
+            writeln!(f, "CHR ROM: {} KB", self.chr_rom_size / 1024)?;
+        }
+        writeln!(f, "PRG RAM: {} KB", self.prg_ram_size / 1024)?;
+        writeln!(f, "Mirroring: {:?}", self.mirroring())?;
+        writeln!(f, "Battery: {}", yes_no(self.is_battery_backed()))?;
+        writeln!(f, "Trainer: {}", yes_no(self.has_trainer()))?;
+        write!(f, "Region: {}", self.region())
+    }
+}
+
+/// NES 2.0 ROM sizes are either a 12 bit chunk count, or when the upper nibble is 0xF,
+/// an exponent-multiplier pair of the form 2^E * (MM * 2 + 1).
+/// The exponent goes up to 63, so a broken header can ask for more than fits in a usize.
+fn nes2_rom_size(kind: &str, lsb: u8, msb: u8, chunk_size: usize) -> Result<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        return 2usize
+            .checked_pow(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} ROM size of 2^{} * {} is too large",
+                    kind,
+                    exponent,
+                    multiplier
+                )
+            });
+    }
+
+    Ok((((msb as usize) << 8) | lsb as usize) * chunk_size)
+}
+
+/// How many `chunk_size` banks a ROM of `size` bytes has, as the mappers count them.
+fn rom_chunks(kind: &str, size: usize, chunk_size: usize) -> Result<u16> {
+    if !size.is_multiple_of(chunk_size) {
+        return Err(anyhow!(
+            "{} ROM of {} bytes isn't a whole number of {} KB banks",
+            kind,
+            size,
+            chunk_size / 1024
+        ));
+    }
+    u16::try_from(size / chunk_size)
+        .map_err(|_| anyhow!("{} ROM of {} bytes is too large", kind, size))
+}
+
+/// NES 2.0 RAM sizes are encoded as a shift count, where the size is 64 << shift.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     SingleScreenLower,
     SingleScreenUpper,
+    /// The cartridge provides 2 KB of extra VRAM, so each nametable is separate
+    FourScreen,
+    /// The mapper picks which of the console's two nametables each of the four is, like MMC5
+    Mapped([usize; 4]),
+}
+
+/// Which banks are mapped where, see `Mapper::prg_bank_map` and `Mapper::chr_bank_map`.
+pub struct BankLayout {
+    pub prg: Vec<(u16, usize)>,
+    pub chr: Vec<(u16, usize)>,
 }
 
 pub struct Cartridge {
@@ -79,97 +298,429 @@ pub struct Cartridge {
 
     mapper: Box<dyn Mapper>,
     mirroring: Mirroring,
+
+    header: Header,
+    /// Hash of the ROM file, used to tell save files apart.
+    rom_hash: u64,
+    /// Hash of the save RAM as of the last save or load, see `save_ram_changed`.
+    saved_ram_hash: Option<u64>,
+    /// Copied into PRG RAM at every power on, see `power_on`.
+    trainer: Option<Vec<u8>>,
+
+    cheats: Vec<GameGenieCode>,
 }
 
 impl Cartridge {
+    /// Loads an iNES ROM file or a zip archive containing one.
+    /// Famicom Disk System images need the BIOS too, see `from_fds`.
+    #[cfg(feature = "io")]
     pub fn new<T: AsRef<Path> + Display>(rom_path: T) -> Result<Self> {
         log::info!("Loading ROM: {}", rom_path);
-        let mut f = File::open(rom_path)?;
+        Cartridge::from_bytes(&read_rom_file(rom_path)?)
+    }
 
-        let mut header_buf = [0; 16];
-        f.read_exact(&mut header_buf)?;
+    /// Disk System images can't be loaded from their bytes alone, see `from_fds`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if is_fds(bytes) {
+            return Err(anyhow!(
+                "Disk System games need the BIOS, load them with Cartridge::from_fds"
+            ));
+        }
 
-        let header = Header::from_bytes(header_buf);
+        let header = Header::parse(bytes)?;
+        let mut f = Cursor::new(&bytes[16..]);
         log::info!("Header: {:?}", header);
 
-        if header.flags6.contains(Flags6::HasTrainer) {
-            log::info!("Rom has trainer info, skipping 512 bytes");
-            f.seek(SeekFrom::Current(512))?;
-        }
-
-        let mirroring = if header.flags6.contains(Flags6::Mirroring) {
-            Mirroring::Vertical
+        let trainer = if header.has_trainer() {
+            log::info!("Rom has a {} byte trainer", TRAINER_SIZE);
+            let mut trainer = vec![0; TRAINER_SIZE];
+            f.read_exact(&mut trainer)?;
+            Some(trainer)
         } else {
-            Mirroring::Horizontal
+            None
         };
 
-        let file_type = 1;
+        let mirroring = header.mirroring();
 
-        let (prg_rom, chr_rom) = match file_type {
-            0 => todo!(),
-            1 => Cartridge::from_type1(f, &header)?,
-            2 => todo!(),
-            _ => panic!("Invalid file type"),
-        };
+        let prg_rom_chunks = header.prg_rom_chunks()?;
+        let chr_rom_chunks = header.chr_rom_chunks()?;
+
+        let (prg_rom, chr_rom) = Cartridge::read_rom(f, &header)?;
 
         let mapper: Box<dyn Mapper> = match header.mapper_num {
-            0 => Box::new(Mapper0::new(header.prg_rom_chunks)),
-            1 => Box::new(Mapper1::new(header.prg_rom_chunks, header.chr_rom_chunks)),
-            2 => Box::new(Mapper2::new(header.prg_rom_chunks, header.chr_rom_chunks)),
-            3 => Box::new(Mapper3::new(header.prg_rom_chunks, header.chr_rom_chunks)),
+            0 => Box::new(Mapper0::new(prg_rom_chunks)),
+            1 => Box::new(Mapper1::new(prg_rom_chunks, chr_rom_chunks)),
+            2 => Box::new(Mapper2::new(prg_rom_chunks, chr_rom_chunks)),
+            // Submapper 1 is for boards without bus conflicts and 2 for boards with them.
+            // Most CNROM boards have them, and games written for those avoid them anyway by
+            // writing where ROM already holds the value, so they're on unless ruled out
+            3 => Box::new(Mapper3::new(
+                prg_rom_chunks,
+                chr_rom_chunks,
+                header.submapper != 1,
+            )),
+            4 => Box::new(Mapper4::new(prg_rom_chunks, chr_rom_chunks)),
+            5 => Box::new(Mapper5::new(prg_rom_chunks, chr_rom_chunks)),
+            7 => Box::new(Mapper7::new(prg_rom_chunks, chr_rom_chunks)),
+            9 => Box::new(Mapper9::new(prg_rom_chunks, chr_rom_chunks)),
+            10 => Box::new(Mapper10::new(prg_rom_chunks, chr_rom_chunks)),
+            11 => Box::new(Mapper11::new(prg_rom_chunks, chr_rom_chunks)),
+            24 => Box::new(Mapper24::new(prg_rom_chunks, chr_rom_chunks, false)),
+            26 => Box::new(Mapper24::new(prg_rom_chunks, chr_rom_chunks, true)),
+            // NINA-001 shares the number, and is the only one of the two with CHR ROM
+            34 if header.submapper == 1 || chr_rom_chunks > 0 => Err(anyhow!(
+                "Unimplemented mapper 34 board NINA-001, only BNROM is supported"
+            ))?,
+            34 => Box::new(Mapper34::new(prg_rom_chunks)),
+            69 => Box::new(Mapper69::new(prg_rom_chunks, chr_rom_chunks)),
             _ => Err(anyhow!("Unimplemented mapper {}", header.mapper_num))?,
         };
 
-        Ok(Cartridge {
+        let mut cartridge = Cartridge {
             prg_memory: prg_rom,
             chr_memory: chr_rom,
             mapper,
             mirroring,
+            header,
+            rom_hash: fnv1a(bytes),
+            saved_ram_hash: None,
+            trainer,
+            cheats: Vec::new(),
+        };
+        // Blank RAM has nothing worth saving
+        cartridge.mark_ram_saved();
+        Ok(cartridge)
+    }
+
+    /// Loads a Famicom Disk System disk image, which runs on the RAM adapter with its BIOS.
+    pub fn from_fds(bytes: &[u8], bios: Vec<u8>) -> Result<Self> {
+        if bios.len() != BIOS_SIZE {
+            return Err(anyhow!(
+                "FDS BIOS is {} bytes, expected {}",
+                bios.len(),
+                BIOS_SIZE
+            ));
+        }
+
+        let disk = FdsDisk::parse(bytes)?;
+        log::info!("Loaded disk image with {} sides", disk.side_count());
+
+        // The RAM adapter isn't described by an iNES header, so fill in what it would say
+        let header = Header {
+            format: HeaderFormat::INes,
+            prg_rom_size: BIOS_SIZE,
+            chr_rom_size: 0,
+            chr_ram_size: 8 * 1024,
+            prg_ram_size: 32 * 1024,
+            flags6: Flags6::empty(),
+            mapper_num: FDS_MAPPER_NUM,
+            submapper: 0,
+            timing: 0,
+        };
+
+        Ok(Cartridge {
+            prg_memory: bios,
+            chr_memory: vec![0; header.chr_ram_size],
+            mapper: Box::new(Fds::new(disk)),
+            mirroring: Mirroring::Horizontal,
+            header,
+            rom_hash: fnv1a(bytes),
+            saved_ram_hash: None,
+            trainer: None,
+            cheats: Vec::new(),
         })
     }
 
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn mapper_num(&self) -> u16 {
+        self.header.mapper_num
+    }
+
+    /// Size of the PRG ROM in bytes.
+    pub fn prg_size(&self) -> usize {
+        self.header.prg_rom_size
+    }
+
+    /// Size of the CHR ROM in bytes, 0 if the cartridge uses CHR RAM.
+    pub fn chr_size(&self) -> usize {
+        self.header.chr_rom_size
+    }
+
+    /// The region the game was made for, see `Header::region`.
+    pub fn region(&self) -> Region {
+        self.header.region()
+    }
+
+    pub fn is_battery_backed(&self) -> bool {
+        self.header.is_battery_backed()
+    }
+
+    /// Identifies the ROM, for keying saves and checking movies were recorded with it.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// The PRG RAM to save, if the cartridge has a battery.
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        if !self.is_battery_backed() {
+            return None;
+        }
+        self.mapper.onboard_ram()
+    }
+
+    /// Restores PRG RAM saved with `save_ram`. Does nothing if the cartridge has no battery.
+    pub fn load_save_ram(&mut self, data: &[u8]) -> Result<()> {
+        if !self.is_battery_backed() {
+            return Ok(());
+        }
+        let Some(ram) = self.mapper.onboard_ram_mut() else {
+            return Ok(());
+        };
+
+        if data.len() != ram.len() {
+            return Err(anyhow!(
+                "Save is {} bytes, expected {}",
+                data.len(),
+                ram.len()
+            ));
+        }
+        ram.copy_from_slice(data);
+        self.mark_ram_saved();
+
+        Ok(())
+    }
+
+    /// Whether the save RAM has been written to since it was last saved or loaded,
+    /// for saving periodically without rewriting the same file every time.
+    pub fn save_ram_changed(&self) -> bool {
+        self.save_ram()
+            .is_some_and(|ram| Some(fnv1a(ram)) != self.saved_ram_hash)
+    }
+
+    fn mark_ram_saved(&mut self) {
+        self.saved_ram_hash = self.save_ram().map(fnv1a);
+    }
+
+    /// The file that battery-backed RAM is saved to, `<save_dir>/<rom hash>.sav`.
+    #[cfg(feature = "io")]
+    pub fn save_path(&self, save_dir: &Path) -> PathBuf {
+        save_dir.join(format!("{:016x}.sav", self.rom_hash))
+    }
+
+    /// Writes the cartridge's PRG RAM to its save file, if the cartridge has a battery.
+    #[cfg(feature = "io")]
+    pub fn write_save_file(&mut self, save_dir: &Path) -> Result<()> {
+        let Some(ram) = self.save_ram() else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(save_dir)?;
+        let path = self.save_path(save_dir);
+        // Written next to it and renamed over it, so a crash partway through can't leave a
+        // truncated save in place of the old one
+        let tmp_path = path.with_extension("sav.tmp");
+        fs::write(&tmp_path, ram)?;
+        fs::rename(&tmp_path, &path)?;
+        self.mark_ram_saved();
+        log::info!("Wrote save file {}", path.display());
+
+        Ok(())
+    }
+
+    /// Restores the cartridge's PRG RAM from its save file, if there is one.
+    #[cfg(feature = "io")]
+    pub fn load_save_file(&mut self, save_dir: &Path) -> Result<()> {
+        if self.save_ram().is_none() {
+            return Ok(());
+        }
+
+        let path = self.save_path(save_dir);
+        if !path.exists() {
+            return Ok(());
+        }
+        self.import_save_file(&path)
+    }
+
+    /// Restores the cartridge's PRG RAM from a file other than its own save file.
+    /// It has to be the size of the cartridge's RAM.
+    #[cfg(feature = "io")]
+    pub fn import_save_file(&mut self, path: &Path) -> Result<()> {
+        if self.save_ram().is_none() {
+            return Err(anyhow!(
+                "The game doesn't have battery-backed RAM to load a save into"
+            ));
+        }
+
+        let data = fs::read(path)?;
+        self.load_save_ram(&data)
+            .map_err(|e| anyhow!("Save file {}: {}", path.display(), e))?;
+        log::info!("Loaded save file {}", path.display());
+
+        Ok(())
+    }
+
+    pub fn bank_layout(&self) -> BankLayout {
+        BankLayout {
+            prg: self.mapper.prg_bank_map(),
+            chr: self.mapper.chr_bank_map(),
+        }
+    }
+
+    /// Length of PRG memory in bytes. For FDS images this is the BIOS.
+    pub fn prg_len(&self) -> usize {
+        self.prg_memory.len()
+    }
+
+    /// Length of CHR memory in bytes, including CHR RAM.
+    pub fn chr_len(&self) -> usize {
+        self.chr_memory.len()
+    }
+
+    /// Reads a byte straight out of PRG memory, bypassing the mapper, or None past the end.
+    pub fn prg_byte(&self, offset: usize) -> Option<u8> {
+        self.prg_memory.get(offset).copied()
+    }
+
+    /// Reads a byte straight out of CHR memory, bypassing the mapper, or None past the end.
+    pub fn chr_byte(&self, offset: usize) -> Option<u8> {
+        self.chr_memory.get(offset).copied()
+    }
+
+    /// How many 1 KB banks of CHR ROM or RAM the cartridge has.
+    pub fn chr_bank_count(&self) -> usize {
+        self.chr_memory.len().div_ceil(CHR_BANK_SIZE)
+    }
+
+    /// Reads a byte of CHR ROM or RAM from a 1 KB bank, whether or not the mapper has it mapped in.
+    /// Banks past the end of CHR memory wrap around, like most mappers' bank registers.
+    pub fn read_chr_bank(&self, bank: usize, offset: u16) -> u8 {
+        if self.chr_memory.is_empty() {
+            return 0;
+        }
+        let addr = bank * CHR_BANK_SIZE + offset as usize % CHR_BANK_SIZE;
+        self.chr_memory[addr % self.chr_memory.len()]
+    }
+
     pub fn mirroring(&self) -> Mirroring {
+        // Four-screen VRAM is wired up on the board, so the mapper can't change it
+        if let Mirroring::FourScreen = self.mirroring {
+            return Mirroring::FourScreen;
+        }
         self.mapper.mirroring().unwrap_or(self.mirroring)
     }
 
-    fn from_type1(mut f: File, header: &Header) -> Result<(Vec<u8>, Vec<u8>)> {
-        let prg_rom_size = (header.prg_rom_chunks as usize) * 16 * 1024;
+    fn read_rom<R: Read>(mut f: R, header: &Header) -> Result<(Vec<u8>, Vec<u8>)> {
+        let prg_rom_size = header.prg_rom_size;
         log::info!("Reading {} bytes of program ROM", prg_rom_size);
 
         let mut prg_mem = vec![0u8; prg_rom_size];
-        f.read_exact(prg_mem.as_mut_slice())?;
+        f.read_exact(prg_mem.as_mut_slice()).map_err(|_| {
+            anyhow!(
+                "The file is too short for the {} bytes of PRG ROM in its header",
+                prg_rom_size
+            )
+        })?;
 
         let mut chr_mem;
 
-        if header.chr_rom_chunks == 0 {
-            log::info!("No character ROM, allocating 8 KB of character RAM");
+        if header.chr_rom_size == 0 {
+            // The mappers always address a full 8 KB pattern table space
+            let chr_ram_size = header.chr_ram_size.max(8 * 1024);
+            log::info!(
+                "No character ROM, allocating {} KB of character RAM",
+                chr_ram_size / 1024
+            );
 
-            chr_mem = vec![0u8; 8 * 1024];
+            chr_mem = vec![0u8; chr_ram_size];
         } else {
-            let chr_rom_size = (header.chr_rom_chunks as usize) * 8 * 1024;
+            let chr_rom_size = header.chr_rom_size;
             log::info!("Reading {} bytes of character ROM", chr_rom_size);
 
             chr_mem = vec![0u8; chr_rom_size];
-            f.read_exact(chr_mem.as_mut_slice())?;
+            f.read_exact(chr_mem.as_mut_slice()).map_err(|_| {
+                anyhow!(
+                    "The file is too short for the {} bytes of CHR ROM in its header",
+                    chr_rom_size
+                )
+            })?;
         }
 
         Ok((prg_mem, chr_mem))
     }
 
+    /// Copies the trainer into PRG RAM, if the ROM has one. This happens at power on rather
+    /// than at load, so that a battery save loaded in between doesn't overwrite it.
+    pub fn power_on(&mut self) {
+        if let Some(trainer) = &self.trainer {
+            if let Err(err) = self.mapper.load_trainer(trainer) {
+                log::warn!("{}, ignoring it", err);
+            }
+        }
+    }
+
     pub fn cpu_write(&mut self, addr: u16, data: u8) -> Result<()> {
+        let mut data = data;
+        if addr >= 0x8000 && self.mapper.has_bus_conflicts() {
+            // ROM pulls down any bit that's 0 in the byte it outputs
+            if let MapRead::Address(rom_addr) = self.mapper.map_prg_read(addr)? {
+                data &= self.prg_memory[rom_addr];
+            }
+        }
+
         if let MapWrite::Address(addr) = self.mapper.map_prg_write(addr, data)? {
             self.prg_memory[addr] = data;
         }
         Ok(())
     }
 
-    pub fn cpu_read(&self, addr: u16) -> Result<u8> {
+    pub fn cpu_read(&mut self, addr: u16) -> Result<u8> {
+        let data = self.cpu_read_debug(addr)?;
+        self.mapper.on_prg_read(addr);
+        Ok(data)
+    }
+
+    /// Reads like the CPU would, but without acknowledging anything the read would for a game.
+    pub fn cpu_read_debug(&self, addr: u16) -> Result<u8> {
         match self.mapper.map_prg_read(addr)? {
-            MapRead::Address(addr) => Ok(self.prg_memory[addr]),
+            MapRead::Address(rom_addr) => {
+                let data = self.prg_memory[rom_addr];
+                Ok(self
+                    .cheats
+                    .iter()
+                    .fold(data, |data, cheat| cheat.apply(addr, data)))
+            }
             MapRead::RAMData(data) => Ok(data),
         }
     }
 
+    /// Patches PRG ROM reads with a Game Genie code, see `GameGenieCode`.
+    pub fn add_genie_code(&mut self, code: &str) -> Result<()> {
+        let cheat: GameGenieCode = code.parse()?;
+        log::info!("Game Genie code {}: {}", code.to_ascii_uppercase(), cheat);
+        self.cheats.push(cheat);
+        Ok(())
+    }
+
+    /// Returns whether the mapper is asserting the IRQ line.
+    pub fn on_cpu_cycle(&mut self) -> bool {
+        self.mapper.on_cpu_cycle()
+    }
+
+    pub fn on_ppu_address(&mut self, addr: u16) {
+        self.mapper.on_ppu_address(addr);
+    }
+
+    pub fn on_scanline_hblank(&mut self) {
+        self.mapper.on_scanline_hblank();
+    }
+
+    pub fn audio_sample(&self) -> f32 {
+        self.mapper.audio_sample()
+    }
+
     pub fn ppu_write(&mut self, addr: u16, data: u8) -> Result<()> {
         if let MapWrite::Address(addr) = self.mapper.map_chr_write(addr)? {
             self.chr_memory[addr] = data;
@@ -180,7 +731,396 @@ impl Cartridge {
     pub fn ppu_read(&self, addr: u16) -> Result<u8> {
         match self.mapper.map_chr_read(addr)? {
             MapRead::Address(addr) => Ok(self.chr_memory[addr]),
-            _ => todo!(),
+            MapRead::RAMData(data) => Ok(data),
         }
     }
 }
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` is stable between builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Whether `bytes` are a Famicom Disk System image, with or without the fwNES header.
+pub fn is_fds(bytes: &[u8]) -> bool {
+    bytes.starts_with(FDS_MAGIC) || bytes.starts_with(DISK_INFO_MAGIC)
+}
+
+/// Reads a ROM file, or the ROM inside it if it's a zip archive.
+#[cfg(feature = "io")]
+pub fn read_rom_file<T: AsRef<Path>>(rom_path: T) -> Result<Vec<u8>> {
+    let is_zip = rom_path
+        .as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+
+    if is_zip {
+        read_zipped_rom(File::open(&rom_path)?)
+    } else {
+        Ok(fs::read(&rom_path)?)
+    }
+}
+
+/// Reads the bytes of the only `.nes` file in a zip archive.
+#[cfg(feature = "io")]
+fn read_zipped_rom<R: Read + Seek>(reader: R) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(reader)?;
+
+    let mut roms: Vec<_> = archive
+        .file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".nes"))
+        .map(String::from)
+        .collect();
+    roms.sort();
+
+    match roms.as_slice() {
+        [] => Err(anyhow!("No .nes file found in archive")),
+        [name] => {
+            log::info!("Reading {} from archive", name);
+            let mut bytes = Vec::new();
+            archive.by_name(name)?.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        _ => Err(anyhow!(
+            "Archive contains multiple ROMs: {}",
+            roms.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "io")]
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    #[cfg(feature = "io")]
+    fn zip_of(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut zip = writer.finish().unwrap();
+        zip.set_position(0);
+        zip
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn rom_metadata() {
+        let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        assert_eq!(cartridge.mapper_num(), 0);
+        assert_eq!(cartridge.prg_size(), 16 * 1024);
+        assert_eq!(cartridge.chr_size(), 8 * 1024);
+        assert!(!cartridge.is_battery_backed());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn raw_rom_reads() {
+        let rom = fs::read("assets/test_roms/nestest.nes").unwrap();
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(cartridge.prg_len(), 16 * 1024);
+        assert_eq!(cartridge.chr_len(), 8 * 1024);
+        assert_eq!(cartridge.prg_byte(0), Some(rom[16]));
+        assert_eq!(cartridge.chr_byte(0x1FFF), rom.last().copied());
+        assert_eq!(cartridge.prg_byte(16 * 1024), None);
+
+        assert_eq!(
+            Cartridge::from_bytes(b"not a rom at all")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Not an iNES ROM"
+        );
+        assert!(Cartridge::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn genie_code_patches_prg_reads() {
+        let mut cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        // NROM-128 mirrors $8000 at $C000, so SXIOPO's $91D9 is also $D1D9
+        let original = cartridge.cpu_read(0x91D9).unwrap();
+        cartridge.add_genie_code("SXIOPO").unwrap();
+        assert_eq!(cartridge.cpu_read(0x91D9).unwrap(), 0xAD);
+        assert_eq!(cartridge.cpu_read(0xD1D9).unwrap(), original);
+        assert!(cartridge.add_genie_code("NOTACODE!").is_err());
+    }
+
+    #[test]
+    fn nina_001_unsupported() {
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 2, 1, 0x20, 0x20, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 2 * PRG_ROM_CHUNK_SIZE + CHR_ROM_CHUNK_SIZE, 0);
+        assert!(Cartridge::from_bytes(&rom).is_err());
+
+        // BNROM has CHR RAM instead
+        rom[5] = 0;
+        rom.truncate(16 + 2 * PRG_ROM_CHUNK_SIZE);
+        assert_eq!(Cartridge::from_bytes(&rom).unwrap().mapper_num(), 34);
+    }
+
+    #[test]
+    fn cnrom_bus_conflicts() {
+        // 4 CHR banks, each filled with its number
+        let cnrom = |submapper: u8| {
+            let mut rom = vec![
+                b'N', b'E', b'S', 0x1A, 1, 4, 0x30, 0x08, 0, 0, 0, 0, 0, 0, 0, 0,
+            ];
+            rom[8] = submapper << 4;
+            let mut prg = vec![0xFF; 16 * 1024];
+            prg[0] = 0x01;
+            rom.extend(prg);
+            for bank in 0..4 {
+                rom.extend([bank; 8 * 1024]);
+            }
+            Cartridge::from_bytes(&rom).unwrap()
+        };
+
+        let mut cartridge = cnrom(0);
+        cartridge.cpu_write(0x8000, 0x03).unwrap();
+        assert_eq!(cartridge.ppu_read(0x0000).unwrap(), 1);
+        cartridge.cpu_write(0x8001, 0x03).unwrap();
+        assert_eq!(cartridge.ppu_read(0x0000).unwrap(), 3);
+
+        let mut cartridge = cnrom(1);
+        cartridge.cpu_write(0x8000, 0x03).unwrap();
+        assert_eq!(cartridge.ppu_read(0x0000).unwrap(), 3);
+    }
+
+    #[test]
+    fn trainer_loaded_into_prg_ram() {
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 1, 1, 0x06, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.extend([0xAB; TRAINER_SIZE]);
+        rom.extend([0xEA; 16 * 1024]);
+        rom.extend([0; 8 * 1024]);
+
+        let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+        // Not until power on, so a save loaded before then doesn't overwrite it
+        assert_eq!(cartridge.cpu_read(0x7000).unwrap(), 0x00);
+        cartridge.load_save_ram(&[0x11; 8 * 1024]).unwrap();
+        cartridge.power_on();
+        assert_eq!(cartridge.cpu_read(0x6FFF).unwrap(), 0x11);
+        assert_eq!(cartridge.cpu_read(0x7000).unwrap(), 0xAB);
+        assert_eq!(cartridge.cpu_read(0x71FF).unwrap(), 0xAB);
+        assert_eq!(cartridge.cpu_read(0x7200).unwrap(), 0x11);
+        // PRG ROM starts after the trainer
+        assert_eq!(cartridge.cpu_read(0x8000).unwrap(), 0xEA);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn load_rom_from_zip() {
+        let rom = fs::read("assets/test_roms/nestest.nes").unwrap();
+        let zip = zip_of(&[("readme.txt", b"hello"), ("nestest.nes", &rom)]);
+
+        let bytes = read_zipped_rom(zip).unwrap();
+        assert_eq!(bytes, rom);
+        assert!(Cartridge::from_bytes(&bytes).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn zip_with_multiple_roms() {
+        let zip = zip_of(&[("b.nes", b"NES"), ("a.NES", b"NES")]);
+        let err = read_zipped_rom(zip).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Archive contains multiple ROMs: a.NES, b.nes"
+        );
+
+        let zip = zip_of(&[("readme.txt", b"hello")]);
+        assert!(read_zipped_rom(zip).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn battery_save_round_trip() {
+        // MMC1 with battery-backed PRG RAM
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 0x02, 0x01, 0x12, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 2 * PRG_ROM_CHUNK_SIZE + CHR_ROM_CHUNK_SIZE, 0);
+        let save_dir = std::env::temp_dir().join(format!("nesrs-saves-{}", std::process::id()));
+
+        let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert!(cartridge.is_battery_backed());
+        assert!(!cartridge.save_ram_changed());
+        cartridge.cpu_write(0x6123, 0xAB).unwrap();
+        assert!(cartridge.save_ram_changed());
+        assert_eq!(cartridge.save_ram().unwrap()[0x123], 0xAB);
+        assert!(cartridge.load_save_ram(&[0; 16]).is_err());
+        cartridge.write_save_file(&save_dir).unwrap();
+        assert!(!cartridge.save_ram_changed());
+        assert!(!cartridge
+            .save_path(&save_dir)
+            .with_extension("sav.tmp")
+            .exists());
+        assert!(cartridge
+            .save_path(&save_dir)
+            .ends_with(format!("{:016x}.sav", fnv1a(&rom))));
+
+        let mut reloaded = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(reloaded.cpu_read(0x6123).unwrap(), 0x00);
+        reloaded.load_save_file(&save_dir).unwrap();
+        assert_eq!(reloaded.cpu_read(0x6123).unwrap(), 0xAB);
+
+        // Another game's save is only loaded when asked for, and only if it fits
+        let mut other = rom.clone();
+        other[16] = 0xFF;
+        let mut other = Cartridge::from_bytes(&other).unwrap();
+        other.load_save_file(&save_dir).unwrap();
+        assert_eq!(other.cpu_read(0x6123).unwrap(), 0x00);
+        other
+            .import_save_file(&reloaded.save_path(&save_dir))
+            .unwrap();
+        assert_eq!(other.cpu_read(0x6123).unwrap(), 0xAB);
+        let small_save = save_dir.join("small.sav");
+        fs::write(&small_save, [0; 16]).unwrap();
+        assert!(other.import_save_file(&small_save).is_err());
+
+        fs::remove_dir_all(&save_dir).unwrap();
+    }
+
+    #[test]
+    fn four_screen_header() {
+        // MMC1, which would otherwise control mirroring itself
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 0x02, 0x01, 0x18, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 2 * PRG_ROM_CHUNK_SIZE + CHR_ROM_CHUNK_SIZE, 0);
+
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert!(matches!(cartridge.mirroring(), Mirroring::FourScreen));
+    }
+
+    #[test]
+    fn ines1_header() {
+        let header = Header::from_bytes([
+            b'N', b'E', b'S', 0x1A, 0x08, 0x10, 0x12, 0x40, 0x00, 0x01, 0, 0, 0, 0, 0, 0,
+        ])
+        .unwrap();
+
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.prg_rom_size, 128 * 1024);
+        assert_eq!(header.chr_rom_size, 128 * 1024);
+        assert_eq!(header.mapper_num, 0x41);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.timing, 1);
+        assert!(header.flags6.contains(Flags6::BatteryBacked));
+    }
+
+    #[test]
+    fn nes2_header() {
+        let header = Header::from_bytes([
+            b'N', b'E', b'S', 0x1A, 0x00, 0x00, 0x40, 0x18, 0x52, 0x01, 0x07, 0x07, 0x01, 0, 0, 0,
+        ])
+        .unwrap();
+
+        assert_eq!(header.format, HeaderFormat::Nes2);
+        // 0x100 chunks, which overflows the iNES byte
+        assert_eq!(header.prg_rom_size, 0x100 * 16 * 1024);
+        assert_eq!(header.chr_rom_size, 0);
+        assert_eq!(header.chr_ram_size, 8 * 1024);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.mapper_num, 0x214);
+        assert_eq!(header.submapper, 5);
+        assert_eq!(header.timing, 1);
+        assert_eq!(header.prg_rom_chunks().unwrap(), 0x100);
+    }
+
+    #[test]
+    fn nes2_battery_ram_size() {
+        let header = Header::from_bytes([
+            b'N', b'E', b'S', 0x1A, 0x02, 0x01, 0x12, 0x08, 0x00, 0x00, 0x70, 0x00, 0, 0, 0, 0,
+        ])
+        .unwrap();
+
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert!(header.to_string().contains("PRG RAM: 8 KB"));
+    }
+
+    #[test]
+    fn partial_rom_chunks() {
+        // NES 2.0 exponent sizes, 2^13 * 1 = 8 KB of PRG ROM is half a bank
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 0x34, 0x01, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 8 * 1024 + 8 * 1024, 0);
+        let err = Cartridge::from_bytes(&rom).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "PRG ROM of 8192 bytes isn't a whole number of 16 KB banks"
+        );
+
+        // One bank of each, but the CHR ROM is cut off
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 16 * 1024 + 100, 0);
+        let err = Cartridge::from_bytes(&rom).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "The file is too short for the 8192 bytes of CHR ROM in its header"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn parse_header_summary() {
+        let rom = fs::read("assets/test_roms/nestest.nes").unwrap();
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "Format: iNES\nMapper: 0\nPRG ROM: 16 KB\nCHR ROM: 8 KB\nPRG RAM: 8 KB\n\
+             Mirroring: Horizontal\nBattery: no\nTrainer: no\nRegion: NTSC"
+        );
+
+        assert!(Header::parse(&rom[..15]).is_err());
+        assert!(Header::parse(b"FDS\x1A and the rest of the disk").is_err());
+    }
+
+    #[test]
+    fn region_from_timing() {
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.extend([0; 24 * 1024]);
+        assert_eq!(Cartridge::from_bytes(&rom).unwrap().region(), Region::Pal);
+
+        rom[9] = 0x00;
+        assert_eq!(Cartridge::from_bytes(&rom).unwrap().region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn nes2_exponent_multiplier_size() {
+        // 2^10 * (1 * 2 + 1) = 3 KB
+        assert_eq!(
+            nes2_rom_size("PRG", 0b0010_1001, 0x0F, PRG_ROM_CHUNK_SIZE).unwrap(),
+            3 * 1024
+        );
+        assert_eq!(
+            nes2_rom_size("CHR", 0x02, 0x00, CHR_ROM_CHUNK_SIZE).unwrap(),
+            16 * 1024
+        );
+
+        // 2^63 * 7 overflows, and so does 2^63 * 3
+        let err = nes2_rom_size("PRG", 0xFF, 0x0F, PRG_ROM_CHUNK_SIZE).unwrap_err();
+        assert_eq!(err.to_string(), "PRG ROM size of 2^63 * 7 is too large");
+        assert!(nes2_rom_size("PRG", 0xFD, 0x0F, PRG_ROM_CHUNK_SIZE).is_err());
+        // A whole header with it is rejected, not just the size
+        let header = [
+            b'N', b'E', b'S', 0x1A, 0xFF, 0x00, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0,
+        ];
+        assert!(Header::parse(&header).is_err());
+    }
+}
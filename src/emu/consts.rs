@@ -1,5 +0,0 @@
-// https://www.nesdev.org/wiki/Cycle_reference_chart
-pub const CLOCK_SPEED: usize = 5369318;
-pub const FPS: f64 = 60.0988;
-pub const FRAME_CLOCKS: u32 = 89342;
-pub const FRAME_TIME: f64 = 1.0 / FPS;
@@ -0,0 +1,92 @@
+use super::instructions::{AddressMode, Instruction};
+
+/// Disassembles the instruction at `addr`, reading it and its arguments with `read`.
+/// Relative branches show the address they jump to.
+pub fn format_instruction<F: Fn(u16) -> u8>(addr: u16, read: F) -> String {
+    let instruction = Instruction::lookup(read(addr));
+    let name = instruction.instruction_type.as_ref().to_uppercase();
+
+    let arg_addr = addr.wrapping_add(1);
+    let byte = || read(arg_addr);
+    let word = || u16::from_le_bytes([read(arg_addr), read(arg_addr.wrapping_add(1))]);
+
+    match instruction.address_mode {
+        AddressMode::Imp => name,
+        AddressMode::Acc => format!("{} A", name),
+        AddressMode::Imm => format!("{} #${:02X}", name, byte()),
+        AddressMode::Zp0 => format!("{} ${:02X}", name, byte()),
+        AddressMode::Zpx => format!("{} ${:02X},X", name, byte()),
+        AddressMode::Zpy => format!("{} ${:02X},Y", name, byte()),
+        AddressMode::Rel => {
+            let target = addr
+                .wrapping_add(2)
+                .wrapping_add_signed(byte() as i8 as i16);
+            format!("{} ${:04X}", name, target)
+        }
+        AddressMode::Abs => format!("{} ${:04X}", name, word()),
+        AddressMode::Abx => format!("{} ${:04X},X", name, word()),
+        AddressMode::Aby => format!("{} ${:04X},Y", name, word()),
+        AddressMode::Ind => format!("{} (${:04X})", name, word()),
+        AddressMode::Izx => format!("{} (${:02X},X)", name, byte()),
+        AddressMode::Izy => format!("{} (${:02X}),Y", name, byte()),
+    }
+}
+
+/// Disassembles `bytes` as if they were mapped at `base`, without needing a CPU.
+/// An instruction cut off by the end of the slice is listed as a raw byte.
+pub fn disasm(bytes: &[u8], base: u16) -> Vec<(u16, String)> {
+    let mut listing = Vec::new();
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = base.wrapping_add(offset as u16);
+        let size = Instruction::lookup(bytes[offset]).address_mode.arg_size() as usize + 1;
+
+        if offset + size > bytes.len() {
+            listing.push((addr, format!(".DB ${:02X}", bytes[offset])));
+            offset += 1;
+            continue;
+        }
+
+        let read = |a: u16| bytes[offset + a.wrapping_sub(addr) as usize];
+        listing.push((addr, format_instruction(addr, read)));
+        offset += size;
+    }
+
+    listing
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listing() {
+        let code = [
+            0xA9, 0x10, // LDA #$10
+            0x8D, 0x00, 0x20, // STA $2000
+            0xD0, 0xF9, // BNE $C000
+            0x0A, // ASL A
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0xB1, 0x02, // LDA ($02),Y
+            0x4C, // Cut off
+            0xEA, // NOP
+        ];
+
+        let listing = disasm(&code, 0xC000);
+        let expected = [
+            (0xC000, "LDA #$10"),
+            (0xC002, "STA $2000"),
+            (0xC005, "BNE $C000"),
+            (0xC007, "ASL A"),
+            (0xC008, "JMP ($FFFC)"),
+            (0xC00B, "LDA ($02),Y"),
+            (0xC00D, ".DB $4C"),
+            (0xC00E, "NOP"),
+        ];
+        assert_eq!(listing.len(), expected.len());
+        for ((addr, text), (expected_addr, expected_text)) in listing.iter().zip(expected) {
+            assert_eq!((*addr, text.as_str()), (expected_addr, expected_text));
+        }
+    }
+}
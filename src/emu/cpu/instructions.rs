@@ -401,3 +401,15 @@ pub enum InstructionType {
     Las,
     Stp,
 }
+
+impl InstructionType {
+    /// Instructions that only read their operand, which take an extra cycle when an indexed
+    /// address crosses a page. See `Cpu::clock` for the dummy read done in that cycle.
+    pub fn is_read(&self) -> bool {
+        use InstructionType::*;
+        matches!(
+            self,
+            Adc | And | Cmp | Eor | Lda | Ldx | Ldy | Ora | Sbc | Lax | Las | Nop
+        )
+    }
+}
@@ -2,12 +2,14 @@ use self::instructions::{AddressMode, Instruction, InstructionType};
 use super::apu::Apu;
 use super::bits::IntoBit;
 use super::cartridge::Cartridge;
-use super::input::{ControllerInput, StandardController};
+use super::input::{ControllerInput, StandardController, Zapper};
 use super::ppu::Ppu;
+use super::ram_init::RamInit;
 use bitflags::bitflags;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+pub mod disasm;
 pub mod instructions;
 
 bitflags! {
@@ -61,23 +63,48 @@ pub struct Cpu {
     dma_page: u8,
     dma_index: u8,
     dma_data: u8,
+    // Cycles left in the DMA fetching a DMC sample byte, which stalls the CPU
+    // See: https://www.nesdev.org/wiki/DMA#DMC_DMA
+    dmc_dma_cycles: u8,
+    dmc_dma_addr: u16,
+    // The controller port read by the current instruction, which DMC DMA can read again
+    controller_read: Option<u16>,
+    // The IRQ line is level triggered, held low by the cartridge or APU until acknowledged
+    irq_line: bool,
+    cartridge_irq: bool,
+    // NMI is edge triggered, so one is remembered until the current instruction finishes
+    nmi_pending: bool,
+    // Set when the NMI comes from a write on an instruction's last cycle, after interrupts were
+    // polled, so it waits for one more instruction
+    nmi_delayed: bool,
 
     // Memory
     ram: [u8; CPU_RAM_SIZE],
+    ram_init: RamInit,
 
     // Other components
     apu: Option<Rc<RefCell<Apu>>>,
     ppu: Option<Rc<RefCell<Ppu>>>,
     cartridge: Option<Rc<RefCell<Cartridge>>>,
 
+    // Last value read or written on the data bus, returned by reads from unmapped addresses
+    // See: https://www.nesdev.org/wiki/Open_bus_behavior
+    bus_latch: u8,
+
     // Input
     controller_strobe: bool,
     controllers: [StandardController; 2],
+    // Replaces the controller in port 2 once plugged in
+    zapper: Option<Zapper>,
+
+    // Debugging
+    write_watches: Vec<u16>,
+    // The watched address that was last written to, until the debugger takes it
+    watch_hit: Option<u16>,
 }
 
 struct AddressModeResult {
     /// The pointer that was used to get the computed address, used for debugging
-    #[allow(dead_code)]
     ptr: Option<u16>,
     /// The computed address to read from
     addr: u16,
@@ -85,6 +112,12 @@ struct AddressModeResult {
     additional_cycles: bool,
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Cpu {
@@ -103,15 +136,29 @@ impl Cpu {
             dma_page: 0x00,
             dma_index: 0x00,
             dma_data: 0x00,
+            dmc_dma_cycles: 0,
+            dmc_dma_addr: 0x0000,
+            controller_read: None,
+            irq_line: false,
+            cartridge_irq: false,
+            nmi_pending: false,
+            nmi_delayed: false,
 
             ram: [0; CPU_RAM_SIZE],
+            ram_init: RamInit::default(),
 
             cartridge: None,
             ppu: None,
             apu: None,
 
+            bus_latch: 0x00,
+
             controller_strobe: false,
             controllers: [StandardController::default(); 2],
+            zapper: None,
+
+            write_watches: Vec::new(),
+            watch_hit: None,
         }
     }
 
@@ -127,6 +174,10 @@ impl Cpu {
         self.cartridge = Some(cartridge);
     }
 
+    pub fn eject_cartridge(&mut self) {
+        self.cartridge = None;
+    }
+
     #[cfg(test)]
     fn next_instruction(&mut self) {
         self.total_cycles += self.cycles as u64;
@@ -158,7 +209,26 @@ impl Cpu {
         self.status
     }
 
-    #[allow(dead_code)]
+    /// Whether an NMI has been requested and is waiting for the current instruction to finish.
+    pub fn pending_nmi(&self) -> bool {
+        self.nmi_pending
+    }
+
+    /// Whether the cartridge or APU is holding the IRQ line, as of the last clock.
+    /// The IRQ is only taken if interrupts aren't disabled.
+    pub fn pending_irq(&self) -> bool {
+        self.irq_line
+    }
+
+    /// Whether the cartridge's mapper is holding the IRQ line, as of the last clock.
+    pub fn cartridge_irq(&self) -> bool {
+        self.cartridge_irq
+    }
+
+    pub fn interrupts_disabled(&self) -> bool {
+        self.get_flag(StatusFlags::I)
+    }
+
     pub fn opcode(&self) -> u8 {
         self.opcode
     }
@@ -171,14 +241,33 @@ impl Cpu {
         self.total_cycles
     }
 
+    /// Whether the next clock will fetch a new instruction.
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.cycles == 0 && !self.dma_transfer && self.dmc_dma_cycles == 0
+    }
+
     pub fn trigger_inputs(&mut self, input: ControllerInput) {
         match input {
             ControllerInput::One(buttons) => self.controllers[0].notify_input(buttons),
             ControllerInput::Two(buttons) => self.controllers[1].notify_input(buttons),
+            ControllerInput::Zapper { x, y, trigger } => self
+                .zapper
+                .get_or_insert_with(Zapper::default)
+                .notify_input(x, y, trigger),
         }
     }
 
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        self.zapper.as_mut()
+    }
+
     pub fn read(&mut self, addr: u16) -> u8 {
+        let data = self.read_bus(addr);
+        self.bus_latch = data;
+        data
+    }
+
+    fn read_bus(&mut self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => {
                 // Actual RAM is from 0x0000 to 0x07FF, but it is mirrored
@@ -190,21 +279,34 @@ impl Cpu {
                 Some(ppu) => ppu.borrow_mut().cpu_read(addr),
                 None => panic!("PPU not attached"),
             },
+            0x4015 => match &self.apu {
+                Some(apu) => apu.borrow_mut().read(addr, self.bus_latch),
+                None => panic!("APU not attached"),
+            },
             0x4016..=0x4017 => {
+                self.controller_read = Some(addr);
                 let i = (addr % 2) as usize;
-                // While the controller strobe is high,
-                // the button data should be continuously reloaded, so we use the latest value from the controller
+                // The upper bits aren't driven by the controller ports
+                let open_bus = self.bus_latch & 0xE0;
+                if let (1, Some(zapper)) = (i, &self.zapper) {
+                    return open_bus | zapper.read();
+                }
+                // While the controller strobe is high, the button data is continuously reloaded,
+                // so reads keep returning the current state of A
                 if self.controller_strobe {
-                    return self.controllers[i].peek_button();
+                    self.controllers[i].reload();
+                    return open_bus | self.controllers[i].peek_button();
                 }
-                self.controllers[i].read_button()
+                open_bus | self.controllers[i].read_button()
             }
             0x4020..=0xFFFF => match &self.cartridge {
-                Some(cartridge) => cartridge.borrow_mut().cpu_read(addr).unwrap_or(0),
+                Some(cartridge) => cartridge
+                    .borrow_mut()
+                    .cpu_read(addr)
+                    .unwrap_or(self.bus_latch),
                 None => panic!("Cartridge not attached"),
             },
-            _ => 0,
-            // _ => todo!("Reading from CPU address {:04X} not implemented yet", addr),
+            _ => self.bus_latch,
         }
     }
 
@@ -222,14 +324,20 @@ impl Cpu {
             },
             0x4016..=0x4017 => {
                 let i = (addr % 2) as usize;
-                self.controllers[i].peek_button()
+                let open_bus = self.bus_latch & 0xE0;
+                if let (1, Some(zapper)) = (i, &self.zapper) {
+                    return open_bus | zapper.read();
+                }
+                open_bus | self.controllers[i].peek_button()
             }
             0x4020..=0xFFFF => match &self.cartridge {
-                Some(cartridge) => cartridge.borrow_mut().cpu_read(addr).unwrap_or(0),
+                Some(cartridge) => cartridge
+                    .borrow()
+                    .cpu_read_debug(addr)
+                    .unwrap_or(self.bus_latch),
                 None => panic!("Cartridge not attached"),
             },
-            _ => 0,
-            // _ => todo!("Reading from CPU address {:04X} not implemented yet", addr),
+            _ => self.bus_latch,
         }
     }
 
@@ -248,6 +356,12 @@ impl Cpu {
     }
 
     fn write(&mut self, addr: u16, data: u8) {
+        self.bus_latch = data;
+
+        if self.write_watches.contains(&watch_addr(addr)) {
+            self.watch_hit = Some(addr);
+        }
+
         match addr {
             0x0000..=0x1FFF => {
                 let mapped_addr = addr as usize % CPU_RAM_SIZE;
@@ -268,15 +382,15 @@ impl Cpu {
                 self.dma_index = 0x00;
             }
             0x4016 => {
-                let data = data & 0x01;
+                let strobe = data & 0x01 != 0;
 
-                if data == 1 {
-                    self.controller_strobe = true;
-                } else {
-                    self.controller_strobe = false;
+                // The shift registers are reloaded while the strobe is high,
+                // and keep the last buttons once it goes low
+                if strobe || self.controller_strobe {
                     self.controllers[0].reload();
                     self.controllers[1].reload();
                 }
+                self.controller_strobe = strobe;
             }
             0x4020..=0xFFFF => match &self.cartridge {
                 Some(cartridge) => cartridge.borrow_mut().cpu_write(addr, data).unwrap_or(()),
@@ -357,12 +471,48 @@ impl Cpu {
         self.cycles = cycles;
     }
 
-    pub fn reset(&mut self) {
+    /// The state when the console is switched on: registers are cleared and RAM is filled
+    /// as set by `set_ram_init`, which zeroes it by default to keep runs deterministic.
+    pub fn power_on(&mut self) {
+        self.ram_init.fill(&mut self.ram);
+        self.bus_latch = 0x00;
+        self.total_cycles = 0;
+        self.cancel_dma();
+        self.nmi_pending = false;
+        self.nmi_delayed = false;
+
         let reset_addr = self.read_u16(0xFFFC);
-        log::info!("CPU Reset, read reset vector {:#06X}", reset_addr);
+        log::info!("CPU power on, read reset vector {:#06X}", reset_addr);
         self.reset_to(reset_addr);
     }
 
+    /// Sets what RAM holds after the next power on. Resets keep RAM as it is.
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+    }
+
+    /// Pressing the reset button keeps RAM and the A, X and Y registers,
+    /// only moving the stack pointer down 3 and disabling interrupts.
+    /// See: https://www.nesdev.org/wiki/CPU_power_up_state
+    pub fn reset(&mut self) {
+        self.cancel_dma();
+        self.nmi_pending = false;
+        self.nmi_delayed = false;
+
+        let reset_addr = self.read_u16(0xFFFC);
+        log::info!("CPU reset, read reset vector {:#06X}", reset_addr);
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_flag(StatusFlags::I, true);
+        self.pc = reset_addr;
+        self.cycles = 7;
+    }
+
+    fn cancel_dma(&mut self) {
+        self.dma_transfer = false;
+        self.dma_halting = false;
+        self.dmc_dma_cycles = 0;
+    }
+
     pub fn reset_to(&mut self, pc: u16) {
         self.a = 0;
         self.x = 0;
@@ -377,13 +527,42 @@ impl Cpu {
 
     /// Run one clock cycle.
     pub fn clock(&mut self) {
+        let cartridge_irq = self
+            .cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.borrow_mut().on_cpu_cycle());
+        let apu_irq = self.apu.as_ref().is_some_and(|apu| apu.borrow().irq_line());
+        self.irq_line = cartridge_irq || apu_irq;
+        self.cartridge_irq = cartridge_irq;
+
+        if self.dmc_dma_cycles == 0 {
+            self.check_dmc_dma();
+        }
+        if self.dmc_dma_cycles > 0 {
+            self.dmc_dma_clock();
+            self.total_cycles += 1;
+            return;
+        }
+
         if self.dma_transfer {
             self.dma_clock();
             self.total_cycles += 1;
             return;
         }
 
+        if self.cycles == 0 && self.nmi_pending && !self.nmi_delayed {
+            self.nmi_pending = false;
+            self.nmi();
+        } else if self.cycles == 0 && self.irq_line {
+            // Does nothing if interrupts are disabled
+            self.irq();
+        }
+        if self.cycles == 0 {
+            self.nmi_delayed = false;
+        }
+
         if self.cycles == 0 {
+            self.controller_read = None;
             self.opcode = self.read(self.pc);
 
             let instruction = Instruction::lookup(self.opcode);
@@ -412,6 +591,19 @@ impl Cpu {
                 A::Izy => self.izy(arg_addr),
             };
 
+            // Indexed reads add the index to the low byte first, and read from there while the
+            // high byte is being fixed up. When that crosses a page it's the wrong address,
+            // which can still have side effects, like acknowledging an interrupt at $4015.
+            // This affects the abs,X, abs,Y and (ind),Y forms of ADC, AND, CMP, EOR, LDA, LDX,
+            // LDY, ORA, SBC, LAX and LAS, and the unofficial NOP abs,X.
+            // See: https://www.nesdev.org/wiki/CPU_addressing_modes
+            if additional_cycles
+                && matches!(instruction.address_mode, A::Abx | A::Aby | A::Izy)
+                && instruction.instruction_type.is_read()
+            {
+                self.read(addr.wrapping_sub(0x100));
+            }
+
             // Add the size in bytes of the instruction (1, 2, or 3) to the program counter
             self.pc += 1 + instruction.address_mode.arg_size();
 
@@ -487,11 +679,11 @@ impl Cpu {
                 Arr => self.arr(addr),
                 Xaa => self.xaa(addr),
                 Axs => self.axs(addr),
-                Ahx => self.ahx(),
-                Shy => self.shy(addr, arg_addr, additional_cycles),
-                Shx => self.shx(addr, arg_addr, additional_cycles),
-                Tas => self.tas(addr, arg_addr),
-                Las => self.las(),
+                Ahx => self.ahx(addr),
+                Shy => self.shy(addr),
+                Shx => self.shx(addr),
+                Tas => self.tas(addr),
+                Las => self.las(addr),
                 Stp => self.stp(),
             };
 
@@ -508,13 +700,13 @@ impl Cpu {
         if self.dma_halting {
             // If we're on an even cycle, wait again the next cycle so that
             // we start the DMA transfer on an even cycle
-            self.dma_halting = self.total_cycles % 2 == 0;
+            self.dma_halting = self.total_cycles.is_multiple_of(2);
             return;
         }
 
         // Even cycles: Get from CPU Page (don't have to do anything in code
         // Odd cycles: Put (write) to PPU OAM
-        if self.total_cycles % 2 == 0 {
+        if self.total_cycles.is_multiple_of(2) {
             let page_base_addr = (self.dma_page as u16) << 8;
             let addr = page_base_addr + (self.dma_index as u16);
             self.dma_data = self.read(addr);
@@ -534,6 +726,52 @@ impl Cpu {
         }
     }
 
+    fn check_dmc_dma(&mut self) {
+        let Some(addr) = self
+            .apu
+            .as_ref()
+            .and_then(|apu| apu.borrow().dmc_dma_request())
+        else {
+            return;
+        };
+
+        self.dmc_dma_addr = addr;
+        self.dmc_dma_cycles = if self.dma_transfer && !self.dma_halting {
+            // Takes over one of OAM DMA's get cycles, then realigns
+            2
+        } else if self.total_cycles.is_multiple_of(2) {
+            // Halt, dummy, then read on the next get (even) cycle
+            3
+        } else {
+            // Halt, dummy, alignment, read
+            4
+        };
+
+        // The DMA halts the CPU on a read cycle, and the halt repeats the read. When that's an
+        // instruction reading a controller on its last cycle, the controller shifts an extra time
+        // and a button is lost, so games that play DMC samples have to read until 2 reads match.
+        // The PAL CPU fixed this, but it isn't told apart here.
+        // See: https://www.nesdev.org/wiki/DMA#Register_conflicts
+        if self.cycles == 1 && !self.dma_transfer {
+            if let Some(addr) = self.controller_read {
+                self.read(addr);
+            }
+        }
+    }
+
+    fn dmc_dma_clock(&mut self) {
+        self.dmc_dma_cycles -= 1;
+        if self.dmc_dma_cycles > 0 {
+            return;
+        }
+
+        let data = self.read(self.dmc_dma_addr);
+        match &self.apu {
+            Some(apu) => apu.borrow_mut().dmc_dma_complete(data),
+            None => panic!("Attempted to perform DMC DMA without APU attached to CPU"),
+        }
+    }
+
     // Addressing modes
     // See https://www.nesdev.org/obelisk-6502-guide/addressing.html for details
 
@@ -746,6 +984,9 @@ impl Cpu {
     /// Sets the zero bit if the result is 0.
     /// Sets the overflow bit if there is an overflow, i.e the accumulator and the argument are
     /// both negative or both positive, but the result is the opposite.
+    ///
+    /// The 2A03 has the 6502's decimal flag, but its BCD circuitry is disconnected, so this
+    /// intentionally ignores D. Don't add decimal mode here, test ROMs check for its absence.
     fn adc(&mut self, addr: u16) -> u8 {
         let a = self.a as u16;
         let arg = self.read(addr) as u16;
@@ -776,11 +1017,19 @@ impl Cpu {
         1
     }
 
+    /// Reads the argument of a read-modify-write instruction. The CPU writes the unmodified
+    /// value back while it computes the result, so I/O registers and mappers see two writes.
+    fn read_for_modify(&mut self, addr: u16) -> u8 {
+        let arg = self.read(addr);
+        self.write(addr, arg);
+        arg
+    }
+
     /// Arithmetic shift left.
     fn asl(&mut self, addr: u16, addr_mode: AddressMode) -> u8 {
         let arg = match addr_mode {
             AddressMode::Acc => self.a,
-            _ => self.read(addr),
+            _ => self.read_for_modify(addr),
         };
         let val = (arg as u16) << 1;
         let res = (val & 0x00FF) as u8;
@@ -928,7 +1177,7 @@ impl Cpu {
 
     /// Decrement argument.
     fn dec(&mut self, addr: u16) -> u8 {
-        let arg = self.read(addr);
+        let arg = self.read_for_modify(addr);
 
         let res = arg.wrapping_sub(1);
         self.write(addr, res);
@@ -971,7 +1220,7 @@ impl Cpu {
 
     /// Increment the argument by 1.
     fn inc(&mut self, addr: u16) -> u8 {
-        let arg = self.read(addr);
+        let arg = self.read_for_modify(addr);
 
         let res = arg.wrapping_add(1);
         self.write(addr, res);
@@ -1053,7 +1302,7 @@ impl Cpu {
     fn lsr(&mut self, addr: u16, addr_mode: AddressMode) -> u8 {
         let arg = match addr_mode {
             AddressMode::Acc => self.a,
-            _ => self.read(addr),
+            _ => self.read_for_modify(addr),
         };
 
         self.set_flag(StatusFlags::C, (arg & 0x01) != 0);
@@ -1125,7 +1374,7 @@ impl Cpu {
     fn rol(&mut self, addr: u16, addr_mode: AddressMode) -> u8 {
         let arg = match addr_mode {
             AddressMode::Acc => self.a,
-            _ => self.read(addr),
+            _ => self.read_for_modify(addr),
         };
 
         let old_bit7 = (arg & (1 << 7)) != 0;
@@ -1147,7 +1396,7 @@ impl Cpu {
     fn ror(&mut self, addr: u16, addr_mode: AddressMode) -> u8 {
         let arg = match addr_mode {
             AddressMode::Acc => self.a,
-            _ => self.read(addr),
+            _ => self.read_for_modify(addr),
         };
 
         let old_bit0 = (arg & 0x01) != 0;
@@ -1185,7 +1434,7 @@ impl Cpu {
         0
     }
 
-    /// Subtraction with carry.
+    /// Subtraction with carry. Like `adc`, this is always binary, whatever the D flag says.
     fn sbc(&mut self, addr: u16) -> u8 {
         let a = self.a as u16;
 
@@ -1386,9 +1635,17 @@ impl Cpu {
         0
     }
 
+    /// Unstable, A is ORed with a constant that depends on the chip and temperature
+    /// before the AND. $EE is what most NES CPUs use.
+    /// See: https://www.nesdev.org/wiki/Visual6502wiki/6502_Opcode_8B_(XAA,_ANE)
     fn xaa(&mut self, addr: u16) -> u8 {
-        self.txa();
-        self.and(addr)
+        const MAGIC: u8 = 0xEE;
+
+        self.a = (self.a | MAGIC) & self.x & self.read(addr);
+        self.set_flag(StatusFlags::Z, self.a == 0);
+        self.set_flag(StatusFlags::N, is_negative(self.a));
+
+        0
     }
 
     fn axs(&mut self, addr: u16) -> u8 {
@@ -1403,42 +1660,51 @@ impl Cpu {
         0
     }
 
-    // TODO: Figure this one out
-    fn ahx(&mut self) -> u8 {
-        0
-    }
+    /// The indexed stores AHX, SHX, SHY and TAS AND the value with the high byte of the base
+    /// address plus 1. When indexing crosses a page, the high byte of the address written to
+    /// is replaced by the value as well.
+    /// See: https://forums.nesdev.org/viewtopic.php?t=8107
+    fn unstable_store(&mut self, addr: u16, index: u8, value: u8) -> u8 {
+        let high = (addr.wrapping_sub(index as u16) >> 8) as u8;
+        let value = value & high.wrapping_add(1);
 
-    // Had to see this thread to get these two instructions to work
-    // https://forums.nesdev.org/viewtopic.php?t=8107
-    fn shy(&mut self, addr: u16, arg_addr: u16, page_crossed: bool) -> u8 {
-        let high = self.read(arg_addr + 1);
-        let val = self.y & (high + 1);
-        if !page_crossed {
-            self.write(addr, val);
-        }
+        let addr = if (addr >> 8) as u8 != high {
+            ((value as u16) << 8) | (addr & 0x00FF)
+        } else {
+            addr
+        };
+        self.write(addr, value);
 
         0
     }
 
-    fn shx(&mut self, addr: u16, arg_addr: u16, page_crossed: bool) -> u8 {
-        let high = self.read(arg_addr + 1);
-        let val = self.x & (high + 1);
-        if !page_crossed {
-            self.write(addr, val);
-        }
+    fn ahx(&mut self, addr: u16) -> u8 {
+        self.unstable_store(addr, self.y, self.a & self.x)
+    }
 
-        0
+    fn shy(&mut self, addr: u16) -> u8 {
+        self.unstable_store(addr, self.x, self.y)
+    }
+
+    fn shx(&mut self, addr: u16) -> u8 {
+        self.unstable_store(addr, self.y, self.x)
     }
 
-    fn tas(&mut self, addr: u16, arg_addr: u16) -> u8 {
+    fn tas(&mut self, addr: u16) -> u8 {
         self.sp = self.a & self.x;
-        self.and(arg_addr + 1);
-        self.sta(addr)
+        self.unstable_store(addr, self.y, self.sp)
     }
 
-    // TODO: Figure this one out
-    fn las(&mut self) -> u8 {
-        0
+    fn las(&mut self, addr: u16) -> u8 {
+        let value = self.read(addr) & self.sp;
+        self.a = value;
+        self.x = value;
+        self.sp = value;
+
+        self.set_flag(StatusFlags::Z, value == 0);
+        self.set_flag(StatusFlags::N, is_negative(value));
+
+        1
     }
 
     fn stp(&mut self) -> u8 {
@@ -1456,12 +1722,53 @@ impl Cpu {
         self.interrupt(0xFFFE, 7);
     }
 
+    /// Signals an NMI, which is handled once the current instruction finishes.
+    pub fn request_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Signals an NMI caused by the current instruction writing to the PPU, which is only
+    /// handled after the next instruction. Writes happen on an instruction's last cycle,
+    /// after the CPU has checked for interrupts.
+    /// See: https://www.nesdev.org/wiki/CPU_interrupts#Detailed_interrupt_behavior
+    pub fn request_nmi_after_next_instruction(&mut self) {
+        self.nmi_pending = true;
+        self.nmi_delayed = true;
+    }
+
     /// Non-maskable interrupt, can't be disabled
-    pub fn nmi(&mut self) {
+    fn nmi(&mut self) {
         self.interrupt(0xFFFA, 7);
     }
 
     // Debug functions
+    /// Flags a hit whenever `addr` is written to, see `take_watch_hit`.
+    /// Watching an address in RAM also catches writes to its mirrors.
+    pub fn set_write_watch(&mut self, addr: u16) {
+        let addr = watch_addr(addr);
+        if !self.write_watches.contains(&addr) {
+            self.write_watches.push(addr);
+        }
+    }
+
+    pub fn clear_write_watches(&mut self) {
+        self.write_watches.clear();
+        self.watch_hit = None;
+    }
+
+    /// The address of the last write that hit a watch, clearing it.
+    pub fn take_watch_hit(&mut self) -> Option<u16> {
+        self.watch_hit.take()
+    }
+
+    /// Writes to the bus the same way an instruction would, for changing memory from outside
+    /// the emulation. Writes to registers take effect as usual, but write watches aren't hit.
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        let watch_hit = self.watch_hit;
+        self.write(addr, data);
+        self.watch_hit = watch_hit;
+    }
+
     pub fn get_instruction_repr(&mut self, instruction_addr: u16) -> String {
         let instruction = Instruction::lookup(self.read_debug(instruction_addr));
         let arg_addr = instruction_addr + 1;
@@ -1564,7 +1871,6 @@ impl Cpu {
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_log_line(&mut self) -> String {
         format!(
             "{:04X} {:02X} {:31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
@@ -1585,15 +1891,27 @@ fn is_negative(byte: u8) -> bool {
     (byte & 0x80) != 0
 }
 
+/// Maps mirrors of the internal RAM to the same address, so a watch catches all of them.
+fn watch_addr(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x1FFF => addr & 0x07FF,
+        _ => addr,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::emu::cartridge::Cartridge;
+    #[cfg(feature = "io")]
+    use crate::emu::input::ControllerButtons;
+    use crate::emu::region::Region;
 
     use super::*;
+    use std::rc::Rc;
+    #[cfg(feature = "io")]
     use std::{
         fs::File,
         io::{BufRead, BufReader},
-        rc::Rc,
     };
 
     #[test]
@@ -1652,6 +1970,403 @@ mod test {
     }
 
     #[test]
+    fn controller_ports_read_independently() {
+        use crate::emu::input::ControllerButtons;
+
+        let mut cpu = Cpu::new();
+        cpu.trigger_inputs(ControllerInput::One(ControllerButtons::A));
+        cpu.trigger_inputs(ControllerInput::Two(
+            ControllerButtons::B | ControllerButtons::Right,
+        ));
+
+        // Strobe to latch both controllers
+        cpu.write(0x4016, 1);
+        cpu.write(0x4016, 0);
+
+        let one: Vec<_> = (0..8).map(|_| cpu.read(0x4016)).collect();
+        let two: Vec<_> = (0..8).map(|_| cpu.read(0x4017)).collect();
+
+        assert_eq!(one, [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(two, [0, 1, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn controller_strobe() {
+        use crate::emu::input::ControllerButtons;
+
+        let mut cpu = Cpu::new();
+        cpu.trigger_inputs(ControllerInput::One(ControllerButtons::A));
+
+        // Held high, every read returns the current state of A
+        cpu.write(0x4016, 1);
+        assert!((0..10).all(|_| cpu.read(0x4016) == 1));
+        cpu.trigger_inputs(ControllerInput::One(ControllerButtons::Start));
+        assert_eq!(cpu.read(0x4016), 0);
+
+        // Latched when the strobe goes low
+        cpu.write(0x4016, 0);
+        cpu.trigger_inputs(ControllerInput::One(ControllerButtons::A));
+        assert_eq!(cpu.read(0x4016), 0);
+        assert_eq!(cpu.read(0x4016), 0);
+        assert_eq!(cpu.read(0x4016), 0);
+
+        // Writing low again doesn't reload, so shifting carries on from Start
+        cpu.write(0x4016, 0);
+        assert_eq!(cpu.read(0x4016), 1);
+        assert_eq!(cpu.read(0x4016), 0);
+    }
+
+    #[test]
+    fn open_bus_reads() {
+        use crate::emu::palette::Palette;
+
+        let mut cpu = Cpu::new();
+        cpu.with_ppu(Rc::new(RefCell::new(Ppu::new(
+            Palette::default(),
+            Region::Ntsc,
+        ))));
+
+        // Only the top 3 bits of PPUSTATUS are driven, and VBlank is set at power on.
+        // The rest come from the PPU's own latch, not the CPU's data bus
+        cpu.write(0x2003, 0x1F);
+        cpu.write(0x0000, 0x00);
+        assert_eq!(cpu.read(0x2002), 0x80 | 0x1F);
+        cpu.write(0x2003, 0x00);
+        assert_eq!(cpu.read(0x2002), 0x00);
+
+        // Write-only PPU registers return the PPU's latch, and unmapped addresses
+        // return the last value on the CPU's bus
+        cpu.write(0x2003, 0x5A);
+        assert_eq!(cpu.read(0x2000), 0x5A);
+        cpu.write(0x0000, 0xA5);
+        assert_eq!(cpu.read(0x4018), 0xA5);
+
+        // The controller only drives bit 0
+        cpu.write(0x0000, 0x40);
+        assert_eq!(cpu.read(0x4016), 0x40);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn dmc_dma_stalls_cpu() {
+        let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc)));
+        let mut cpu = Cpu::new();
+        cpu.with_apu(apu.clone());
+        let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+        cpu.reset_to(0xC000);
+
+        // Play a 1 byte sample from $C000
+        cpu.write(0x4012, 0x00);
+        cpu.write(0x4013, 0x00);
+        cpu.write(0x4015, 0x10);
+        assert_eq!(apu.borrow().dmc_dma_request(), Some(0xC000));
+
+        let stall = if cpu.total_cycles.is_multiple_of(2) {
+            3
+        } else {
+            4
+        };
+        for _ in 0..stall {
+            cpu.clock();
+            assert_eq!(cpu.cycles, 7);
+        }
+        assert_eq!(apu.borrow().dmc_dma_request(), None);
+
+        cpu.clock();
+        assert_eq!(cpu.cycles, 6);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn dmc_dma_repeats_controller_read() {
+        let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc)));
+        let mut cpu = Cpu::new();
+        cpu.with_apu(apu.clone());
+        let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+
+        // LDA $4016, which reads the controller on its last cycle
+        for (i, byte) in [0xAD, 0x16, 0x40].into_iter().enumerate() {
+            cpu.write(0x0200 + i as u16, byte);
+        }
+        cpu.reset_to(0x0200);
+        cpu.trigger_inputs(ControllerInput::One(
+            ControllerButtons::A | ControllerButtons::Select,
+        ));
+        cpu.write(0x4016, 1);
+        cpu.write(0x4016, 0);
+
+        cpu.cycles = 0;
+        cpu.clock();
+        assert_eq!(cpu.a & 0x01, 1);
+        while cpu.cycles > 1 {
+            cpu.clock();
+        }
+
+        // A sample fetch starting on the read cycle shifts out B, so Select is read next
+        cpu.write(0x4012, 0x00);
+        cpu.write(0x4013, 0x00);
+        cpu.write(0x4015, 0x10);
+        cpu.clock();
+        assert_eq!(cpu.read(0x4016) & 0x01, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn apu_frame_irq() {
+        let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc)));
+        let mut cpu = Cpu::new();
+        cpu.with_apu(apu.clone());
+        let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+
+        // An infinite loop, JMP $0200, which is left when the IRQ jumps to the cartridge's handler
+        for (i, byte) in [0x4C, 0x00, 0x02].into_iter().enumerate() {
+            cpu.write(0x0200 + i as u16, byte);
+        }
+        cpu.reset_to(0x0200);
+        cpu.set_flag(StatusFlags::I, false);
+
+        let mut cycles = 0;
+        while (0x0200..0x0203).contains(&cpu.pc()) {
+            cpu.clock();
+            apu.borrow_mut().clock();
+            cycles += 1;
+            assert!(cycles < 40000, "IRQ never taken");
+        }
+        assert!(cycles > 29828);
+        assert!(cpu.get_flag(StatusFlags::I));
+    }
+
+    #[test]
+    fn mapper_irq_respects_interrupt_disable() {
+        // Mapper 69 ROM filled with NOPs, with the IRQ vector pointing to $E000
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 2, 1, 0x50, 0x40, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut prg = vec![0xEA; 32 * 1024];
+        prg[0x7FFE..].copy_from_slice(&[0x00, 0xE0]);
+        rom.extend(prg);
+        rom.extend([0; 8 * 1024]);
+
+        let mut cpu = Cpu::new();
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+        cpu.reset_to(0x8000);
+
+        // Fire an IRQ after 5 cycles
+        for (command, parameter) in [(0xE, 0x05), (0xF, 0x00), (0xD, 0x81)] {
+            cpu.write(0x8000, command);
+            cpu.write(0xA000, parameter);
+        }
+        for _ in 0..100 {
+            cpu.clock();
+        }
+        assert!(cpu.pc() < 0xE000);
+        assert!(cpu.pending_irq());
+        assert!(cpu.interrupts_disabled());
+
+        cpu.set_flag(StatusFlags::I, false);
+        for _ in 0..10 {
+            cpu.clock();
+        }
+        assert!(cpu.pc() >= 0xE000);
+        assert!(cpu.get_flag(StatusFlags::I));
+    }
+
+    #[test]
+    fn read_modify_write_dummy_write() {
+        use crate::emu::palette::Palette;
+
+        let ppu = Rc::new(RefCell::new(Ppu::new(Palette::default(), Region::Ntsc)));
+        let mut cpu = Cpu::new();
+        cpu.with_ppu(ppu.clone());
+
+        // ASL $2006, which reads back the $20 last written to the PPU
+        for (i, byte) in [0x0E, 0x06, 0x20].into_iter().enumerate() {
+            cpu.write(0x0200 + i as u16, byte);
+        }
+        cpu.write(0x2003, 0x20);
+        cpu.reset_to(0x0200);
+        for _ in 0..8 {
+            cpu.clock();
+        }
+
+        // PPUADDR sees $20 written back, then the shifted $40
+        assert_eq!(ppu.borrow().addr(), 0x2040);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn page_crossing_dummy_read() {
+        // LDA $40F5,X and the unofficial NOP $40F5,X
+        for opcode in [0xBD, 0x1C] {
+            let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc)));
+            let mut cpu = Cpu::new();
+            cpu.with_apu(apu.clone());
+            let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+            cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+            // Just past the end of the frame sequence, where the frame interrupt is raised
+            for _ in 0..29840 {
+                apu.borrow_mut().clock();
+            }
+
+            // Without a page cross it doesn't go near $4015
+            run_instruction(&mut cpu, &[opcode, 0xF5, 0x40], 0x00, 0x00, 0x00);
+            assert!(apu.borrow().irq_line());
+
+            // With X = $20 it reads $4015 before $4115, acknowledging the frame interrupt
+            run_instruction(&mut cpu, &[opcode, 0xF5, 0x40], 0x00, 0x20, 0x00);
+            assert!(!apu.borrow().irq_line());
+        }
+    }
+
+    #[test]
+    fn decimal_flag_ignored() {
+        let mut cpu = Cpu::new();
+        // SED or CLD, then CLC, LDA #$28, ADC #$19: $41 in binary, $47 in BCD.
+        // Then SEC, LDA #$50, SBC #$01: $4F in binary, $49 in BCD
+        for flag_op in [0xF8, 0xD8] {
+            let code = [
+                flag_op, 0x18, 0xA9, 0x28, 0x69, 0x19, 0x38, 0xA9, 0x50, 0xE9, 0x01,
+            ];
+            run_instruction(&mut cpu, &code, 0x00, 0x00, 0x00);
+            assert_eq!(cpu.get_flag(StatusFlags::D), flag_op == 0xF8);
+
+            for _ in 0..3 {
+                cpu.next_instruction();
+            }
+            assert_eq!(cpu.a(), 0x41);
+            assert!(!cpu.get_flag(StatusFlags::C));
+
+            for _ in 0..3 {
+                cpu.next_instruction();
+            }
+            assert_eq!(cpu.a(), 0x4F);
+            assert!(cpu.get_flag(StatusFlags::C));
+        }
+    }
+
+    /// Runs one instruction from $0200, with A, X and Y set first.
+    fn run_instruction(cpu: &mut Cpu, code: &[u8], a: u8, x: u8, y: u8) {
+        for (i, &byte) in code.iter().enumerate() {
+            cpu.write(0x0200 + i as u16, byte);
+        }
+        cpu.reset_to(0x0200);
+        cpu.a = a;
+        cpu.x = x;
+        cpu.y = y;
+        cpu.next_instruction();
+    }
+
+    #[test]
+    fn unstable_stores() {
+        let mut cpu = Cpu::new();
+
+        // SHX $0300,Y: X & ($03 + 1)
+        run_instruction(&mut cpu, &[0x9E, 0x00, 0x03], 0x00, 0xFF, 0x10);
+        assert_eq!(cpu.read(0x0310), 0x04);
+
+        // SHY $0300,X
+        run_instruction(&mut cpu, &[0x9C, 0x00, 0x03], 0x00, 0x20, 0x0D);
+        assert_eq!(cpu.read(0x0320), 0x04);
+
+        // AHX $0300,Y: A & X & ($03 + 1)
+        run_instruction(&mut cpu, &[0x9F, 0x00, 0x03], 0xFC, 0x07, 0x30);
+        assert_eq!(cpu.read(0x0330), 0x04);
+
+        // AHX ($10),Y uses the high byte of the pointer
+        cpu.write(0x0010, 0x00);
+        cpu.write(0x0011, 0x05);
+        run_instruction(&mut cpu, &[0x93, 0x10], 0xFF, 0xFF, 0x01);
+        assert_eq!(cpu.read(0x0501), 0x06);
+
+        // TAS $0300,Y sets SP to A & X before storing
+        run_instruction(&mut cpu, &[0x9B, 0x00, 0x03], 0xF7, 0x3F, 0x40);
+        assert_eq!(cpu.stkp(), 0x37);
+        assert_eq!(cpu.read(0x0340), 0x04);
+
+        // Crossing a page replaces the high byte of the address with the value:
+        // $01F0 + $20 would be $0210, but X & ($01 + 1) = $02 is written to $0210 instead
+        run_instruction(&mut cpu, &[0x9E, 0xF0, 0x01], 0x00, 0x06, 0x20);
+        assert_eq!(cpu.read(0x0210), 0x02);
+        // With X & $02 = $00 it goes to $0010, leaving $0210 alone
+        cpu.write(0x0010, 0xFF);
+        cpu.write(0x0210, 0xFF);
+        run_instruction(&mut cpu, &[0x9E, 0xF0, 0x01], 0x00, 0x05, 0x20);
+        assert_eq!(cpu.read(0x0010), 0x00);
+        assert_eq!(cpu.read(0x0210), 0xFF);
+    }
+
+    #[test]
+    fn las_and_xaa() {
+        let mut cpu = Cpu::new();
+
+        // LAS $0300,Y: A, X and SP all become memory & SP
+        cpu.write(0x0305, 0xF3);
+        run_instruction(&mut cpu, &[0xBB, 0x00, 0x03], 0x00, 0x00, 0x05);
+        assert_eq!(cpu.stkp(), 0xFD & 0xF3);
+        assert_eq!((cpu.a(), cpu.x()), (0xF1, 0xF1));
+        assert!(cpu.get_flag(StatusFlags::N));
+
+        // XAA #$FF: (A | $EE) & X & $FF
+        run_instruction(&mut cpu, &[0x8B, 0xFF], 0x01, 0x0F, 0x00);
+        assert_eq!(cpu.a(), 0x0F);
+        run_instruction(&mut cpu, &[0x8B, 0x0F], 0x00, 0xF1, 0x00);
+        assert_eq!(cpu.a(), 0x00);
+        assert!(cpu.get_flag(StatusFlags::Z));
+    }
+
+    #[test]
+    fn write_watch() {
+        let mut cpu = Cpu::new();
+        cpu.set_write_watch(0x0300);
+        cpu.set_write_watch(0x0010);
+
+        cpu.write(0x0301, 0x01);
+        assert_eq!(cpu.take_watch_hit(), None);
+        cpu.write(0x0300, 0x01);
+        assert_eq!(cpu.take_watch_hit(), Some(0x0300));
+        assert_eq!(cpu.take_watch_hit(), None);
+
+        // Mirror of $0010
+        cpu.write(0x0810, 0x01);
+        assert_eq!(cpu.take_watch_hit(), Some(0x0810));
+
+        cpu.clear_write_watches();
+        cpu.write(0x0300, 0x01);
+        assert_eq!(cpu.take_watch_hit(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn soft_reset_keeps_ram() {
+        let mut cpu = Cpu::new();
+        let cartridge = Cartridge::new("assets/test_roms/nestest.nes").unwrap();
+        cpu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+        cpu.power_on();
+        assert_eq!((cpu.pc(), cpu.stkp()), (0xC004, 0xFD));
+
+        cpu.write(0x0010, 0xAB);
+        cpu.a = 0x12;
+        cpu.set_flag(StatusFlags::I, false);
+
+        cpu.reset();
+        assert_eq!(cpu.read(0x0010), 0xAB);
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.stkp(), 0xFA);
+        assert!(cpu.get_flag(StatusFlags::I));
+        assert_eq!(cpu.pc(), 0xC004);
+
+        cpu.power_on();
+        assert_eq!(cpu.read(0x0010), 0x00);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.stkp(), 0xFD);
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
     fn nestest_rom() {
         let mut cpu = Cpu::new();
 
@@ -0,0 +1,112 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+/// The letters of a Game Genie code, in the order of the values they stand for.
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// A Game Genie code, which replaces what the CPU reads from an address in PRG ROM.
+/// 8 letter codes only replace it when the ROM holds the compare value, so they don't
+/// break other banks that are switched into the same address.
+/// See: https://www.nesdev.org/wiki/Game_Genie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameGenieCode {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    /// The byte the CPU sees at `addr` when the ROM holds `data`.
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        if addr == self.addr && self.compare.is_none_or(|compare| compare == data) {
+            self.value
+        } else {
+            data
+        }
+    }
+}
+
+/// Each letter is 4 bits, which are shuffled into the address, value and compare value.
+impl FromStr for GameGenieCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let n = s
+            .bytes()
+            .map(|letter| {
+                LETTERS
+                    .iter()
+                    .position(|&l| l == letter.to_ascii_uppercase())
+                    .map(|n| n as u16)
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("Invalid Game Genie code {:?}, unknown letter", s))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(anyhow!(
+                "Invalid Game Genie code {:?}, expected 6 or 8 letters",
+                s
+            ));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[4] & 8) << 8)
+            | ((n[5] & 7) << 8)
+            | ((n[1] & 8) << 4)
+            | ((n[2] & 7) << 4)
+            | (n[3] & 8)
+            | (n[4] & 7);
+
+        // The value's bit 3 comes from the last letter
+        let last = n[n.len() - 1];
+        let value = ((n[0] & 8) << 4) | ((n[1] & 7) << 4) | (last & 8) | (n[0] & 7);
+
+        let compare =
+            (n.len() == 8).then(|| ((n[6] & 8) << 4) | ((n[7] & 7) << 4) | (n[5] & 8) | (n[6] & 7));
+
+        Ok(GameGenieCode {
+            addr,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+        })
+    }
+}
+
+impl Display for GameGenieCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "${:04X} = ${:02X}", self.addr, self.value)?;
+        if let Some(compare) = self.compare {
+            write!(f, " if ${:02X}", compare)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        // Super Mario Bros. infinite lives
+        let code: GameGenieCode = "SXIOPO".parse().unwrap();
+        assert_eq!(
+            code,
+            GameGenieCode {
+                addr: 0x91D9,
+                value: 0xAD,
+                compare: None
+            }
+        );
+
+        let code: GameGenieCode = "zexpyglt".parse().unwrap();
+        assert_eq!(code.to_string(), "$94A7 = $02 if $63");
+        assert_eq!(code.apply(0x94A7, 0x63), 0x02);
+        assert_eq!(code.apply(0x94A7, 0x64), 0x64);
+        assert_eq!(code.apply(0x94A8, 0x63), 0x63);
+
+        assert!("SXIOP".parse::<GameGenieCode>().is_err());
+        assert!("SXIOPB".parse::<GameGenieCode>().is_err());
+    }
+}
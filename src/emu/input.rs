@@ -1,7 +1,9 @@
 use bitflags::bitflags;
 
+use crate::renderer::{Color, Pixel};
+
 bitflags! {
-    #[derive(Clone, Copy, Debug, Default)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub struct ControllerButtons: u8 {
         const A = 1 << 0;
         const B = 1 << 1;
@@ -15,10 +17,15 @@ bitflags! {
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum ControllerInput {
     One(ControllerButtons),
     Two(ControllerButtons),
+    /// Plugs a Zapper into port 2, aimed at the given screen position.
+    Zapper {
+        x: usize,
+        y: usize,
+        trigger: bool,
+    },
 }
 
 #[derive(Default, Copy, Clone, Debug)]
@@ -55,3 +62,90 @@ impl StandardController {
         data
     }
 }
+
+/// The Zapper light gun.
+/// See: https://www.nesdev.org/wiki/Zapper
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Zapper {
+    x: usize,
+    y: usize,
+    trigger: bool,
+    // Scanline where the photodiode last saw a bright pixel
+    lit_scanline: Option<usize>,
+}
+
+impl Zapper {
+    /// The photodiode sees a small area around where the Zapper is aimed.
+    const SENSE_RADIUS: usize = 2;
+    /// How many scanlines the light bit stays on after the beam passes.
+    const LIGHT_SCANLINES: usize = 20;
+    const BRIGHTNESS_THRESHOLD: u16 = 0xC0;
+
+    pub fn notify_input(&mut self, x: usize, y: usize, trigger: bool) {
+        self.x = x;
+        self.y = y;
+        self.trigger = trigger;
+    }
+
+    /// Updates the light sensor as the PPU outputs a pixel.
+    pub fn sense(&mut self, pixel: &Pixel) {
+        if let Some(lit) = self.lit_scanline {
+            if pixel.y < lit || pixel.y >= lit + Self::LIGHT_SCANLINES {
+                self.lit_scanline = None;
+            }
+        }
+
+        let in_view = pixel.x.abs_diff(self.x) <= Self::SENSE_RADIUS
+            && pixel.y.abs_diff(self.y) <= Self::SENSE_RADIUS;
+        if in_view && Self::is_bright(pixel.color) {
+            self.lit_scanline = Some(pixel.y);
+        }
+    }
+
+    fn is_bright(color: Color) -> bool {
+        let brightness = (color.0 as u16 + color.1 as u16 + color.2 as u16) / 3;
+        brightness >= Self::BRIGHTNESS_THRESHOLD
+    }
+
+    /// Bit 3 is cleared while light is detected, bit 4 is set while the trigger is pulled.
+    pub fn read(&self) -> u8 {
+        let light = if self.lit_scanline.is_some() { 0 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0 };
+        light | trigger
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pixel(x: usize, y: usize, color: Color) -> Pixel {
+        Pixel { x, y, color }
+    }
+
+    #[test]
+    fn zapper_senses_bright_pixels() {
+        let mut zapper = Zapper::default();
+        zapper.notify_input(100, 50, false);
+        assert_eq!(zapper.read(), 0x08);
+
+        // Dark pixels or pixels outside the view don't register
+        zapper.sense(&pixel(100, 50, Color::BLACK));
+        zapper.sense(&pixel(10, 50, Color::WHITE));
+        assert_eq!(zapper.read(), 0x08);
+
+        zapper.sense(&pixel(101, 50, Color::WHITE));
+        assert_eq!(zapper.read(), 0x00);
+
+        // The light fades once the beam has moved far enough down
+        zapper.sense(&pixel(0, 50 + Zapper::LIGHT_SCANLINES, Color::BLACK));
+        assert_eq!(zapper.read(), 0x08);
+    }
+
+    #[test]
+    fn zapper_trigger() {
+        let mut zapper = Zapper::default();
+        zapper.notify_input(0, 0, true);
+        assert_eq!(zapper.read(), 0x18);
+    }
+}
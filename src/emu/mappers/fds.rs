@@ -0,0 +1,518 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+pub const FDS_MAGIC: &[u8; 4] = b"FDS\x1A";
+/// Headerless images start straight away with the first side's disk info block.
+pub const DISK_INFO_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+
+pub const BIOS_SIZE: usize = 8 * 1024;
+const HEADER_SIZE: usize = 16;
+const SIDE_SIZE: usize = 65500;
+const RAM_SIZE: usize = 32 * 1024;
+
+/// Gaps the drive reads as zeros, which .fds files leave out.
+const LEADING_GAP_BYTES: usize = 28300 / 8;
+const BLOCK_GAP_BYTES: usize = 976 / 8;
+/// Marks the start of each block after a gap.
+const BLOCK_START: u8 = 0x80;
+
+/// CPU cycles between each byte the drive reads, about 96.4 kbit/s.
+const BYTE_CYCLES: u32 = 150;
+/// CPU cycles for the head to get back to the start of the disk.
+const REWIND_CYCLES: u32 = 50000;
+
+/// The sides of a Famicom Disk System disk image, as the drive sees them.
+/// See: https://www.nesdev.org/wiki/FDS_disk_format
+#[derive(Debug)]
+pub struct FdsDisk {
+    sides: Vec<Vec<u8>>,
+}
+
+impl FdsDisk {
+    /// Parses an .fds file, with or without its 16 byte header.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let data = if bytes.starts_with(FDS_MAGIC) {
+            &bytes[HEADER_SIZE.min(bytes.len())..]
+        } else {
+            bytes
+        };
+
+        if data.is_empty() || !data.len().is_multiple_of(SIDE_SIZE) {
+            return Err(anyhow!(
+                "Disk image of {} bytes isn't a whole number of {} byte sides",
+                data.len(),
+                SIDE_SIZE
+            ));
+        }
+
+        let sides = data
+            .chunks_exact(SIDE_SIZE)
+            .enumerate()
+            .map(|(i, side)| {
+                if !side.starts_with(DISK_INFO_MAGIC) {
+                    return Err(anyhow!("Side {} is missing its disk info block", i));
+                }
+                Ok(add_gaps(side))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(FdsDisk { sides })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+}
+
+/// Lays out a side the way it is on the disk, with gaps and start marks between the blocks
+/// and a CRC after each one. The CRCs are never checked, so they're left as zeros.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP_BYTES];
+
+    let mut pos = 0;
+    while pos < side.len() {
+        let len = match side[pos] {
+            // Disk info
+            1 => 56,
+            // File amount
+            2 => 2,
+            // File header
+            3 => 16,
+            // File data, with the size from the end of the file header before it
+            4 if pos >= 3 => 1 + u16::from_le_bytes([side[pos - 3], side[pos - 2]]) as usize,
+            // The rest of the side is unused
+            _ => break,
+        };
+        let block = &side[pos..(pos + len).min(side.len())];
+
+        raw.push(BLOCK_START);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&[0, 0]);
+        raw.resize(raw.len() + BLOCK_GAP_BYTES, 0);
+
+        pos += len;
+    }
+
+    raw
+}
+
+/// The Famicom Disk System's RAM adapter, with a disk inserted into the drive.
+/// It has 32 KB of PRG RAM, 8 KB of CHR RAM and the BIOS at $E000,
+/// which loads the game from the disk. Only the first side can be inserted for now,
+/// and the expansion audio isn't emulated.
+/// See: https://www.nesdev.org/wiki/Family_Computer_Disk_System
+pub struct Fds {
+    disk: FdsDisk,
+    side: Option<usize>,
+    ram: Vec<u8>,
+    mirroring: Mirroring,
+
+    disk_registers_enabled: bool,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_enabled: bool,
+    timer_repeat: bool,
+    // Acknowledged by reading $4030, so these need to change on reads
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    // Set by the BIOS once it's waiting for the next block
+    transfer_start: bool,
+    disk_irq_enabled: bool,
+
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    position: usize,
+    delay: u32,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    disk_irq: bool,
+}
+
+impl Fds {
+    pub fn new(disk: FdsDisk) -> Self {
+        Self {
+            disk,
+            side: Some(0),
+            ram: vec![0; RAM_SIZE],
+            mirroring: Mirroring::Horizontal,
+
+            disk_registers_enabled: false,
+
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_enabled: false,
+            timer_repeat: false,
+            timer_irq: false,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            transfer_start: false,
+            disk_irq_enabled: false,
+
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            position: 0,
+            delay: 0,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+        }
+    }
+
+    fn read_status(&self) -> u8 {
+        self.timer_irq as u8 | (self.transfer_complete as u8) << 1 | (self.end_of_head as u8) << 6
+    }
+
+    fn drive_status(&self) -> u8 {
+        let inserted = self.side.is_some();
+        // Bit 6 is open bus, which is usually set from the high byte of the address
+        0x40 | (!inserted as u8)
+            | ((!inserted || !self.scanning) as u8) << 1
+            | (!inserted as u8) << 2
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.motor_on = data & 0x01 != 0;
+        self.reset_transfer = data & 0x02 != 0;
+        self.read_mode = data & 0x04 != 0;
+        self.mirroring = if data & 0x08 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.crc_control = data & 0x10 != 0;
+        self.transfer_start = data & 0x40 != 0;
+        self.disk_irq_enabled = data & 0x80 != 0;
+        self.disk_irq = false;
+    }
+
+    fn clock_timer(&mut self) {
+        if !(self.timer_enabled && self.disk_registers_enabled) {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            if !self.timer_repeat {
+                self.timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Moves the disk under the head, transferring a byte every `BYTE_CYCLES`.
+    fn clock_drive(&mut self) {
+        let Some(side) = self.side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let raw = &mut self.disk.sides[side];
+        let mut raise_irq = self.disk_irq_enabled;
+
+        if self.read_mode {
+            let data = raw[self.position];
+            if !self.transfer_start {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // The start mark is transferred, but doesn't interrupt
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+            if !self.transfer_start {
+                data = 0;
+            }
+            raw[self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= raw.len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x4030 => Ok(MapRead::RAMData(self.read_status())),
+            0x4031 => Ok(MapRead::RAMData(self.read_data)),
+            0x4032 => Ok(MapRead::RAMData(self.drive_status())),
+            // Bit 7 is the battery in the disk drive, which is always good
+            0x4033 => Ok(MapRead::RAMData(0x80)),
+            0x6000..=0xDFFF => Ok(MapRead::RAMData(self.ram[(addr - 0x6000) as usize])),
+            0xE000..=0xFFFF => Ok(MapRead::Address((addr - 0xE000) as usize)),
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | data as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((data as u16) << 8),
+            0x4022 => {
+                self.timer_repeat = data & 0x01 != 0;
+                self.timer_enabled = data & 0x02 != 0 && self.disk_registers_enabled;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = data & 0x01 != 0;
+                if !self.disk_registers_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024..=0x4026 if !self.disk_registers_enabled => {}
+            0x4024 => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => self.write_control(data),
+            // External connector, and the expansion audio
+            0x4026 | 0x4040..=0x4097 => {}
+            0x6000..=0xDFFF => {
+                self.ram[(addr - 0x6000) as usize] = data;
+                return Ok(MapWrite::RAMWritten);
+            }
+            0xE000..=0xFFFF => return Err(anyhow!("Can't write to the BIOS")),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+
+        Ok(MapWrite::WroteRegister)
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+        Ok(MapRead::Address(addr as usize))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+        Ok(MapWrite::Address(addr as usize))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_prg_read(&mut self, addr: u16) {
+        // Reading the status acknowledges both IRQs, and reading the data only the disk's
+        match addr {
+            0x4030 => {
+                self.timer_irq = false;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        self.clock_timer();
+        self.clock_drive();
+        self.timer_irq || self.disk_irq
+    }
+
+    fn prg_bank_map(&self) -> Vec<(u16, usize)> {
+        vec![(0xE000, 0)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::cartridge::Cartridge;
+
+    /// A one-sided disk with a single 4 byte file.
+    fn disk_image() -> Vec<u8> {
+        let mut image = FDS_MAGIC.to_vec();
+        image.push(1);
+        image.resize(HEADER_SIZE, 0);
+
+        let mut disk_info = DISK_INFO_MAGIC.to_vec();
+        disk_info.resize(56, 0);
+        image.extend_from_slice(&disk_info);
+        image.extend_from_slice(&[0x02, 1]);
+        // File number, ID and name, then a 4 byte program to load at $6000
+        image.extend_from_slice(&[0x03, 0, 0]);
+        image.extend_from_slice(b"FILENAME");
+        image.extend_from_slice(&[0x00, 0x60, 4, 0, 0]);
+        image.extend_from_slice(&[0x04, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+        image.resize(HEADER_SIZE + SIDE_SIZE, 0);
+        image
+    }
+
+    fn peek(fds: &Fds, addr: u16) -> u8 {
+        match fds.map_prg_read(addr).unwrap() {
+            MapRead::RAMData(data) => data,
+            MapRead::Address(_) => panic!("Expected a register"),
+        }
+    }
+
+    fn read(fds: &mut Fds, addr: u16) -> u8 {
+        let data = peek(fds, addr);
+        fds.on_prg_read(addr);
+        data
+    }
+
+    #[test]
+    fn parse_disk() {
+        let image = disk_image();
+        let disk = FdsDisk::parse(&image).unwrap();
+        assert_eq!(disk.side_count(), 1);
+
+        let raw = &disk.sides[0];
+        assert!(raw[..LEADING_GAP_BYTES].iter().all(|&b| b == 0));
+        assert_eq!(raw[LEADING_GAP_BYTES], BLOCK_START);
+        assert!(raw[LEADING_GAP_BYTES + 1..].starts_with(DISK_INFO_MAGIC));
+
+        // Each of the 4 blocks gets a start mark, CRC and gap
+        let blocks = 56 + 2 + 16 + 5;
+        assert_eq!(
+            raw.len(),
+            LEADING_GAP_BYTES + blocks + 4 * (3 + BLOCK_GAP_BYTES)
+        );
+        let file_data = raw.len() - BLOCK_GAP_BYTES - 2 - 5;
+        assert_eq!(
+            raw[file_data..file_data + 5],
+            [0x04, 0xDE, 0xAD, 0xBE, 0xEF]
+        );
+
+        // Headerless images work too
+        assert_eq!(
+            FdsDisk::parse(&image[HEADER_SIZE..]).unwrap().sides,
+            disk.sides
+        );
+        assert!(FdsDisk::parse(&image[..1000]).is_err());
+    }
+
+    #[test]
+    fn timer_irq() {
+        let mut fds = Fds::new(FdsDisk::parse(&disk_image()).unwrap());
+        fds.map_prg_write(0x4023, 0x01).unwrap();
+        fds.map_prg_write(0x4020, 0x02).unwrap();
+        fds.map_prg_write(0x4021, 0x00).unwrap();
+        fds.map_prg_write(0x4022, 0x02).unwrap();
+
+        assert!(!fds.on_cpu_cycle());
+        assert!(!fds.on_cpu_cycle());
+        assert!(fds.on_cpu_cycle());
+
+        // Peeking at the status doesn't acknowledge it, but reading it does,
+        // and without repeat it only fires once
+        assert_eq!(peek(&fds, 0x4030) & 0x01, 0x01);
+        assert!(fds.on_cpu_cycle());
+        assert_eq!(read(&mut fds, 0x4030) & 0x01, 0x01);
+        assert!((0..10).all(|_| !fds.on_cpu_cycle()));
+    }
+
+    #[test]
+    fn read_disk() {
+        let mut fds = Fds::new(FdsDisk::parse(&disk_image()).unwrap());
+        // Inserted, but not ready until the motor is on
+        assert_eq!(read(&mut fds, 0x4032), 0x42);
+
+        fds.map_prg_write(0x4023, 0x01).unwrap();
+        // Motor on, read mode, start the transfer with IRQs
+        fds.map_prg_write(0x4025, 0xE5).unwrap();
+
+        let mut next_byte = || {
+            while !fds.on_cpu_cycle() {}
+            read(&mut fds, 0x4031)
+        };
+        // The block start mark is skipped, and the disk info block is read
+        assert_eq!(next_byte(), 0x01);
+        assert_eq!(next_byte(), b'*');
+        assert_eq!(read(&mut fds, 0x4032), 0x40);
+    }
+
+    #[test]
+    fn cartridge_from_disk() {
+        let mut bios = vec![0; BIOS_SIZE];
+        // Reset vector
+        bios[0x1FFC..].copy_from_slice(&[0x24, 0xEE, 0x00, 0x00]);
+
+        let mut cartridge = Cartridge::from_fds(&disk_image(), bios).unwrap();
+        assert_eq!(cartridge.mapper_num(), 20);
+        assert_eq!(cartridge.cpu_read(0xFFFC).unwrap(), 0x24);
+        assert_eq!(cartridge.cpu_read(0xFFFD).unwrap(), 0xEE);
+
+        cartridge.cpu_write(0xDFFF, 0x42).unwrap();
+        assert_eq!(cartridge.cpu_read(0xDFFF).unwrap(), 0x42);
+        cartridge.ppu_write(0x1FFF, 0x24).unwrap();
+        assert_eq!(cartridge.ppu_read(0x1FFF).unwrap(), 0x24);
+
+        assert!(Cartridge::from_fds(&disk_image(), vec![0; 100]).is_err());
+    }
+}
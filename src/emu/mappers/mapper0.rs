@@ -5,12 +5,12 @@ use super::{MapRead, MapWrite, Mapper};
 const RAM_SIZE: usize = 8 * 1024;
 
 pub struct Mapper0 {
-    prg_banks: u8,
+    prg_banks: u16,
     ram: [u8; RAM_SIZE],
 }
 
 impl Mapper0 {
-    pub fn new(num_banks: u8) -> Self {
+    pub fn new(num_banks: u16) -> Self {
         Self {
             prg_banks: num_banks,
             ram: [0; RAM_SIZE],
@@ -57,4 +57,12 @@ impl Mapper for Mapper0 {
     fn map_chr_write(&self, _addr: u16) -> Result<MapWrite> {
         Err(anyhow!("Can't write to ROM"))
     }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
 }
@@ -2,32 +2,40 @@ use crate::emu::cartridge::Mirroring;
 
 use super::{MapRead, MapWrite, Mapper};
 use anyhow::{anyhow, Result};
-use modular_bitfield::prelude::*;
 
+// TODO: Emulate PRG RAM bank switching, in 8 KB banks
 const PRG_RAM_SIZE: usize = 32 * 1024;
-// TODO: Emulate PRG RAM bank switching
-#[allow(dead_code)]
-const PRG_RAM_BANK_SIZE: usize = 8 * 1024;
 const PRG_ROM_BANK_SIZE: usize = 16 * 1024;
 const CHR_BANK_SIZE: usize = 4 * 1024;
 
-#[bitfield]
-#[derive(Debug)]
-struct ControlRegister {
-    mirroring: B2,
-    prg_rom_bank_mode: B2,
-    chr_bank_mode: B1,
-    #[skip]
-    padding: B3,
+mod control {
+    // modular_bitfield expands field types with redundant parentheses and generates
+    // accessors that aren't all used, outside of the struct where an allow can't reach them
+    #![allow(unused_parens, dead_code)]
+
+    use modular_bitfield::prelude::*;
+
+    #[bitfield]
+    #[derive(Debug)]
+    pub(super) struct ControlRegister {
+        pub(super) mirroring: B2,
+        pub(super) prg_rom_bank_mode: B2,
+        pub(super) chr_bank_mode: B1,
+        #[skip]
+        padding: B3,
+    }
 }
+use control::ControlRegister;
 
 #[derive(Debug)]
 pub struct Mapper1 {
-    prg_bank_count: u8,
-    chr_bank_count: u8,
+    prg_bank_count: u16,
+    chr_bank_count: u16,
 
     load: u8,
     load_write_count: u8,
+    // Writes to the load register on consecutive CPU cycles are ignored
+    cycles_since_load_write: u8,
 
     control: ControlRegister,
     chr_bank0: u8,
@@ -38,12 +46,13 @@ pub struct Mapper1 {
 }
 
 impl Mapper1 {
-    pub fn new(prg_banks: u8, chr_banks: u8) -> Self {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
         Self {
             prg_bank_count: prg_banks,
             chr_bank_count: chr_banks,
             load: 0x00,
             load_write_count: 0,
+            cycles_since_load_write: u8::MAX,
 
             control: ControlRegister::from_bytes([0x0C]),
             chr_bank0: 0x00,
@@ -53,6 +62,8 @@ impl Mapper1 {
         }
     }
 
+    /// Writing a value with bit 7 set clears the shift register
+    /// and ORs the control register with 0x0C, fixing the last bank at 0xC000.
     fn reset(&mut self) {
         self.load = 0x00;
         self.load_write_count = 0;
@@ -104,8 +115,16 @@ impl Mapper for Mapper1 {
                 self.prg_ram[(addr - 0x6000) as usize] = data;
                 Ok(MapWrite::RAMWritten)
             }
-            // TODO: Emulate ignoring consecutive writes
             0x8000..=0xFFFF => {
+                // The MMC1 only sees the first of two writes on consecutive cycles,
+                // so the dummy write of a read-modify-write instruction wins.
+                // See: https://www.nesdev.org/wiki/MMC1#Consecutive-cycle_writes
+                let consecutive = self.cycles_since_load_write < 2;
+                self.cycles_since_load_write = 0;
+                if consecutive {
+                    return Ok(MapWrite::WroteRegister);
+                }
+
                 if data & 0x80 != 0 {
                     self.reset();
                     return Ok(MapWrite::WroteRegister);
@@ -187,4 +206,99 @@ impl Mapper for Mapper1 {
             _ => unreachable!(),
         })
     }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        self.cycles_since_load_write = self.cycles_since_load_write.saturating_add(1);
+        false
+    }
+
+    fn prg_bank_map(&self) -> Vec<(u16, usize)> {
+        match self.control.prg_rom_bank_mode() {
+            // Banks are numbered in 32 KB units
+            0 | 1 => vec![(0x8000, (self.prg_bank >> 1) as usize)],
+            2 => vec![(0x8000, 0), (0xC000, self.prg_bank as usize)],
+            _ => vec![
+                (0x8000, self.prg_bank as usize),
+                (0xC000, (self.prg_bank_count - 1) as usize),
+            ],
+        }
+    }
+
+    fn chr_bank_map(&self) -> Vec<(u16, usize)> {
+        match self.control.chr_bank_mode() {
+            // Banks are numbered in 8 KB units
+            0 => vec![(0x0000, (self.chr_bank0 >> 1) as usize)],
+            _ => vec![
+                (0x0000, self.chr_bank0 as usize),
+                (0x1000, self.chr_bank1 as usize),
+            ],
+        }
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes to the serial port with enough CPU cycles in between for each to be seen.
+    fn write_serial(mapper: &mut Mapper1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.on_cpu_cycle();
+            mapper.on_cpu_cycle();
+            mapper.map_prg_write(addr, (value >> i) & 0x01).unwrap();
+        }
+    }
+
+    #[test]
+    fn reset_forces_prg_mode_3() {
+        let mut mapper = Mapper1::new(8, 0);
+        write_serial(&mut mapper, 0x8000, 0b00010);
+        assert_eq!(mapper.control.prg_rom_bank_mode(), 0);
+
+        mapper.on_cpu_cycle();
+        mapper.on_cpu_cycle();
+        mapper.map_prg_write(0x8000, 0x80).unwrap();
+        assert_eq!(mapper.control.prg_rom_bank_mode(), 3);
+        // The rest of the control register is untouched
+        assert_eq!(mapper.control.mirroring(), 2);
+        assert_eq!(mapper.load_write_count, 0);
+    }
+
+    #[test]
+    fn bank_maps() {
+        let mut mapper = Mapper1::new(8, 4);
+        write_serial(&mut mapper, 0xE000, 0b00101);
+        assert_eq!(mapper.prg_bank_map(), [(0x8000, 5), (0xC000, 7)]);
+
+        // 32 KB PRG, 4 KB CHR
+        write_serial(&mut mapper, 0x8000, 0b10000);
+        write_serial(&mut mapper, 0xA000, 0b00011);
+        write_serial(&mut mapper, 0xC000, 0b00110);
+        assert_eq!(mapper.prg_bank_map(), [(0x8000, 2)]);
+        assert_eq!(mapper.chr_bank_map(), [(0x0000, 3), (0x1000, 6)]);
+    }
+
+    #[test]
+    fn consecutive_cycle_writes_ignored() {
+        let mut mapper = Mapper1::new(8, 0);
+        write_serial(&mut mapper, 0xE000, 0b00011);
+        assert_eq!(mapper.prg_bank, 3);
+
+        // Write 1 then immediately write 0 on the next cycle, like an RMW instruction
+        mapper.on_cpu_cycle();
+        mapper.on_cpu_cycle();
+        mapper.map_prg_write(0x8000, 0x01).unwrap();
+        mapper.on_cpu_cycle();
+        mapper.map_prg_write(0x8000, 0x00).unwrap();
+        assert_eq!(mapper.load_write_count, 1);
+        assert_eq!(mapper.load, 0x10);
+    }
 }
@@ -0,0 +1,134 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{
+    mapper9::{chr_read, mirroring, ChrLatches},
+    MapRead, MapWrite, Mapper,
+};
+use anyhow::{anyhow, Result};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 16 * 1024;
+
+/// Nintendo MMC4, used by Fire Emblem and Famicom Wars. It's MMC2 with a 16 KB switchable
+/// PRG bank, PRG RAM, and latches that are set by any row of tiles $FD and $FE.
+/// See: https://www.nesdev.org/wiki/MMC4
+pub struct Mapper10 {
+    prg_bank_count: usize,
+    chr_bank_count: usize,
+
+    prg_bank: u8,
+    latches: ChrLatches,
+    mirroring: Mirroring,
+
+    prg_ram: [u8; PRG_RAM_SIZE],
+}
+
+impl Mapper10 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_bank_count: (prg_banks as usize).max(1),
+            chr_bank_count: (chr_banks as usize * 2).max(1),
+
+            prg_bank: 0,
+            latches: ChrLatches::new(false),
+            mirroring: Mirroring::Vertical,
+
+            prg_ram: [0; PRG_RAM_SIZE],
+        }
+    }
+}
+
+impl Mapper for Mapper10 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        let bank = match addr {
+            0x6000..=0x7FFF => return Ok(MapRead::RAMData(self.prg_ram[(addr - 0x6000) as usize])),
+            0x8000..=0xBFFF => self.prg_bank as usize % self.prg_bank_count,
+            0xC000..=0xFFFF => self.prg_bank_count - 1,
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        };
+
+        Ok(MapRead::Address(
+            bank * PRG_BANK_SIZE + (addr & 0x3FFF) as usize,
+        ))
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x6000..=0x7FFF => {
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+                return Ok(MapWrite::RAMWritten);
+            }
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xEFFF => self
+                .latches
+                .write_bank((addr - 0xB000) as usize >> 12, data),
+            0xF000..=0xFFFF => self.mirroring = mirroring(data),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+
+        Ok(MapWrite::WroteRegister)
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        chr_read(&self.latches, self.chr_bank_count, addr)
+    }
+
+    fn map_chr_write(&self, _addr: u16) -> Result<MapWrite> {
+        Err(anyhow!("Can't write to ROM"))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_ppu_address(&mut self, addr: u16) {
+        self.latches.on_ppu_address(addr);
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_bank_map(&self) -> Vec<(u16, usize)> {
+        super::bank_map(0x8000..=0xFFFF, PRG_BANK_SIZE, |addr| {
+            self.map_prg_read(addr)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::mapper9::CHR_BANK_SIZE;
+
+    #[test]
+    fn prg_banks() {
+        let mut mapper = Mapper10::new(8, 16);
+        mapper.map_prg_write(0xA000, 3).unwrap();
+        assert_eq!(mapper.prg_bank_map(), vec![(0x8000, 3), (0xC000, 7)]);
+
+        mapper.map_prg_write(0x6010, 0x42).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x6010).unwrap(),
+            MapRead::RAMData(0x42)
+        ));
+    }
+
+    #[test]
+    fn chr_latch_set_by_any_row() {
+        let mut mapper = Mapper10::new(8, 16);
+        mapper.map_prg_write(0xB000, 1).unwrap();
+        mapper.map_prg_write(0xC000, 2).unwrap();
+
+        mapper.on_ppu_address(0x0FDD);
+        mapper.on_ppu_address(0x0000);
+        match mapper.map_chr_read(0x0123).unwrap() {
+            MapRead::Address(addr) => assert_eq!(addr, CHR_BANK_SIZE + 0x123),
+            MapRead::RAMData(_) => panic!("Expected a CHR address"),
+        }
+    }
+}
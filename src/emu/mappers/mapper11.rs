@@ -0,0 +1,96 @@
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// Color Dreams, used by many unlicensed games.
+/// See: https://www.nesdev.org/wiki/Color_Dreams
+pub struct Mapper11 {
+    prg_banks: u16,
+    chr_banks: u16,
+    prg_bank: u8,
+    chr_bank: u8,
+}
+
+impl Mapper11 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_banks,
+            chr_banks,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper11 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x8000..=0xFFFF => {
+                // prg_banks is in 16 KB units, but the board switches 32 KB at a time
+                let bank_count = (self.prg_banks as usize / 2).max(1);
+                let bank = self.prg_bank as usize % bank_count;
+                Ok(MapRead::Address(
+                    bank * PRG_BANK_SIZE + (addr & 0x7FFF) as usize,
+                ))
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.prg_bank = data & 0x03;
+                self.chr_bank = data >> 4;
+                Ok(MapWrite::WroteRegister)
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        let bank = self.chr_bank as usize % (self.chr_banks as usize).max(1);
+        Ok(MapRead::Address(bank * CHR_BANK_SIZE + addr as usize))
+    }
+
+    fn map_chr_write(&self, _addr: u16) -> Result<MapWrite> {
+        Err(anyhow!("Can't write to ROM"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bank_switching() {
+        let mut mapper = Mapper11::new(8, 16);
+        mapper.map_prg_write(0x8000, 0x52).unwrap();
+
+        assert!(matches!(
+            mapper.map_prg_read(0x8123),
+            Ok(MapRead::Address(addr)) if addr == 2 * PRG_BANK_SIZE + 0x123
+        ));
+        assert!(matches!(
+            mapper.map_chr_read(0x1000),
+            Ok(MapRead::Address(addr)) if addr == 5 * CHR_BANK_SIZE + 0x1000
+        ));
+
+        // Banks past the end of the ROM wrap around
+        mapper.map_prg_write(0xFFFF, 0xF3).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x8000),
+            Ok(MapRead::Address(addr)) if addr == 3 * PRG_BANK_SIZE
+        ));
+        assert!(matches!(
+            mapper.map_chr_read(0x0000),
+            Ok(MapRead::Address(addr)) if addr == 15 * CHR_BANK_SIZE
+        ));
+    }
+}
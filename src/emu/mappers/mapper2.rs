@@ -2,15 +2,15 @@ use super::{MapRead, MapWrite, Mapper};
 use anyhow::{anyhow, Result};
 
 pub struct Mapper2 {
-    prg_banks: u8,
-    chr_banks: u8,
+    prg_banks: u16,
+    chr_banks: u16,
     bank_select: u8,
 }
 
 const BANK_SIZE: usize = 16 * 1024;
 
 impl Mapper2 {
-    pub fn new(prg_banks: u8, chr_banks: u8) -> Self {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
         Self {
             prg_banks,
             chr_banks,
@@ -0,0 +1,519 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// Konami VRC6, along with its expansion audio.
+/// Mapper 26 is the same board with the A0 and A1 address lines swapped.
+/// Only the 1 KB CHR banking mode is emulated, which is the only one used by released games.
+/// See: https://www.nesdev.org/wiki/VRC6
+pub struct Mapper24 {
+    prg_bank_count: usize,
+    chr_bank_count: usize,
+    chr_ram: bool,
+    swap_address_lines: bool,
+
+    // 16 KB bank at $8000, in 8 KB units
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+
+    irq: VrcIrq,
+
+    prg_ram: [u8; PRG_RAM_SIZE],
+    audio: Vrc6Audio,
+}
+
+impl Mapper24 {
+    pub fn new(prg_banks: u16, chr_banks: u16, swap_address_lines: bool) -> Self {
+        Self {
+            prg_bank_count: (prg_banks as usize * 2).max(1),
+            // CHR RAM boards have a single 8 KB bank
+            chr_bank_count: (chr_banks as usize).max(1) * 8,
+            chr_ram: chr_banks == 0,
+            swap_address_lines,
+
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+
+            irq: VrcIrq::default(),
+
+            prg_ram: [0; PRG_RAM_SIZE],
+            audio: Vrc6Audio::default(),
+        }
+    }
+
+    /// The register an address selects, as $X000-$X003.
+    fn register(&self, addr: u16) -> u16 {
+        let addr = addr & 0xF003;
+        if self.swap_address_lines {
+            (addr & 0xF000) | ((addr & 0x01) << 1) | ((addr & 0x02) >> 1)
+        } else {
+            addr
+        }
+    }
+
+    fn write_banking_style(&mut self, data: u8) {
+        self.mirroring = match (data >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            3 => Mirroring::SingleScreenUpper,
+            _ => unreachable!(),
+        };
+        self.prg_ram_enabled = data & 0x80 != 0;
+    }
+
+    fn prg_rom_addr(&self, bank: usize, addr: u16) -> usize {
+        let bank = bank % self.prg_bank_count;
+        bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        let addr = match addr {
+            0x6000..=0x7FFF => {
+                if !self.prg_ram_enabled {
+                    return Err(anyhow!("PRG RAM is disabled"));
+                }
+                return Ok(MapRead::RAMData(self.prg_ram[(addr - 0x6000) as usize]));
+            }
+            0x8000..=0xBFFF => {
+                let bank = self.prg_bank_16k as usize * 2 + ((addr - 0x8000) / 0x2000) as usize;
+                self.prg_rom_addr(bank, addr)
+            }
+            0xC000..=0xDFFF => self.prg_rom_addr(self.prg_bank_8k as usize, addr),
+            // Fixed to the last bank
+            0xE000..=0xFFFF => self.prg_rom_addr(self.prg_bank_count - 1, addr),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        };
+
+        Ok(MapRead::Address(addr))
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        if let 0x6000..=0x7FFF = addr {
+            if !self.prg_ram_enabled {
+                return Err(anyhow!("PRG RAM is disabled"));
+            }
+            self.prg_ram[(addr - 0x6000) as usize] = data;
+            return Ok(MapWrite::RAMWritten);
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_bank_16k = data & 0x0F,
+            reg @ (0x9000..=0x9003 | 0xA000..=0xA003 | 0xB000..=0xB002) => {
+                self.audio.write(reg, data)
+            }
+            0xB003 => self.write_banking_style(data),
+            0xC000..=0xC003 => self.prg_bank_8k = data & 0x1F,
+            reg @ (0xD000..=0xD003 | 0xE000..=0xE003) => {
+                let window = ((reg - 0xD000) >> 12) * 4 + (reg & 0x03);
+                self.chr_banks[window as usize] = data;
+            }
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+
+        Ok(MapWrite::WroteRegister)
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        let window = (addr / 0x0400) as usize;
+        let bank = self.chr_banks[window] as usize % self.chr_bank_count;
+        Ok(MapRead::Address(
+            bank * CHR_BANK_SIZE + (addr & 0x03FF) as usize,
+        ))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if !self.chr_ram {
+            return Err(anyhow!("Can't write to ROM"));
+        }
+
+        match self.map_chr_read(addr)? {
+            MapRead::Address(addr) => Ok(MapWrite::Address(addr)),
+            MapRead::RAMData(_) => unreachable!(),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        self.audio.clock();
+        self.irq.clock()
+    }
+
+    fn audio_sample(&self) -> f32 {
+        self.audio.sample()
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+/// The IRQ counter shared by Konami's VRC chips, which counts either CPU cycles or scanlines.
+/// Scanlines are approximated with a prescaler, since the chip can't see the PPU.
+/// See: https://www.nesdev.org/wiki/VRC_IRQ
+#[derive(Debug, Default)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    // Whether to enable the IRQ again when it's acknowledged
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0x01 != 0;
+        self.enabled = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Returns whether the IRQ line is asserted.
+    fn clock(&mut self) -> bool {
+        if self.enabled {
+            if self.cycle_mode {
+                self.clock_counter();
+            } else {
+                // 341 PPU clocks per scanline, 3 per CPU cycle
+                self.prescaler -= 3;
+                if self.prescaler <= 0 {
+                    self.prescaler += 341;
+                    self.clock_counter();
+                }
+            }
+        }
+
+        self.pending
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+/// Two pulse channels and a sawtooth channel.
+/// See: https://www.nesdev.org/wiki/VRC6_audio
+#[derive(Debug, Default)]
+struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halted: bool,
+    // Divides every period by 16 or 256, for the test mode in $9003
+    period_shift: u8,
+}
+
+impl Vrc6Audio {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0x9003 => {
+                self.halted = data & 0x01 != 0;
+                self.period_shift = if data & 0x04 != 0 {
+                    8
+                } else if data & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000..=0x9002 => self.pulses[0].write(register & 0x03, data),
+            0xA000..=0xA002 => self.pulses[1].write(register & 0x03, data),
+            0xB000..=0xB002 => self.saw.write(register & 0x03, data),
+            // Not connected to anything
+            0xA003 => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.halted {
+            return;
+        }
+        for pulse in &mut self.pulses {
+            pulse.clock(self.period_shift);
+        }
+        self.saw.clock(self.period_shift);
+    }
+
+    fn sample(&self) -> f32 {
+        let total = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        // A full volume pulse is about as loud as one of the APU's pulse channels
+        total as f32 * 0.01
+    }
+}
+
+#[derive(Debug, Default)]
+struct Vrc6Timer {
+    period: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Vrc6Timer {
+    fn write_low(&mut self, data: u8) {
+        self.period = (self.period & 0x0F00) | data as u16;
+    }
+
+    fn write_high(&mut self, data: u8) {
+        self.period = (self.period & 0x00FF) | (((data & 0x0F) as u16) << 8);
+        self.enabled = data & 0x80 != 0;
+    }
+
+    /// Returns whether the timer reached the end of its period.
+    fn clock(&mut self, shift: u8) -> bool {
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Vrc6Pulse {
+    timer: Vrc6Timer,
+    volume: u8,
+    duty: u8,
+    // Ignores the duty cycle and outputs the volume constantly
+    digitized: bool,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.volume = data & 0x0F;
+                self.duty = (data >> 4) & 0x07;
+                self.digitized = data & 0x80 != 0;
+            }
+            1 => self.timer.write_low(data),
+            2 => {
+                self.timer.write_high(data);
+                if !self.timer.enabled {
+                    self.step = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if self.timer.enabled && self.timer.clock(shift) {
+            self.step = (self.step + 1) % 16;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.timer.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Vrc6Saw {
+    timer: Vrc6Timer,
+    rate: u8,
+    accumulator: u8,
+    step: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0x3F,
+            1 => self.timer.write_low(data),
+            2 => {
+                self.timer.write_high(data);
+                if !self.timer.enabled {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The accumulator adds the rate on every other step, and resets after 7 additions.
+    fn clock(&mut self, shift: u8) {
+        if !(self.timer.enabled && self.timer.clock(shift)) {
+            return;
+        }
+
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::{chr_addr, prg_addr};
+
+    #[test]
+    fn prg_bank_switching() {
+        let mut mapper = Mapper24::new(16, 16, false);
+        // Last bank is fixed
+        assert_eq!(prg_addr(&mapper, 0xE000), 31 * PRG_BANK_SIZE);
+
+        mapper.map_prg_write(0x8000, 3).unwrap();
+        mapper.map_prg_write(0xC000, 9).unwrap();
+        assert_eq!(prg_addr(&mapper, 0x8123), 6 * PRG_BANK_SIZE + 0x123);
+        assert_eq!(prg_addr(&mapper, 0xBFFF), 8 * PRG_BANK_SIZE - 1);
+        assert_eq!(prg_addr(&mapper, 0xC000), 9 * PRG_BANK_SIZE);
+    }
+
+    #[test]
+    fn chr_banks_and_swapped_lines() {
+        let mut mapper24 = Mapper24::new(16, 16, false);
+        let mut mapper26 = Mapper24::new(16, 16, true);
+        for mapper in [&mut mapper24, &mut mapper26] {
+            mapper.map_prg_write(0xD000, 10).unwrap();
+            mapper.map_prg_write(0xD001, 11).unwrap();
+            mapper.map_prg_write(0xE003, 20).unwrap();
+        }
+
+        assert_eq!(chr_addr(&mapper24, 0x0010), 10 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(chr_addr(&mapper24, 0x0400), 11 * CHR_BANK_SIZE);
+        assert_eq!(chr_addr(&mapper24, 0x1FFF), 21 * CHR_BANK_SIZE - 1);
+
+        // $D001 is $D002 on mapper 26
+        assert_eq!(chr_addr(&mapper26, 0x0400), 0);
+        assert_eq!(chr_addr(&mapper26, 0x0800), 11 * CHR_BANK_SIZE);
+        assert_eq!(chr_addr(&mapper26, 0x1FFF), 21 * CHR_BANK_SIZE - 1);
+    }
+
+    #[test]
+    fn banking_style() {
+        let mut mapper = Mapper24::new(16, 16, false);
+        assert!(mapper.map_prg_write(0x6000, 0x42).is_err());
+
+        mapper.map_prg_write(0xB003, 0x84).unwrap();
+        assert!(matches!(mapper.mirroring(), Some(Mirroring::Horizontal)));
+        mapper.map_prg_write(0x6000, 0x42).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x6000),
+            Ok(MapRead::RAMData(0x42))
+        ));
+    }
+
+    #[test]
+    fn irq_cycle_mode() {
+        let mut mapper = Mapper24::new(16, 16, false);
+        mapper.map_prg_write(0xF000, 0xFD).unwrap();
+        mapper.map_prg_write(0xF001, 0x07).unwrap();
+
+        // Fires when the counter overflows from $FF, and reloads from the latch
+        assert!(!mapper.on_cpu_cycle());
+        assert!(!mapper.on_cpu_cycle());
+        assert!(mapper.on_cpu_cycle());
+        assert_eq!(mapper.irq.counter, 0xFD);
+
+        // Acknowledging copies the enable-after-acknowledge bit
+        mapper.map_prg_write(0xF002, 0).unwrap();
+        assert!(!mapper.on_cpu_cycle());
+        assert!(mapper.irq.enabled);
+    }
+
+    #[test]
+    fn irq_scanline_mode() {
+        let mut mapper = Mapper24::new(16, 16, false);
+        mapper.map_prg_write(0xF000, 0xFE).unwrap();
+        mapper.map_prg_write(0xF001, 0x02).unwrap();
+
+        // Two scanlines of 113 2/3 CPU cycles
+        let cycles = (0..300).position(|_| mapper.on_cpu_cycle()).unwrap();
+        assert_eq!(cycles, 227);
+    }
+
+    #[test]
+    fn audio_channels() {
+        let mut mapper = Mapper24::new(16, 16, false);
+        assert_eq!(mapper.audio_sample(), 0.0);
+
+        // Pulse 1 at full volume with a 50% duty cycle and a period of 1
+        mapper.map_prg_write(0x9000, 0x7F).unwrap();
+        mapper.map_prg_write(0x9001, 0x01).unwrap();
+        mapper.map_prg_write(0x9002, 0x80).unwrap();
+        assert!((mapper.audio_sample() - 0.15).abs() < 1e-6);
+
+        // High for 8 of the 16 steps
+        let high = (0..32)
+            .filter(|_| {
+                mapper.on_cpu_cycle();
+                mapper.audio_sample() > 0.0
+            })
+            .count();
+        assert_eq!(high, 16);
+
+        // Sawtooth rises by the rate every other step
+        mapper.map_prg_write(0x9002, 0x00).unwrap();
+        mapper.map_prg_write(0xB000, 0x10).unwrap();
+        mapper.map_prg_write(0xB002, 0x80).unwrap();
+        mapper.on_cpu_cycle();
+        mapper.on_cpu_cycle();
+        assert_eq!(mapper.audio.saw.accumulator, 0x10);
+    }
+}
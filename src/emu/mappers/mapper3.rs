@@ -2,19 +2,21 @@ use super::{MapRead, MapWrite, Mapper};
 use anyhow::{anyhow, Result};
 
 pub struct Mapper3 {
-    prg_banks: u8,
-    chr_banks: u8,
+    prg_banks: u16,
+    chr_banks: u16,
     bank_select: u8,
+    bus_conflicts: bool,
 }
 
 const BANK_SIZE: usize = 8 * 1024;
 
 impl Mapper3 {
-    pub fn new(prg_banks: u8, chr_banks: u8) -> Self {
+    pub fn new(prg_banks: u16, chr_banks: u16, bus_conflicts: bool) -> Self {
         Self {
             prg_banks,
             chr_banks,
             bank_select: 0,
+            bus_conflicts,
         }
     }
 }
@@ -65,4 +67,8 @@ impl Mapper for Mapper3 {
 
         Ok(MapWrite::Address(addr as usize))
     }
+
+    fn has_bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
 }
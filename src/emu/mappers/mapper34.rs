@@ -0,0 +1,91 @@
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const BANK_SIZE: usize = 32 * 1024;
+
+/// BNROM, submapper 2, which switches 32 KB of PRG ROM and has 8 KB of CHR RAM.
+/// TODO: NINA-001 (submapper 1) shares the mapper number, but has its registers at $7FFD-$7FFF
+/// and banks CHR ROM in 4 KB units.
+/// See: https://www.nesdev.org/wiki/INES_Mapper_034
+pub struct Mapper34 {
+    prg_banks: u16,
+    bank_select: u8,
+}
+
+impl Mapper34 {
+    pub fn new(prg_banks: u16) -> Self {
+        Self {
+            prg_banks,
+            bank_select: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x8000..=0xFFFF => {
+                // prg_banks is in 16 KB units, but BNROM switches 32 KB at a time
+                let bank_count = (self.prg_banks as usize / 2).max(1);
+                let bank = self.bank_select as usize % bank_count;
+                Ok(MapRead::Address(
+                    bank * BANK_SIZE + (addr & 0x7FFF) as usize,
+                ))
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.bank_select = data;
+                Ok(MapWrite::WroteRegister)
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        Ok(MapRead::Address(addr as usize))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        Ok(MapWrite::Address(addr as usize))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prg_bank_switching() {
+        let mut mapper = Mapper34::new(8);
+        assert!(matches!(
+            mapper.map_prg_read(0xFFFF),
+            Ok(MapRead::Address(0x7FFF))
+        ));
+
+        mapper.map_prg_write(0x8000, 3).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0xC123),
+            Ok(MapRead::Address(addr)) if addr == 3 * BANK_SIZE + 0x4123
+        ));
+
+        // Only as many banks as the ROM has
+        mapper.map_prg_write(0x8000, 5).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x8000),
+            Ok(MapRead::Address(addr)) if addr == BANK_SIZE
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+// A rise of PPU A12 is only seen once it has been low for this many CPU cycles,
+// which filters out the short drops between the sprite pattern fetches
+const A12_FILTER_CYCLES: u8 = 3;
+
+/// Nintendo MMC3, the mapper with a scanline counter clocked by PPU A12.
+/// The IRQ behaves like the newer MMC3 revisions, where a latch of 0 fires every scanline.
+/// See: https://www.nesdev.org/wiki/MMC3
+pub struct Mapper4 {
+    prg_bank_count: usize,
+    chr_bank_count: usize,
+    chr_ram: bool,
+
+    // R0-R7, selected by the bank select register
+    bank_select: u8,
+    banks: [u8; 8],
+    // Swaps $8000 with $C000, and the two halves of the pattern tables
+    prg_mode: bool,
+    chr_inversion: bool,
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+    a12_low_cycles: u8,
+
+    prg_ram: [u8; PRG_RAM_SIZE],
+}
+
+impl Mapper4 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_bank_count: (prg_banks as usize * 2).max(2),
+            // CHR RAM boards have a single 8 KB bank
+            chr_bank_count: (chr_banks as usize).max(1) * 8,
+            chr_ram: chr_banks == 0,
+
+            bank_select: 0,
+            banks: [0; 8],
+            prg_mode: false,
+            chr_inversion: false,
+            mirroring: Mirroring::Vertical,
+            // Enabled until the game says otherwise, since some never write $A001
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
+
+            prg_ram: [0; PRG_RAM_SIZE],
+        }
+    }
+
+    fn prg_rom_addr(&self, addr: u16) -> usize {
+        let second_last = self.prg_bank_count - 2;
+        let bank = match (addr, self.prg_mode) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.banks[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.banks[7] as usize,
+            _ => self.prg_bank_count - 1,
+        };
+
+        (bank % self.prg_bank_count) * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        // Inverting swaps the 2 KB banks at $0000 with the 1 KB banks at $1000
+        let addr = if self.chr_inversion {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+
+        let bank = match addr {
+            // R0 and R1 select 2 KB banks, ignoring the low bit
+            0x0000..=0x07FF => (self.banks[0] & 0xFE) as usize + (addr as usize >> 10 & 1),
+            0x0800..=0x0FFF => (self.banks[1] & 0xFE) as usize + (addr as usize >> 10 & 1),
+            _ => self.banks[2 + ((addr - 0x1000) / 0x0400) as usize] as usize,
+        };
+
+        (bank % self.chr_bank_count) * CHR_BANK_SIZE + (addr & 0x03FF) as usize
+    }
+
+    /// Called on each filtered rise of A12, which is once per scanline while rendering.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mapper4 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if !self.prg_ram_enabled {
+                    return Err(anyhow!("PRG RAM is disabled"));
+                }
+                Ok(MapRead::RAMData(self.prg_ram[(addr - 0x6000) as usize]))
+            }
+            0x8000..=0xFFFF => Ok(MapRead::Address(self.prg_rom_addr(addr))),
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        // Each register is a pair, told apart by whether the address is even or odd
+        let odd = addr & 0x01 != 0;
+        match addr {
+            0x6000..=0x7FFF => {
+                if !self.prg_ram_enabled || self.prg_ram_write_protected {
+                    return Err(anyhow!("PRG RAM is not writable"));
+                }
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+                return Ok(MapWrite::RAMWritten);
+            }
+            0x8000..=0x9FFF if !odd => {
+                self.bank_select = data & 0x07;
+                self.prg_mode = data & 0x40 != 0;
+                self.chr_inversion = data & 0x80 != 0;
+            }
+            0x8000..=0x9FFF => self.banks[self.bank_select as usize] = data,
+            0xA000..=0xBFFF if !odd => {
+                self.mirroring = if data & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protected = data & 0x40 != 0;
+            }
+            0xC000..=0xDFFF if !odd => self.irq_latch = data,
+            // Clears the counter, so it's reloaded from the latch on the next clock
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // Disabling also acknowledges a pending IRQ
+            0xE000..=0xFFFF if !odd => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+
+        Ok(MapWrite::WroteRegister)
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        Ok(MapRead::Address(self.chr_addr(addr)))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if !self.chr_ram {
+            return Err(anyhow!("Can't write to ROM"));
+        }
+
+        match self.map_chr_read(addr)? {
+            MapRead::Address(addr) => Ok(MapWrite::Address(addr)),
+            MapRead::RAMData(_) => unreachable!(),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+
+        self.irq_pending
+    }
+
+    fn on_ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low_cycles >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::{chr_addr, prg_addr};
+
+    fn set_bank(mapper: &mut Mapper4, mode: u8, register: u8, bank: u8) {
+        mapper.map_prg_write(0x8000, mode | register).unwrap();
+        mapper.map_prg_write(0x8001, bank).unwrap();
+    }
+
+    /// A12 rises after being low long enough, like it does once per scanline.
+    fn scanline(mapper: &mut Mapper4) -> bool {
+        mapper.on_ppu_address(0x0000);
+        for _ in 0..A12_FILTER_CYCLES {
+            mapper.on_cpu_cycle();
+        }
+        mapper.on_ppu_address(0x1000);
+        mapper.on_cpu_cycle()
+    }
+
+    #[test]
+    fn prg_bank_switching() {
+        let mut mapper = Mapper4::new(8, 8);
+        set_bank(&mut mapper, 0x00, 6, 3);
+        set_bank(&mut mapper, 0x00, 7, 4);
+        assert_eq!(prg_addr(&mapper, 0x8123), 3 * PRG_BANK_SIZE + 0x123);
+        assert_eq!(prg_addr(&mapper, 0xA000), 4 * PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xC000), 14 * PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xFFFF), 16 * PRG_BANK_SIZE - 1);
+
+        // Swaps $8000 and $C000
+        mapper.map_prg_write(0x8000, 0x40).unwrap();
+        assert_eq!(prg_addr(&mapper, 0x8000), 14 * PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xC000), 3 * PRG_BANK_SIZE);
+    }
+
+    #[test]
+    fn chr_bank_switching() {
+        let mut mapper = Mapper4::new(8, 8);
+        set_bank(&mut mapper, 0x00, 0, 9);
+        set_bank(&mut mapper, 0x00, 5, 20);
+        // The low bit of the 2 KB banks is ignored
+        assert_eq!(chr_addr(&mapper, 0x0010), 8 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(chr_addr(&mapper, 0x0400), 9 * CHR_BANK_SIZE);
+        assert_eq!(chr_addr(&mapper, 0x1FFF), 21 * CHR_BANK_SIZE - 1);
+
+        mapper.map_prg_write(0x8000, 0x80).unwrap();
+        assert_eq!(chr_addr(&mapper, 0x1010), 8 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(chr_addr(&mapper, 0x0C00), 20 * CHR_BANK_SIZE);
+        assert!(mapper.map_chr_write(0x0000).is_err());
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut mapper = Mapper4::new(8, 8);
+        mapper.map_prg_write(0xC000, 2).unwrap();
+        mapper.map_prg_write(0xC001, 0).unwrap();
+        mapper.map_prg_write(0xE001, 0).unwrap();
+
+        // Reloaded to 2, then counts down to 0
+        assert!(!scanline(&mut mapper));
+        assert!(!scanline(&mut mapper));
+        assert!(scanline(&mut mapper));
+
+        mapper.map_prg_write(0xE000, 0).unwrap();
+        assert!(!mapper.on_cpu_cycle());
+
+        // A latch of 0 fires on every scanline once enabled
+        mapper.map_prg_write(0xC000, 0).unwrap();
+        mapper.map_prg_write(0xC001, 0).unwrap();
+        mapper.map_prg_write(0xE001, 0).unwrap();
+        for _ in 0..3 {
+            assert!(scanline(&mut mapper));
+            mapper.map_prg_write(0xE000, 0).unwrap();
+            mapper.map_prg_write(0xE001, 0).unwrap();
+        }
+    }
+
+    #[test]
+    fn a12_filtering() {
+        let mut mapper = Mapper4::new(8, 8);
+        scanline(&mut mapper);
+        assert_eq!(mapper.irq_counter, 0);
+        mapper.map_prg_write(0xC000, 5).unwrap();
+        mapper.map_prg_write(0xC001, 0).unwrap();
+
+        // Rises after only a short drop aren't counted
+        for _ in 0..8 {
+            mapper.on_ppu_address(0x2000);
+            mapper.on_cpu_cycle();
+            mapper.on_ppu_address(0x1000);
+        }
+        assert_eq!(mapper.irq_counter, 0);
+
+        scanline(&mut mapper);
+        assert_eq!(mapper.irq_counter, 5);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const PRG_RAM_SIZE: usize = 64 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const EXRAM_SIZE: usize = 1024;
+
+/// MMC5
+/// See: https://www.nesdev.org/wiki/MMC5
+///
+/// Only enough is emulated for the common games to boot:
+/// PRG banking in all 4 modes, PRG RAM, CHR banking using the $5120-$5127 registers,
+/// ExRAM as plain CPU RAM, the multiplier, nametable mapping through $5105
+/// and the scanline IRQ ($5203/$5204).
+///
+/// Not emulated yet:
+/// - Separate background CHR banks for 8x16 sprites ($5128-$512B) and upper CHR bits ($5130)
+/// - ExRAM as a nametable or extended attributes, and fill mode nametables,
+///   which read as 0 and ignore writes for now
+/// - Vertical split mode ($5200-$5202)
+/// - Expansion audio ($5000-$5015)
+pub struct Mapper5 {
+    prg_banks: u16,
+    chr_banks: u16,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    nametable_mapping: u8,
+
+    // $5113-$5117
+    prg_bank_regs: [u8; 5],
+    // $5120-$5127
+    chr_bank_regs: [u16; 8],
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// Set while the PPU is rendering, from the first scanline until the NMI
+    in_frame: bool,
+    scanline_counter: u8,
+
+    prg_ram: Vec<u8>,
+    exram: [u8; EXRAM_SIZE],
+}
+
+impl Mapper5 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_banks,
+            chr_banks,
+
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            nametable_mapping: 0,
+
+            prg_bank_regs: [0, 0, 0, 0, 0xFF],
+            chr_bank_regs: [0; 8],
+
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            exram: [0; EXRAM_SIZE],
+        }
+    }
+
+    /// Finds the bank register and window size in 8 KB units for a PRG ROM address.
+    fn prg_window(&self, addr: u16) -> (u8, usize) {
+        let regs = &self.prg_bank_regs;
+        match (self.prg_mode, addr) {
+            (0, _) => (regs[4], 4),
+            (1, 0x8000..=0xBFFF) => (regs[2], 2),
+            (1, _) => (regs[4], 2),
+            (2, 0x8000..=0xBFFF) => (regs[2], 2),
+            (2, 0xC000..=0xDFFF) => (regs[3], 1),
+            (2, _) => (regs[4], 1),
+            (_, 0x8000..=0x9FFF) => (regs[1], 1),
+            (_, 0xA000..=0xBFFF) => (regs[2], 1),
+            (_, 0xC000..=0xDFFF) => (regs[3], 1),
+            (_, _) => (regs[4], 1),
+        }
+    }
+
+    /// Returns whether the address is in ROM, and its offset into PRG ROM or RAM.
+    fn map_prg_window(&self, addr: u16) -> (bool, usize) {
+        let (reg, size) = self.prg_window(addr);
+        // $E000-$FFFF is always ROM
+        let is_rom = reg & 0x80 != 0 || addr >= 0xE000;
+
+        let bank = (reg & 0x7F) as usize & !(size - 1);
+        let offset = addr as usize & (size * PRG_BANK_SIZE - 1);
+        (is_rom, bank * PRG_BANK_SIZE + offset)
+    }
+
+    /// Where a nametable comes from according to $5105: 0 and 1 are the console's own,
+    /// 2 is ExRAM and 3 is fill mode.
+    fn nametable_source(&self, nametable: usize) -> u8 {
+        (self.nametable_mapping >> (nametable * 2)) & 0x03
+    }
+
+    /// Whether a PPU address is in a nametable that isn't one of the console's.
+    fn is_mapper_nametable(&self, addr: u16) -> bool {
+        (0x2000..=0x3EFF).contains(&addr)
+            && self.nametable_source((addr as usize & 0x0FFF) / 0x0400) >= 2
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn prg_ram_addr(&self, bank: u8, addr: u16) -> usize {
+        (bank as usize * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % PRG_RAM_SIZE
+    }
+
+    fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6),
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF => self.exram[(addr - 0x5C00) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.prg_ram_protect[0] = data & 0x03,
+            0x5103 => self.prg_ram_protect[1] = data & 0x03,
+            0x5105 => self.nametable_mapping = data,
+            0x5113..=0x5117 => self.prg_bank_regs[(addr - 0x5113) as usize] = data,
+            0x5120..=0x5127 => self.chr_bank_regs[(addr - 0x5120) as usize] = data as u16,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF => self.exram[(addr - 0x5C00) as usize] = data,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper5 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x5000..=0x5FFF => Ok(MapRead::RAMData(self.read_register(addr))),
+            0x6000..=0x7FFF => {
+                let addr = self.prg_ram_addr(self.prg_bank_regs[0] & 0x07, addr);
+                Ok(MapRead::RAMData(self.prg_ram[addr]))
+            }
+            0x8000..=0xFFFF => {
+                let (is_rom, offset) = self.map_prg_window(addr);
+                if is_rom {
+                    let rom_size = self.prg_banks.max(1) as usize * 2 * PRG_BANK_SIZE;
+                    Ok(MapRead::Address(offset % rom_size))
+                } else {
+                    Ok(MapRead::RAMData(self.prg_ram[offset % PRG_RAM_SIZE]))
+                }
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x5000..=0x5FFF => {
+                self.write_register(addr, data);
+                Ok(MapWrite::WroteRegister)
+            }
+            0x6000..=0x7FFF => {
+                if self.prg_ram_writable() {
+                    let addr = self.prg_ram_addr(self.prg_bank_regs[0] & 0x07, addr);
+                    self.prg_ram[addr] = data;
+                }
+                Ok(MapWrite::RAMWritten)
+            }
+            0x8000..=0xFFFF => match self.map_prg_window(addr) {
+                (true, _) => Err(anyhow!("Can't write to ROM")),
+                (false, offset) => {
+                    if self.prg_ram_writable() {
+                        self.prg_ram[offset % PRG_RAM_SIZE] = data;
+                    }
+                    Ok(MapWrite::RAMWritten)
+                }
+            },
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if self.is_mapper_nametable(addr) {
+            return Ok(MapRead::RAMData(0));
+        }
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        // Window size in 1 KB units, and the register that selects the bank for it
+        let size = 8 >> self.chr_mode;
+        let window = addr as usize / (size * CHR_BANK_SIZE);
+        let reg = self.chr_bank_regs[(window + 1) * size - 1] as usize;
+
+        let offset = addr as usize & (size * CHR_BANK_SIZE - 1);
+        let chr_size = self.chr_banks.max(1) as usize * 8 * CHR_BANK_SIZE;
+        Ok(MapRead::Address(
+            (reg * size * CHR_BANK_SIZE + offset) % chr_size,
+        ))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if self.is_mapper_nametable(addr) {
+            return Ok(MapWrite::RAMWritten);
+        }
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+        if self.chr_banks > 0 {
+            return Err(anyhow!("Can't write to ROM"));
+        }
+
+        Ok(MapWrite::Address(addr as usize))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        // Each pair of bits selects the source for one nametable. ExRAM and fill mode are
+        // handled by `map_chr_read`, so only the console's nametables are mapped here.
+        Some(match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::Mapped(std::array::from_fn(|nametable| {
+                (self.nametable_source(nametable) & 0x01) as usize
+            })),
+        })
+    }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn on_scanline_hblank(&mut self) {
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            self.irq_pending = false;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_compare {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn on_prg_read(&mut self, addr: u16) {
+        match addr {
+            0x5204 => self.irq_pending = false,
+            // The NMI vector is fetched when vblank starts, which ends the frame
+            0xFFFA | 0xFFFB => self.in_frame = false,
+            _ => {}
+        }
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::{chr_addr, prg_addr};
+
+    #[test]
+    fn prg_mode_3_banking() {
+        // 256 KB of PRG ROM
+        let mut mapper = Mapper5::new(16, 16);
+
+        // The last bank is mapped at power on
+        assert_eq!(prg_addr(&mapper, 0xE000), 31 * PRG_BANK_SIZE);
+
+        mapper.map_prg_write(0x5114, 0x81).unwrap();
+        mapper.map_prg_write(0x5115, 0x82).unwrap();
+        mapper.map_prg_write(0x5116, 0x83).unwrap();
+        mapper.map_prg_write(0x5117, 0x04).unwrap();
+        assert_eq!(prg_addr(&mapper, 0x8000), PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xA123), 2 * PRG_BANK_SIZE + 0x123);
+        assert_eq!(prg_addr(&mapper, 0xC000), 3 * PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xFFFF), 5 * PRG_BANK_SIZE - 1);
+    }
+
+    #[test]
+    fn prg_ram_in_rom_window() {
+        let mut mapper = Mapper5::new(16, 16);
+        mapper.map_prg_write(0x5114, 0x01).unwrap();
+
+        // Writes are ignored until PRG RAM is unprotected
+        assert!(mapper.map_prg_write(0x8000, 0x42).is_ok());
+        assert!(matches!(
+            mapper.map_prg_read(0x8000),
+            Ok(MapRead::RAMData(0x00))
+        ));
+
+        mapper.map_prg_write(0x5102, 0b10).unwrap();
+        mapper.map_prg_write(0x5103, 0b01).unwrap();
+        mapper.map_prg_write(0x8000, 0x42).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x8000),
+            Ok(MapRead::RAMData(0x42))
+        ));
+        // Same RAM bank through $6000
+        mapper.map_prg_write(0x5113, 0x01).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x6000),
+            Ok(MapRead::RAMData(0x42))
+        ));
+    }
+
+    #[test]
+    fn chr_banking() {
+        let mut mapper = Mapper5::new(16, 32);
+
+        // 8 KB mode uses $5127
+        mapper.map_prg_write(0x5127, 0x02).unwrap();
+        assert_eq!(chr_addr(&mapper, 0x1234), 2 * 8 * CHR_BANK_SIZE + 0x1234);
+
+        // 1 KB mode uses $5120-$5127
+        mapper.map_prg_write(0x5101, 0x03).unwrap();
+        mapper.map_prg_write(0x5120, 0x10).unwrap();
+        assert_eq!(chr_addr(&mapper, 0x0010), 0x10 * CHR_BANK_SIZE + 0x10);
+        assert_eq!(chr_addr(&mapper, 0x1C10), 0x02 * CHR_BANK_SIZE + 0x10);
+    }
+
+    #[test]
+    fn nametable_mapping_and_multiplier() {
+        let mut mapper = Mapper5::new(16, 16);
+        mapper.map_prg_write(0x5105, 0x44).unwrap();
+        assert!(matches!(mapper.mirroring(), Some(Mirroring::Vertical)));
+        mapper.map_prg_write(0x5105, 0x50).unwrap();
+        assert!(matches!(mapper.mirroring(), Some(Mirroring::Horizontal)));
+        // Console nametables 1, 0, 0, 1, which isn't a standard layout
+        mapper.map_prg_write(0x5105, 0x41).unwrap();
+        assert!(matches!(
+            mapper.mirroring(),
+            Some(Mirroring::Mapped([1, 0, 0, 1]))
+        ));
+        // ExRAM and fill mode nametables read as 0 for now
+        mapper.map_prg_write(0x5105, 0xE4).unwrap();
+        assert!(mapper.map_chr_read(0x2400).is_err());
+        assert!(matches!(
+            mapper.map_chr_read(0x2800),
+            Ok(MapRead::RAMData(0))
+        ));
+        assert!(matches!(
+            mapper.map_chr_read(0x3C05),
+            Ok(MapRead::RAMData(0))
+        ));
+        assert!(matches!(
+            mapper.map_chr_write(0x2C00),
+            Ok(MapWrite::RAMWritten)
+        ));
+
+        mapper.map_prg_write(0x5205, 200).unwrap();
+        mapper.map_prg_write(0x5206, 100).unwrap();
+        let lo = mapper.read_register(0x5205) as u16;
+        let hi = mapper.read_register(0x5206) as u16;
+        assert_eq!((hi << 8) | lo, 20000);
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut mapper = Mapper5::new(16, 16);
+        mapper.map_prg_write(0x5203, 3).unwrap();
+        mapper.map_prg_write(0x5204, 0x80).unwrap();
+
+        // The first hblank is before scanline 0
+        for _ in 0..3 {
+            mapper.on_scanline_hblank();
+            assert!(!mapper.on_cpu_cycle());
+        }
+        assert_eq!(mapper.read_register(0x5204), 0x40);
+
+        mapper.on_scanline_hblank();
+        assert!(mapper.on_cpu_cycle());
+        assert_eq!(mapper.read_register(0x5204), 0xC0);
+
+        // Reading the status acknowledges it
+        mapper.on_prg_read(0x5204);
+        assert!(!mapper.on_cpu_cycle());
+
+        // The NMI ends the frame, so the next one counts from scanline 0 again
+        mapper.on_prg_read(0xFFFA);
+        assert_eq!(mapper.read_register(0x5204), 0x00);
+        for _ in 0..3 {
+            mapper.on_scanline_hblank();
+        }
+        assert!(!mapper.on_cpu_cycle());
+        mapper.on_scanline_hblank();
+        assert!(mapper.on_cpu_cycle());
+    }
+}
@@ -0,0 +1,396 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// Sunsoft FME-7, along with the 5B variant's expansion audio.
+/// See: https://www.nesdev.org/wiki/Sunsoft_FME-7
+pub struct Mapper69 {
+    prg_bank_count: usize,
+    chr_bank_count: usize,
+    chr_ram: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    // Bank mapped at $6000-$7FFF, which can also select the PRG RAM
+    prg_bank0: u8,
+    prg_ram_selected: bool,
+    prg_ram_enabled: bool,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    prg_ram: [u8; PRG_RAM_SIZE],
+    audio: Sunsoft5B,
+}
+
+impl Mapper69 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_bank_count: (prg_banks as usize * 2).max(1),
+            // CHR RAM boards have a single 8 KB bank
+            chr_bank_count: (chr_banks as usize).max(1) * 8,
+            chr_ram: chr_banks == 0,
+
+            command: 0,
+            chr_banks: [0; 8],
+            prg_bank0: 0,
+            prg_ram_selected: false,
+            prg_ram_enabled: false,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+
+            prg_ram: [0; PRG_RAM_SIZE],
+            audio: Sunsoft5B::default(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => {
+                self.prg_bank0 = data & 0x3F;
+                self.prg_ram_selected = data & 0x40 != 0;
+                self.prg_ram_enabled = data & 0x80 != 0;
+            }
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x9] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    3 => Mirroring::SingleScreenUpper,
+                    _ => unreachable!(),
+                }
+            }
+            0xD => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                // Any write to the IRQ control register acknowledges the IRQ
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            0xF => self.irq_counter = (self.irq_counter & 0x00FF) | ((data as u16) << 8),
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_rom_addr(&self, bank: u8, addr: u16) -> usize {
+        let bank = bank as usize % self.prg_bank_count;
+        bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        let addr = match addr {
+            0x6000..=0x7FFF if self.prg_ram_selected => {
+                if !self.prg_ram_enabled {
+                    return Err(anyhow!("PRG RAM is disabled"));
+                }
+                return Ok(MapRead::RAMData(self.prg_ram[(addr - 0x6000) as usize]));
+            }
+            0x6000..=0x7FFF => self.prg_rom_addr(self.prg_bank0, addr),
+            0x8000..=0xDFFF => {
+                let window = ((addr - 0x8000) / 0x2000) as usize;
+                self.prg_rom_addr(self.prg_banks[window], addr)
+            }
+            // Fixed to the last bank
+            0xE000..=0xFFFF => self.prg_rom_addr((self.prg_bank_count - 1) as u8, addr),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        };
+
+        Ok(MapRead::Address(addr))
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if !(self.prg_ram_selected && self.prg_ram_enabled) {
+                    return Err(anyhow!("PRG RAM is not writable"));
+                }
+                self.prg_ram[(addr - 0x6000) as usize] = data;
+                Ok(MapWrite::RAMWritten)
+            }
+            0x8000..=0x9FFF => {
+                self.command = data & 0x0F;
+                Ok(MapWrite::WroteRegister)
+            }
+            0xA000..=0xBFFF => {
+                self.write_parameter(data);
+                Ok(MapWrite::WroteRegister)
+            }
+            0xC000..=0xDFFF => {
+                self.audio.select(data);
+                Ok(MapWrite::WroteRegister)
+            }
+            0xE000..=0xFFFF => {
+                self.audio.write(data);
+                Ok(MapWrite::WroteRegister)
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        let window = (addr / 0x0400) as usize;
+        let bank = self.chr_banks[window] as usize % self.chr_bank_count;
+        Ok(MapRead::Address(
+            bank * CHR_BANK_SIZE + (addr & 0x03FF) as usize,
+        ))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if !self.chr_ram {
+            return Err(anyhow!("Can't write to ROM"));
+        }
+
+        match self.map_chr_read(addr)? {
+            MapRead::Address(addr) => Ok(MapWrite::Address(addr)),
+            MapRead::RAMData(_) => unreachable!(),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_cpu_cycle(&mut self) -> bool {
+        self.audio.clock();
+
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+
+        self.irq_pending
+    }
+
+    fn audio_sample(&self) -> f32 {
+        self.audio.sample()
+    }
+
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+/// The Sunsoft 5B's sound chip, a licensed YM2149F.
+/// Only the three square wave channels are emulated, the noise and envelope
+/// generators aren't used by any released game.
+/// See: https://www.nesdev.org/wiki/Sunsoft_5B_audio
+#[derive(Debug, Default)]
+struct Sunsoft5B {
+    register: u8,
+    channels: [ToneChannel; 3],
+    // Bits 0-2 disable the tone of each channel
+    mixer: u8,
+    // The tone generators are clocked every 16 CPU cycles
+    divider: u8,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ToneChannel {
+    period: u16,
+    timer: u16,
+    volume: u8,
+    high: bool,
+}
+
+impl Sunsoft5B {
+    fn select(&mut self, data: u8) {
+        self.register = data & 0x0F;
+    }
+
+    fn write(&mut self, data: u8) {
+        match self.register {
+            0x0 | 0x2 | 0x4 => {
+                let channel = &mut self.channels[self.register as usize / 2];
+                channel.period = (channel.period & 0x0F00) | data as u16;
+            }
+            0x1 | 0x3 | 0x5 => {
+                let channel = &mut self.channels[self.register as usize / 2];
+                channel.period = (channel.period & 0x00FF) | (((data & 0x0F) as u16) << 8);
+            }
+            0x7 => self.mixer = data,
+            0x8..=0xA => self.channels[self.register as usize - 0x8].volume = data & 0x0F,
+            // Noise and envelope registers
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < 16 {
+            return;
+        }
+        self.divider = 0;
+
+        for channel in &mut self.channels {
+            channel.timer += 1;
+            if channel.timer >= channel.period {
+                channel.timer = 0;
+                channel.high = !channel.high;
+            }
+        }
+    }
+
+    fn sample(&self) -> f32 {
+        self.channels
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| {
+                // A disabled tone holds the output high, so the volume acts as a DAC
+                let tone_disabled = self.mixer & (1 << i) != 0;
+                if !(tone_disabled || channel.high) || channel.volume == 0 {
+                    return 0.0;
+                }
+                // Each volume step is 3 dB
+                let gain = 10f32.powf((channel.volume as f32 - 15.0) * 3.0 / 20.0);
+                // Scaled to be about as loud as one of the APU's pulse channels
+                gain * 0.15
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::prg_addr;
+
+    fn write_command(mapper: &mut Mapper69, command: u8, parameter: u8) {
+        mapper.map_prg_write(0x8000, command).unwrap();
+        mapper.map_prg_write(0xA000, parameter).unwrap();
+    }
+
+    #[test]
+    fn prg_bank_switching() {
+        let mut mapper = Mapper69::new(16, 16);
+        // Last bank is fixed
+        assert_eq!(prg_addr(&mapper, 0xE000), 31 * PRG_BANK_SIZE);
+
+        write_command(&mut mapper, 0x9, 3);
+        write_command(&mut mapper, 0xA, 4);
+        write_command(&mut mapper, 0xB, 5);
+        assert_eq!(prg_addr(&mapper, 0x8123), 3 * PRG_BANK_SIZE + 0x123);
+        assert_eq!(prg_addr(&mapper, 0xA000), 4 * PRG_BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xDFFF), 6 * PRG_BANK_SIZE - 1);
+
+        write_command(&mut mapper, 0x8, 7);
+        assert_eq!(prg_addr(&mapper, 0x6000), 7 * PRG_BANK_SIZE);
+    }
+
+    #[test]
+    fn prg_ram() {
+        let mut mapper = Mapper69::new(16, 16);
+
+        // Selected but not enabled
+        write_command(&mut mapper, 0x8, 0x40);
+        assert!(mapper.map_prg_write(0x6000, 0x42).is_err());
+        assert!(mapper.map_prg_read(0x6000).is_err());
+
+        write_command(&mut mapper, 0x8, 0xC0);
+        mapper.map_prg_write(0x6000, 0x42).unwrap();
+        assert!(matches!(
+            mapper.map_prg_read(0x6000),
+            Ok(MapRead::RAMData(0x42))
+        ));
+    }
+
+    #[test]
+    fn chr_bank_switching() {
+        let mut mapper = Mapper69::new(16, 16);
+        write_command(&mut mapper, 0x0, 10);
+        write_command(&mut mapper, 0x7, 20);
+
+        assert!(matches!(
+            mapper.map_chr_read(0x0010),
+            Ok(MapRead::Address(addr)) if addr == 10 * CHR_BANK_SIZE + 0x10
+        ));
+        assert!(matches!(
+            mapper.map_chr_read(0x1FFF),
+            Ok(MapRead::Address(addr)) if addr == 21 * CHR_BANK_SIZE - 1
+        ));
+        assert!(mapper.map_chr_write(0x0000).is_err());
+    }
+
+    #[test]
+    fn mirroring_control() {
+        let mut mapper = Mapper69::new(16, 16);
+        write_command(&mut mapper, 0xC, 1);
+        assert!(matches!(mapper.mirroring(), Some(Mirroring::Horizontal)));
+        write_command(&mut mapper, 0xC, 3);
+        assert!(matches!(
+            mapper.mirroring(),
+            Some(Mirroring::SingleScreenUpper)
+        ));
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut mapper = Mapper69::new(16, 16);
+        write_command(&mut mapper, 0xE, 0x02);
+        write_command(&mut mapper, 0xF, 0x00);
+        write_command(&mut mapper, 0xD, 0x81);
+
+        // Fires when the counter wraps from 0 to $FFFF
+        assert!(!mapper.on_cpu_cycle());
+        assert!(!mapper.on_cpu_cycle());
+        assert!(mapper.on_cpu_cycle());
+        // Held until acknowledged
+        assert!(mapper.on_cpu_cycle());
+
+        write_command(&mut mapper, 0xD, 0x00);
+        assert!(!mapper.on_cpu_cycle());
+        assert_eq!(mapper.irq_counter, 0xFFFE);
+    }
+
+    #[test]
+    fn audio_tone() {
+        let mut mapper = Mapper69::new(16, 16);
+        assert_eq!(mapper.audio_sample(), 0.0);
+
+        let mut write_audio = |register: u8, data: u8| {
+            mapper.map_prg_write(0xC000, register).unwrap();
+            mapper.map_prg_write(0xE000, data).unwrap();
+        };
+        // Channel A at full volume with a period of 1
+        write_audio(0x0, 0x01);
+        write_audio(0x8, 0x0F);
+
+        // The first timer tick toggles the output high
+        for _ in 0..16 {
+            mapper.on_cpu_cycle();
+        }
+        assert!((mapper.audio_sample() - 0.15).abs() < 1e-6);
+
+        for _ in 0..16 {
+            mapper.on_cpu_cycle();
+        }
+        assert_eq!(mapper.audio_sample(), 0.0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const BANK_SIZE: usize = 32 * 1024;
+
+/// AxROM
+/// See: https://www.nesdev.org/wiki/AxROM
+pub struct Mapper7 {
+    prg_banks: u16,
+    chr_banks: u16,
+    bank_select: u8,
+    mirroring: Mirroring,
+}
+
+impl Mapper7 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_banks,
+            chr_banks,
+            bank_select: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        match addr {
+            0x8000..=0xFFFF => {
+                // prg_banks is in 16 KB units, but AxROM switches 32 KB at a time
+                let bank_count = (self.prg_banks as usize / 2).max(1);
+                let bank = self.bank_select as usize % bank_count;
+                let addr = bank * BANK_SIZE + (addr & 0x7FFF) as usize;
+                Ok(MapRead::Address(addr))
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0x8000..=0xFFFF => {
+                self.bank_select = data & 0x07;
+                self.mirroring = if data & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+                Ok(MapWrite::WroteRegister)
+            }
+            _ => Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+
+        Ok(MapRead::Address(addr as usize))
+    }
+
+    fn map_chr_write(&self, addr: u16) -> Result<MapWrite> {
+        if addr > 0x1FFF {
+            return Err(anyhow!("Address {:#06X} out of range", addr));
+        }
+        // AxROM boards always use CHR RAM, but some dumps still report CHR ROM
+        if self.chr_banks > 0 {
+            return Err(anyhow!("Can't write to ROM"));
+        }
+
+        Ok(MapWrite::Address(addr as usize))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::prg_addr;
+
+    #[test]
+    fn prg_bank_switching() {
+        let mut mapper = Mapper7::new(16, 0);
+        assert_eq!(prg_addr(&mapper, 0x8000), 0x0000);
+        assert_eq!(prg_addr(&mapper, 0xFFFF), 0x7FFF);
+
+        mapper.map_prg_write(0x8000, 0x03).unwrap();
+        assert_eq!(prg_addr(&mapper, 0x8000), 3 * BANK_SIZE);
+        assert_eq!(prg_addr(&mapper, 0xC123), 3 * BANK_SIZE + 0x4123);
+
+        // Only the low 3 bits select the bank
+        mapper.map_prg_write(0xFFFF, 0xEF).unwrap();
+        assert_eq!(prg_addr(&mapper, 0x8000), 7 * BANK_SIZE);
+    }
+
+    #[test]
+    fn default_bank_map() {
+        let mut mapper = Mapper7::new(16, 0);
+        mapper.map_prg_write(0x8000, 0x03).unwrap();
+
+        // In 8 KB units
+        assert_eq!(
+            mapper.prg_bank_map(),
+            [(0x8000, 12), (0xA000, 13), (0xC000, 14), (0xE000, 15)]
+        );
+        assert_eq!(mapper.chr_bank_map().len(), 8);
+    }
+
+    #[test]
+    fn single_screen_mirroring() {
+        let mut mapper = Mapper7::new(16, 0);
+        assert!(matches!(
+            mapper.mirroring(),
+            Some(Mirroring::SingleScreenLower)
+        ));
+
+        mapper.map_prg_write(0x8000, 0x10).unwrap();
+        assert!(matches!(
+            mapper.mirroring(),
+            Some(Mirroring::SingleScreenUpper)
+        ));
+
+        mapper.map_prg_write(0x8000, 0x00).unwrap();
+        assert!(matches!(
+            mapper.mirroring(),
+            Some(Mirroring::SingleScreenLower)
+        ));
+    }
+
+    #[test]
+    fn chr_ram_writable() {
+        let mapper = Mapper7::new(16, 0);
+        assert!(matches!(
+            mapper.map_chr_write(0x1ABC),
+            Ok(MapWrite::Address(0x1ABC))
+        ));
+    }
+}
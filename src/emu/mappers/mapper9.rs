@@ -0,0 +1,192 @@
+use crate::emu::cartridge::Mirroring;
+
+use super::{MapRead, MapWrite, Mapper};
+use anyhow::{anyhow, Result};
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+pub(super) const CHR_BANK_SIZE: usize = 4 * 1024;
+
+/// The latches MMC2 and MMC4 switch the pattern tables with. Each half of the pattern tables
+/// has a bank for when tile $FD was last fetched from it and another for tile $FE,
+/// so games can change banks partway through the screen without any timing code.
+pub(super) struct ChrLatches {
+    // Whether each half last saw tile $FE rather than $FD
+    latches: [bool; 2],
+    // The 4 KB banks for $FD and $FE, in each half
+    banks: [[u8; 2]; 2],
+    // A latch only switches after the fetch that set it, so the tile itself uses the old bank
+    pending: Option<(usize, bool)>,
+    // MMC2 only sets the left latch on the first row of the tile's upper plane
+    exact_left_trigger: bool,
+}
+
+impl ChrLatches {
+    pub fn new(exact_left_trigger: bool) -> Self {
+        Self {
+            latches: [true; 2],
+            banks: [[0; 2]; 2],
+            pending: None,
+            exact_left_trigger,
+        }
+    }
+
+    /// The bank registers at $B000, $C000, $D000 and $E000, in that order.
+    pub fn write_bank(&mut self, register: usize, data: u8) {
+        self.banks[register / 2][register % 2] = data & 0x1F;
+    }
+
+    pub fn bank(&self, addr: u16) -> usize {
+        let half = (addr >> 12) as usize & 1;
+        self.banks[half][self.latches[half] as usize] as usize
+    }
+
+    /// Sets a latch when the PPU fetches the upper plane of tile $FD or $FE.
+    pub fn on_ppu_address(&mut self, addr: u16) {
+        if let Some((half, latch)) = self.pending.take() {
+            self.latches[half] = latch;
+        }
+        if addr > 0x1FFF {
+            return;
+        }
+
+        let half = (addr >> 12) as usize;
+        if half == 0 && self.exact_left_trigger && addr & 0x07 != 0 {
+            return;
+        }
+        self.pending = match addr & 0x0FF8 {
+            0x0FD8 => Some((half, false)),
+            0x0FE8 => Some((half, true)),
+            _ => None,
+        };
+    }
+}
+
+/// Nintendo MMC2, used by Punch-Out!!, with an 8 KB switchable PRG bank and CHR latches.
+/// See: https://www.nesdev.org/wiki/MMC2
+pub struct Mapper9 {
+    prg_bank_count: usize,
+    chr_bank_count: usize,
+
+    prg_bank: u8,
+    latches: ChrLatches,
+    mirroring: Mirroring,
+}
+
+impl Mapper9 {
+    pub fn new(prg_banks: u16, chr_banks: u16) -> Self {
+        Self {
+            prg_bank_count: (prg_banks as usize * 2).max(4),
+            chr_bank_count: (chr_banks as usize * 2).max(1),
+
+            prg_bank: 0,
+            latches: ChrLatches::new(true),
+            mirroring: Mirroring::Vertical,
+        }
+    }
+}
+
+impl Mapper for Mapper9 {
+    fn map_prg_read(&self, addr: u16) -> Result<MapRead> {
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_bank as usize % self.prg_bank_count,
+            // The last 3 banks are fixed
+            0xA000..=0xFFFF => self.prg_bank_count - 3 + (addr - 0xA000) as usize / PRG_BANK_SIZE,
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        };
+
+        Ok(MapRead::Address(
+            bank * PRG_BANK_SIZE + (addr & 0x1FFF) as usize,
+        ))
+    }
+
+    fn map_prg_write(&mut self, addr: u16, data: u8) -> Result<MapWrite> {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xEFFF => self
+                .latches
+                .write_bank((addr - 0xB000) as usize >> 12, data),
+            0xF000..=0xFFFF => self.mirroring = mirroring(data),
+            _ => return Err(anyhow!("Address {:#06X} out of range", addr)),
+        }
+
+        Ok(MapWrite::WroteRegister)
+    }
+
+    fn map_chr_read(&self, addr: u16) -> Result<MapRead> {
+        chr_read(&self.latches, self.chr_bank_count, addr)
+    }
+
+    fn map_chr_write(&self, _addr: u16) -> Result<MapWrite> {
+        Err(anyhow!("Can't write to ROM"))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn on_ppu_address(&mut self, addr: u16) {
+        self.latches.on_ppu_address(addr);
+    }
+}
+
+pub(super) fn chr_read(latches: &ChrLatches, chr_bank_count: usize, addr: u16) -> Result<MapRead> {
+    if addr > 0x1FFF {
+        return Err(anyhow!("Address {:#06X} out of range", addr));
+    }
+
+    let bank = latches.bank(addr) % chr_bank_count;
+    Ok(MapRead::Address(
+        bank * CHR_BANK_SIZE + (addr & 0x0FFF) as usize,
+    ))
+}
+
+pub(super) fn mirroring(data: u8) -> Mirroring {
+    if data & 0x01 == 0 {
+        Mirroring::Vertical
+    } else {
+        Mirroring::Horizontal
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::mappers::test_utils::chr_addr;
+
+    #[test]
+    fn prg_banks() {
+        let mut mapper = Mapper9::new(8, 16);
+        mapper.map_prg_write(0xA000, 5).unwrap();
+        match mapper.map_prg_read(0x8001).unwrap() {
+            MapRead::Address(addr) => assert_eq!(addr, 5 * PRG_BANK_SIZE + 1),
+            MapRead::RAMData(_) => panic!("Expected a PRG ROM address"),
+        }
+        match mapper.map_prg_read(0xA000).unwrap() {
+            MapRead::Address(addr) => assert_eq!(addr, 13 * PRG_BANK_SIZE),
+            MapRead::RAMData(_) => panic!("Expected a PRG ROM address"),
+        }
+    }
+
+    #[test]
+    fn chr_latch_switches_after_fetch() {
+        let mut mapper = Mapper9::new(8, 16);
+        for (addr, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mapper.map_prg_write(addr, bank).unwrap();
+        }
+        assert_eq!(chr_addr(&mapper, 0x0000), 2 * CHR_BANK_SIZE);
+        assert_eq!(chr_addr(&mapper, 0x1000), 4 * CHR_BANK_SIZE);
+
+        // Tile $FD's upper plane is still read from the old bank
+        mapper.on_ppu_address(0x0FD8);
+        assert_eq!(chr_addr(&mapper, 0x0FD8), 2 * CHR_BANK_SIZE + 0xFD8);
+        mapper.on_ppu_address(0x2000);
+        assert_eq!(chr_addr(&mapper, 0x0000), CHR_BANK_SIZE);
+
+        // The left latch ignores the other rows of the tile, but the right one doesn't
+        mapper.on_ppu_address(0x0FE9);
+        mapper.on_ppu_address(0x1FDA);
+        mapper.on_ppu_address(0x2000);
+        assert_eq!(chr_addr(&mapper, 0x0000), CHR_BANK_SIZE);
+        assert_eq!(chr_addr(&mapper, 0x1000), 3 * CHR_BANK_SIZE);
+    }
+}
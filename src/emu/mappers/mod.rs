@@ -1,14 +1,36 @@
-use anyhow::Result;
+use std::ops::RangeInclusive;
 
+use anyhow::{anyhow, Result};
+
+mod fds;
 mod mapper0;
 mod mapper1;
+mod mapper10;
+mod mapper11;
 mod mapper2;
+mod mapper24;
 mod mapper3;
+mod mapper34;
+mod mapper4;
+mod mapper5;
+mod mapper69;
+mod mapper7;
+mod mapper9;
 
+pub use fds::{Fds, FdsDisk, BIOS_SIZE, DISK_INFO_MAGIC, FDS_MAGIC};
 pub use mapper0::Mapper0;
 pub use mapper1::Mapper1;
+pub use mapper10::Mapper10;
+pub use mapper11::Mapper11;
 pub use mapper2::Mapper2;
+pub use mapper24::Mapper24;
 pub use mapper3::Mapper3;
+pub use mapper34::Mapper34;
+pub use mapper4::Mapper4;
+pub use mapper5::Mapper5;
+pub use mapper69::Mapper69;
+pub use mapper7::Mapper7;
+pub use mapper9::Mapper9;
 
 use super::cartridge::Mirroring;
 
@@ -31,4 +53,89 @@ pub trait Mapper {
     fn mirroring(&self) -> Option<Mirroring> {
         None
     }
+    /// Called once every CPU cycle, for mappers that need to keep track of timing.
+    /// Returns whether the mapper is asserting the IRQ line.
+    fn on_cpu_cycle(&mut self) -> bool {
+        false
+    }
+    /// Whether ROM keeps driving the data bus while the CPU writes to a register at $8000-$FFFF,
+    /// so the mapper sees the written value ANDed with the ROM byte at that address.
+    /// See: https://www.nesdev.org/wiki/Bus_conflict
+    fn has_bus_conflicts(&self) -> bool {
+        false
+    }
+    /// Called with each address the PPU puts on its bus, for mappers that watch it.
+    fn on_ppu_address(&mut self, _addr: u16) {}
+    /// Called by the PPU in the horizontal blank before each visible scanline while rendering,
+    /// for mappers that count scanlines without watching the bus themselves.
+    fn on_scanline_hblank(&mut self) {}
+    /// Called after the CPU reads from the cartridge, for registers that acknowledge something
+    /// when read. Debug reads don't call it, so they can't change anything.
+    fn on_prg_read(&mut self, _addr: u16) {}
+    /// The current output of the cartridge's expansion audio, mixed with the APU's output.
+    fn audio_sample(&self) -> f32 {
+        0.0
+    }
+    /// The PRG RAM on the cartridge, which is saved to disk for battery-backed games.
+    fn onboard_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    fn onboard_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    /// Copies an iNES trainer into PRG RAM at $7000-$71FF, where the game expects to find it.
+    /// By default this works for mappers whose onboard RAM starts at $6000.
+    fn load_trainer(&mut self, trainer: &[u8]) -> Result<()> {
+        match self.onboard_ram_mut() {
+            Some(ram) if ram.len() >= 0x1000 + trainer.len() => {
+                ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+                Ok(())
+            }
+            _ => Err(anyhow!("No PRG RAM at $7000 to load the trainer into")),
+        }
+    }
+    /// The start address of each PRG ROM window and the bank mapped into it, for debugging.
+    /// By default the windows are 8 KB, with banks numbered in 8 KB units.
+    fn prg_bank_map(&self) -> Vec<(u16, usize)> {
+        bank_map(0x8000..=0xFFFF, 8 * 1024, |addr| self.map_prg_read(addr))
+    }
+    /// Same as `prg_bank_map`, but for the pattern tables, in 1 KB windows by default.
+    fn chr_bank_map(&self) -> Vec<(u16, usize)> {
+        bank_map(0x0000..=0x1FFF, 1024, |addr| self.map_chr_read(addr))
+    }
+}
+
+/// Finds the bank mapped at the start of each window, skipping windows mapped to RAM.
+fn bank_map<F>(range: RangeInclusive<u16>, window_size: usize, map: F) -> Vec<(u16, usize)>
+where
+    F: Fn(u16) -> Result<MapRead>,
+{
+    range
+        .step_by(window_size)
+        .filter_map(|start| match map(start) {
+            Ok(MapRead::Address(addr)) => Some((start, addr / window_size)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_utils {
+    use super::{MapRead, Mapper};
+
+    /// The PRG ROM address the mapper maps a CPU address to, for checking bank switching.
+    pub fn prg_addr(mapper: &dyn Mapper, addr: u16) -> usize {
+        match mapper.map_prg_read(addr).unwrap() {
+            MapRead::Address(rom_addr) => rom_addr,
+            MapRead::RAMData(_) => panic!("Expected PRG ROM at {:#06X}", addr),
+        }
+    }
+
+    /// Same as `prg_addr`, but for CHR ROM or RAM and a PPU address.
+    pub fn chr_addr(mapper: &dyn Mapper, addr: u16) -> usize {
+        match mapper.map_chr_read(addr).unwrap() {
+            MapRead::Address(chr_addr) => chr_addr,
+            MapRead::RAMData(_) => panic!("Expected CHR memory at {:#06X}", addr),
+        }
+    }
 }
@@ -1,10 +1,13 @@
 pub mod apu;
 mod bits;
 pub mod cartridge;
-pub mod consts;
 pub mod cpu;
+pub mod game_genie;
 pub mod input;
 pub mod mappers;
+pub mod movie;
 pub mod nes;
 pub mod palette;
 pub mod ppu;
+pub mod ram_init;
+pub mod region;
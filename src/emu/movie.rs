@@ -0,0 +1,292 @@
+use std::io::Write;
+#[cfg(feature = "io")]
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+
+use super::{
+    input::{ControllerButtons, ControllerInput},
+    region::Region,
+};
+
+/// How FM2 writes the buttons, from bit 7 of `ControllerButtons` down to bit 0.
+const BUTTON_CHARS: &[u8; 8] = b"RLDUTSBA";
+/// Command bit for pressing the reset button before a frame.
+const COMMAND_RESET: u8 = 0x01;
+
+/// The input for a single frame of a movie.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub one: ControllerButtons,
+    pub two: ControllerButtons,
+    /// Whether the reset button is pressed before the frame runs.
+    pub reset: bool,
+}
+
+impl MovieFrame {
+    fn to_line(self) -> String {
+        let command = if self.reset { COMMAND_RESET } else { 0 };
+        format!(
+            "|{}|{}|{}||",
+            command,
+            buttons_to_str(self.one),
+            buttons_to_str(self.two)
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let [_, command, one, two, ..] = fields[..] else {
+            return Err(anyhow!("Expected |command|port0|port1|, got {:?}", line));
+        };
+
+        let command = command
+            .trim()
+            .parse::<u8>()
+            .map_err(|e| anyhow!("Invalid command {:?}: {}", command, e))?;
+
+        Ok(MovieFrame {
+            one: parse_buttons(one)?,
+            two: parse_buttons(two)?,
+            reset: command & COMMAND_RESET != 0,
+        })
+    }
+}
+
+fn buttons_to_str(buttons: ControllerButtons) -> String {
+    BUTTON_CHARS
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            if buttons.bits() & (0x80 >> i) != 0 {
+                c as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// FM2 counts any character other than '.' or a space as pressed.
+/// An empty field is a port with nothing plugged in.
+fn parse_buttons(s: &str) -> Result<ControllerButtons> {
+    if s.is_empty() {
+        return Ok(ControllerButtons::empty());
+    }
+    if s.len() != 8 {
+        return Err(anyhow!("Expected 8 buttons, got {:?}", s));
+    }
+
+    let bits = s
+        .bytes()
+        .enumerate()
+        .filter(|&(_, c)| c != b'.' && c != b' ')
+        .fold(0, |bits, (i, _)| bits | (0x80 >> i));
+    Ok(ControllerButtons::from_bits_truncate(bits))
+}
+
+/// Records the controller input of every frame in FCEUX's FM2 text format.
+/// Movies always start from power on, since there are no save states to start from,
+/// and the ROM checksum is this emulator's ROM hash rather than FCEUX's MD5.
+/// See: https://fceux.com/web/help/fm2.html
+pub struct MovieRecorder<W: Write> {
+    writer: W,
+    next_frame: MovieFrame,
+}
+
+#[cfg(feature = "io")]
+impl MovieRecorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, rom_hash: u64, region: Region) -> Result<Self> {
+        MovieRecorder::new(BufWriter::new(File::create(path)?), rom_hash, region)
+    }
+}
+
+impl<W: Write> MovieRecorder<W> {
+    pub fn new(mut writer: W, rom_hash: u64, region: Region) -> Result<Self> {
+        writeln!(writer, "version 3")?;
+        writeln!(writer, "palFlag {}", (region == Region::Pal) as u8)?;
+        writeln!(writer, "romChecksum {:016x}", rom_hash)?;
+        writeln!(writer, "port0 1")?;
+        writeln!(writer, "port1 1")?;
+        writeln!(writer, "port2 0")?;
+
+        Ok(MovieRecorder {
+            writer,
+            next_frame: MovieFrame::default(),
+        })
+    }
+
+    /// Keeps the latest controller input, to be recorded with the next frame.
+    /// The Zapper can't be recorded.
+    pub fn notify_input(&mut self, input: &ControllerInput) {
+        match *input {
+            ControllerInput::One(buttons) => self.next_frame.one = buttons,
+            ControllerInput::Two(buttons) => self.next_frame.two = buttons,
+            ControllerInput::Zapper { .. } => {}
+        }
+    }
+
+    pub fn notify_reset(&mut self) {
+        self.next_frame.reset = true;
+    }
+
+    /// Writes the input for the frame that's about to run, must be called before every frame.
+    pub fn record_frame(&mut self) -> Result<()> {
+        writeln!(self.writer, "{}", self.next_frame.to_line())?;
+        self.next_frame.reset = false;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Feeds the input of a recorded movie back one frame at a time, see `MovieRecorder`.
+#[derive(Debug)]
+pub struct MoviePlayer {
+    frames: Vec<MovieFrame>,
+    position: usize,
+    region: Region,
+    rom_hash: Option<u64>,
+}
+
+impl MoviePlayer {
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        MoviePlayer::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Header lines this emulator doesn't use are ignored.
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut player = MoviePlayer {
+            frames: Vec::new(),
+            position: 0,
+            region: Region::Ntsc,
+            rom_hash: None,
+        };
+
+        for (i, line) in s.lines().enumerate() {
+            if line.starts_with('|') {
+                let frame =
+                    MovieFrame::parse(line).map_err(|e| anyhow!("Line {}: {}", i + 1, e))?;
+                player.frames.push(frame);
+                continue;
+            }
+
+            match line.split_once(' ') {
+                Some(("palFlag", value)) => {
+                    player.region = if value.trim() == "1" {
+                        Region::Pal
+                    } else {
+                        Region::Ntsc
+                    };
+                }
+                Some(("romChecksum", value)) => {
+                    player.rom_hash = u64::from_str_radix(value.trim(), 16).ok();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(player)
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The hash of the ROM the movie was recorded with, if it was recorded by this emulator.
+    pub fn rom_hash(&self) -> Option<u64> {
+        self.rom_hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The input for the next frame, or `None` once the movie is over.
+    pub fn next_frame(&mut self) -> Option<MovieFrame> {
+        let frame = self.frames.get(self.position).copied();
+        self.position += 1;
+        frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "io")]
+    use crate::emu::{cartridge::Cartridge, nes::Nes, palette::Palette};
+
+    #[test]
+    fn frame_line_round_trip() {
+        let frame = MovieFrame {
+            one: ControllerButtons::A | ControllerButtons::Right,
+            two: ControllerButtons::Start,
+            reset: true,
+        };
+        assert_eq!(frame.to_line(), "|1|R......A|....T...||");
+        assert_eq!(MovieFrame::parse(&frame.to_line()).unwrap(), frame);
+
+        // Port 1 left empty, with FCEUX's spaces for unpressed buttons
+        let frame = MovieFrame::parse("|0|  DU    |||").unwrap();
+        assert_eq!(frame.one, ControllerButtons::Up | ControllerButtons::Down);
+        assert_eq!(frame.two, ControllerButtons::empty());
+
+        assert!(MovieFrame::parse("|0|RLDU|").is_err());
+        assert!(MovieFrame::parse("garbage").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn playback_is_deterministic() {
+        let run = |movie: &mut dyn FnMut(&mut Nes, usize)| {
+            let mut nes = Nes::new(Palette::default(), Region::Ntsc);
+            nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+            nes.power_on();
+            for i in 0..20 {
+                movie(&mut nes, i);
+                nes.advance_frame();
+            }
+            nes.screen_hash()
+        };
+
+        // Move down nestest's menu and start the selected tests
+        let mut recorder = MovieRecorder::new(Vec::new(), 0x1234, Region::Ntsc).unwrap();
+        let recorded = run(&mut |nes, i| {
+            let buttons = match i {
+                5 => ControllerButtons::Down,
+                10 => ControllerButtons::Start,
+                _ => ControllerButtons::empty(),
+            };
+            for input in [ControllerInput::One(buttons), ControllerInput::Two(buttons)] {
+                recorder.notify_input(&input);
+                nes.trigger_inputs(input);
+            }
+            recorder.record_frame().unwrap();
+        });
+
+        let mut player = MoviePlayer::parse(&String::from_utf8(recorder.writer).unwrap()).unwrap();
+        assert_eq!(player.len(), 20);
+        assert_eq!(player.rom_hash(), Some(0x1234));
+        assert_eq!(player.region(), Region::Ntsc);
+
+        let played = run(&mut |nes, _| {
+            let frame = player.next_frame().unwrap();
+            nes.trigger_inputs(ControllerInput::One(frame.one));
+            nes.trigger_inputs(ControllerInput::Two(frame.two));
+        });
+        assert_eq!(recorded, played);
+        assert_eq!(player.next_frame(), None);
+    }
+}
@@ -1,34 +1,73 @@
+#[cfg(feature = "io")]
+use std::path::Path;
 use std::{
     cell::{Ref, RefCell},
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
     rc::Rc,
 };
+#[cfg(feature = "io")]
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{anyhow, Result};
+use ringbuf::traits::Consumer;
 
 use crate::{
-    audio_output::{AudioBufferConsumer, AudioOutput},
-    renderer::{Color, Sprite},
+    audio_output::{AudioBufferConsumer, AudioOutput, AudioQueueStats},
+    renderer::{rgba_bytes, Color, Sprite},
 };
 
 use super::{
-    apu::Apu, cartridge::Cartridge, consts::FRAME_CLOCKS, cpu::Cpu, input::ControllerInput,
-    palette::Palette, ppu::Ppu,
+    apu::{Apu, ApuChannel},
+    cartridge::Cartridge,
+    cpu::{
+        instructions::{Instruction, InstructionType},
+        Cpu,
+    },
+    input::ControllerInput,
+    palette::Palette,
+    ppu::{PatternTable, Ppu},
+    ram_init::RamInit,
+    region::Region,
 };
 
+// How long stepping over or out of a subroutine runs before giving up
+const MAX_STEP_FRAMES: u32 = 60;
+
 pub struct Nes {
     apu: Rc<RefCell<Apu>>,
     cpu: Rc<RefCell<Cpu>>,
     ppu: Rc<RefCell<Ppu>>,
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
+    region: Region,
 
     screen: Sprite,
     audio_output: Option<AudioOutput>,
+    // Kept here when audio is pulled with `drain_audio` instead of played by the frontend
+    audio_consumer: Option<AudioBufferConsumer>,
+
+    // CPU trace log, written to on every instruction while enabled
+    #[cfg(feature = "io")]
+    trace: Option<BufWriter<File>>,
+    // Address of the write watch that stopped emulation, see `Cpu::set_write_watch`
+    breakpoint_hit: Option<u16>,
+
+    // The PPU's NMI output as of the last CPU cycle, to detect its rising edge
+    nmi_line: bool,
+    // The cartridge's IRQ as of the last CPU cycle, so the PPU can mark where it was raised
+    cartridge_irq: bool,
 
     clock_count: u64,
 }
 
 impl Nes {
-    pub fn new(palette: Palette) -> Self {
+    pub fn new(palette: Palette, region: Region) -> Self {
         let cpu = Rc::new(RefCell::new(Cpu::new()));
-        let ppu = Rc::new(RefCell::new(Ppu::new(palette)));
-        let apu = Rc::new(RefCell::new(Apu::new()));
+        let ppu = Rc::new(RefCell::new(Ppu::new(palette, region)));
+        let apu = Rc::new(RefCell::new(Apu::new(region)));
         cpu.borrow_mut().with_ppu(ppu.clone());
         cpu.borrow_mut().with_apu(apu.clone());
 
@@ -36,48 +75,309 @@ impl Nes {
             cpu,
             ppu,
             apu,
+            cartridge: None,
+            region,
 
             screen: Sprite::monocolor(Color::BLACK, 256, 240),
             audio_output: None,
+            audio_consumer: None,
+
+            #[cfg(feature = "io")]
+            trace: None,
+            breakpoint_hit: None,
+
+            nmi_line: false,
+            cartridge_irq: false,
 
             clock_count: 0,
         }
     }
 
     /// Returns the `Nes` struct, as well as the consumer for the audio buffer.
-    pub fn with_audio(mut self, audio_sample_rate: usize) -> (Self, AudioBufferConsumer) {
-        let (audio_output, consumer) = AudioOutput::new(audio_sample_rate);
+    /// See `AudioOutput::new` for how the latency sizes the buffer.
+    pub fn with_audio(
+        mut self,
+        audio_sample_rate: usize,
+        latency_ms: u32,
+    ) -> (Self, AudioBufferConsumer) {
+        let (audio_output, consumer) = AudioOutput::new(audio_sample_rate, latency_ms, self.region);
         self.audio_output = Some(audio_output);
 
         (self, consumer)
     }
 
-    #[allow(dead_code)]
-    pub fn cpu(&self) -> Ref<Cpu> {
+    /// Fills RAM with `ram_init` on power on instead of zeroes.
+    pub fn with_ram_init(self, ram_init: RamInit) -> Self {
+        self.cpu.borrow_mut().set_ram_init(ram_init);
+        self
+    }
+
+    /// Generates audio at `audio_sample_rate` to be read with `drain_audio`, for embedding the
+    /// emulator where something else plays the sound.
+    pub fn with_pulled_audio(self, audio_sample_rate: usize) -> Self {
+        let (mut nes, mut consumer) =
+            self.with_audio(audio_sample_rate, AudioOutput::DEFAULT_LATENCY_MS);
+        // The silence a real-time buffer starts with would only delay the sound here
+        consumer.clear();
+        nes.audio_consumer = Some(consumer);
+        nes
+    }
+
+    pub fn cpu(&self) -> Ref<'_, Cpu> {
         self.cpu.borrow()
     }
 
-    #[allow(dead_code)]
-    pub fn ppu(&self) -> Ref<Ppu> {
+    pub fn ppu(&self) -> Ref<'_, Ppu> {
         self.ppu.borrow()
     }
 
+    pub fn cartridge(&self) -> Option<Ref<'_, Cartridge>> {
+        self.cartridge.as_ref().map(|cartridge| cartridge.borrow())
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switches to NTSC or PAL timing, e.g. for a game from the other region.
+    /// The PPU and APU only pick it up when they're powered on, so power on after this,
+    /// or set it before `load_cartridge` replaces a cartridge, which powers on by itself.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.borrow_mut().set_region(region);
+        self.apu.borrow_mut().set_region(region);
+        if let Some(audio_output) = &mut self.audio_output {
+            audio_output.set_region(region);
+        }
+    }
+
     pub fn screen(&self) -> &Sprite {
         &self.screen
     }
 
-    #[allow(dead_code)]
+    /// One of the two pattern tables as 128x128 RGBA pixels, colored with `palette` from 0 to 7,
+    /// see `Ppu::get_pattern_table`. For frontends that draw their own CHR viewer.
+    pub fn pattern_table(&self, table: u8, palette: u8) -> Vec<u8> {
+        let table = PatternTable::from(table != 0);
+        self.ppu
+            .borrow()
+            .get_pattern_table(table, palette)
+            .to_rgba()
+    }
+
+    /// The CPU, PPU and APU registers as a JSON object, for scripts and for diffing between runs.
+    /// Unlike a save state this is only a view of the registers, it can't be loaded back.
+    pub fn debug_state_json(&self) -> String {
+        let cpu = self.cpu.borrow();
+        let ppu = self.ppu.borrow();
+        let apu = self.apu.borrow();
+
+        let cpu_json = format!(
+            r#"{{"a":{},"x":{},"y":{},"sp":{},"pc":{},"status":{},"cycles":{},"nmi_pending":{},"irq_pending":{}}}"#,
+            cpu.a(),
+            cpu.x(),
+            cpu.y(),
+            cpu.stkp(),
+            cpu.pc(),
+            cpu.status().bits(),
+            cpu.total_cycles(),
+            cpu.pending_nmi(),
+            cpu.pending_irq()
+        );
+        let ppu_json = format!(
+            r#"{{"ctrl":{},"mask":{},"status":{},"addr":{},"scanline":{},"cycle":{}}}"#,
+            ppu.ctrl().bits(),
+            ppu.mask().bits(),
+            ppu.status().bits(),
+            ppu.addr(),
+            ppu.scanline(),
+            ppu.cycle()
+        );
+        let channels: Vec<String> = ApuChannel::ALL
+            .iter()
+            .map(|&channel| {
+                let status = apu.channel_status(channel);
+                format!(
+                    r#""{}":{{"enabled":{},"length":{}}}"#,
+                    format!("{:?}", channel).to_lowercase(),
+                    status.enabled,
+                    status.length
+                )
+            })
+            .collect();
+
+        format!(
+            r#"{{"cpu":{},"ppu":{},"apu":{{{}}}}}"#,
+            cpu_json,
+            ppu_json,
+            channels.join(",")
+        )
+    }
+
+    /// The 32 palette RAM colors as RGBA bytes.
+    pub fn palette_colors(&self) -> Vec<u8> {
+        rgba_bytes(&self.ppu.borrow().palette_colors())
+    }
+
+    /// Fingerprint of the current frame, for comparing rendering output in tests.
+    /// The hash is only stable for a given Rust toolchain, since `DefaultHasher`'s
+    /// algorithm isn't guaranteed across releases.
+    pub fn screen_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.screen.pixels().hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn clock_count(&self) -> u64 {
         self.clock_count
     }
 
+    /// Logs every instruction the CPU executes to a file, in the same format as nestest.log.
+    /// The log is flushed when tracing is disabled or the `Nes` is dropped.
+    #[cfg(feature = "io")]
+    pub fn enable_trace<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.trace = Some(BufWriter::new(File::create(path)?));
+        Ok(())
+    }
+
+    #[cfg(feature = "io")]
+    pub fn disable_trace(&mut self) -> Result<()> {
+        if let Some(mut trace) = self.trace.take() {
+            trace.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_channel_muted(&mut self, channel: ApuChannel, muted: bool) {
+        self.apu.borrow_mut().set_channel_enabled(channel, !muted);
+    }
+
+    pub fn is_channel_muted(&self, channel: ApuChannel) -> bool {
+        !self.apu.borrow().is_channel_enabled(channel)
+    }
+
+    /// Silences the audio output, e.g. while fast-forwarding.
+    pub fn set_audio_muted(&mut self, muted: bool) {
+        if let Some(audio_output) = &mut self.audio_output {
+            audio_output.set_muted(muted);
+        }
+    }
+
+    /// Keeps the audio in step with running at `speed` times normal speed,
+    /// see `AudioOutput::set_speed`.
+    pub fn set_audio_speed(&mut self, speed: f64) {
+        if let Some(audio_output) = &mut self.audio_output {
+            audio_output.set_speed(speed);
+        }
+    }
+
+    /// `None` if the `Nes` was created without audio.
+    pub fn audio_queue_stats(&self) -> Option<AudioQueueStats> {
+        self.audio_output
+            .as_ref()
+            .map(|output| output.queue_stats())
+    }
+
+    /// Scales the audio to fill the output range, see `AudioOutput::set_normalized`.
+    pub fn set_audio_normalized(&mut self, normalized: bool) {
+        if let Some(audio_output) = &mut self.audio_output {
+            audio_output.set_normalized(normalized);
+        }
+    }
+
+    /// Rounds off peaks instead of letting them clip, see `AudioOutput::set_soft_clip`.
+    pub fn set_audio_soft_clip(&mut self, soft_clip: bool) {
+        if let Some(audio_output) = &mut self.audio_output {
+            audio_output.set_soft_clip(soft_clip);
+        }
+    }
+
+    /// Writes the audio output to a WAV file until `stop_audio_recording`,
+    /// see `AudioOutput::start_recording`.
+    #[cfg(feature = "io")]
+    pub fn start_audio_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        match &mut self.audio_output {
+            Some(audio_output) => audio_output.start_recording(path),
+            None => Err(anyhow!("Can't record audio without an audio output")),
+        }
+    }
+
+    pub fn stop_audio_recording(&mut self) -> Result<()> {
+        match &mut self.audio_output {
+            Some(audio_output) => audio_output.stop_recording(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording_audio(&self) -> bool {
+        self.audio_output
+            .as_ref()
+            .is_some_and(|output| output.is_recording())
+    }
+
+    /// Inserts a cartridge. If one was already loaded, it's ejected and dropped without saving,
+    /// and the console is powered back on, since the old game's state means nothing to the new one.
+    /// Use `eject_cartridge` first to keep the old cartridge's save.
     pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        let replacing = self.eject_cartridge().is_some();
+
         let cartridge = Rc::new(RefCell::new(cartridge));
         self.cpu.borrow_mut().load_cartridge(cartridge.clone());
         self.ppu.borrow_mut().load_cartridge(cartridge.clone());
+        self.cartridge = Some(cartridge);
+
+        if replacing {
+            self.power_on();
+        }
+    }
+
+    /// Takes the cartridge out, detaching it from the CPU and PPU together.
+    /// The console can't run again until another cartridge is loaded.
+    pub fn eject_cartridge(&mut self) -> Option<Cartridge> {
+        self.cpu.borrow_mut().eject_cartridge();
+        self.ppu.borrow_mut().eject_cartridge();
+
+        let cartridge = self.cartridge.take()?;
+        let cartridge = Rc::try_unwrap(cartridge)
+            .unwrap_or_else(|_| panic!("Cartridge is still referenced after ejecting it"));
+        Some(cartridge.into_inner())
+    }
+
+    /// Saves the battery-backed RAM of the loaded cartridge, see `Cartridge::write_save_file`.
+    #[cfg(feature = "io")]
+    pub fn write_save_file(&self, save_dir: &Path) -> Result<()> {
+        match &self.cartridge {
+            Some(cartridge) => cartridge.borrow_mut().write_save_file(save_dir),
+            None => Ok(()),
+        }
     }
 
+    /// See `Cartridge::save_ram_changed`.
+    pub fn save_ram_changed(&self) -> bool {
+        self.cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.borrow().save_ram_changed())
+    }
+
+    /// Switches the console on, starting from a clean state.
+    pub fn power_on(&mut self) {
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().power_on();
+        }
+        self.ppu.borrow_mut().power_on();
+        self.apu.borrow_mut().power_on();
+        self.cpu.borrow_mut().power_on();
+        self.screen = Sprite::monocolor(Color::BLACK, 256, 240);
+        self.clock_count = 0;
+        self.nmi_line = false;
+        self.cartridge_irq = false;
+    }
+
+    /// Presses the reset button, which restarts the game but keeps RAM.
+    /// Some games check RAM to tell a reset apart from powering on.
     pub fn reset(&mut self) {
+        self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
         self.cpu.borrow_mut().reset();
     }
 
@@ -85,9 +385,90 @@ impl Nes {
         self.cpu.borrow_mut().trigger_inputs(input);
     }
 
+    /// Runs for a frame, stopping early if a write watch is hit.
     pub fn advance_frame(&mut self) {
-        for _ in 0..FRAME_CLOCKS {
+        // Forget hits from stepping through instructions while paused
+        self.breakpoint_hit = None;
+        for _ in 0..self.region.frame_clocks() {
             self.clock();
+            if self.breakpoint_hit.is_some() {
+                return;
+            }
+        }
+    }
+
+    /// See `Ppu::set_sprite_limit_enabled`.
+    pub fn set_sprite_limit_enabled(&mut self, enabled: bool) {
+        self.ppu.borrow_mut().set_sprite_limit_enabled(enabled);
+    }
+
+    pub fn set_write_watch(&mut self, addr: u16) {
+        self.cpu.borrow_mut().set_write_watch(addr);
+    }
+
+    pub fn clear_write_watches(&mut self) {
+        self.cpu.borrow_mut().clear_write_watches();
+    }
+
+    /// Reads CPU memory without any of the side effects a read by the CPU can have, like
+    /// clearing vblank or acknowledging a mapper's IRQ, see `Cpu::read_debug`.
+    /// Takes no time, so it can be called between any two clocks.
+    /// Panics on cartridge addresses ($4020-$FFFF) if no cartridge is loaded.
+    pub fn peek_cpu(&self, addr: u16) -> u8 {
+        self.cpu.borrow().read_debug(addr)
+    }
+
+    /// Writes to CPU memory through the CPU's bus, see `Cpu::poke`. RAM and cartridge RAM are
+    /// simply changed, but writes to the PPU, APU, controller and mapper registers do what they
+    /// would for a game, like starting OAM DMA or switching banks.
+    pub fn poke_cpu(&mut self, addr: u16, data: u8) {
+        self.cpu.borrow_mut().poke(addr, data);
+    }
+
+    /// Reads PPU memory, see `Ppu::read_debug`, or None if no cartridge is loaded,
+    /// since the pattern tables and nametable mirroring come from it.
+    pub fn peek_ppu(&self, addr: u16) -> Option<u8> {
+        self.cartridge.as_ref()?;
+        Some(self.ppu.borrow().read_debug(addr))
+    }
+
+    /// Writes to the pattern tables, nametables or palette RAM directly, without going
+    /// through PPUADDR and PPUDATA. Writes to CHR ROM are ignored.
+    pub fn poke_ppu(&mut self, addr: u16, data: u8) -> Result<()> {
+        if self.cartridge.is_none() {
+            return Err(anyhow!("No cartridge loaded"));
+        }
+        self.ppu.borrow_mut().write(addr & 0x3FFF, data);
+        Ok(())
+    }
+
+    /// The address written to if emulation stopped on a write watch, clearing it.
+    pub fn take_breakpoint_hit(&mut self) -> Option<u16> {
+        self.breakpoint_hit.take()
+    }
+
+    /// Runs for a frame and returns the picture as 256x240 colors, row by row.
+    /// The slice borrows the screen, which is drawn over in place by the next frame,
+    /// so copy it to keep it around. Like `advance_frame`, a write watch stops the frame early,
+    /// leaving the rest of the picture from the frame before.
+    pub fn next_frame(&mut self) -> &[Color] {
+        self.advance_frame();
+        self.screen.pixels()
+    }
+
+    /// Takes the samples generated since the last call, see `with_pulled_audio`.
+    /// They're generated in chunks of up to 256 samples, so a frame's worth can arrive a little
+    /// late, but none are lost as long as this is called every frame.
+    /// Nothing is returned without pulled audio.
+    pub fn drain_audio(&mut self) -> impl Iterator<Item = f32> + '_ {
+        self.audio_consumer
+            .iter_mut()
+            .flat_map(|consumer| consumer.pop_iter())
+    }
+
+    pub fn run_frames(&mut self, n: u32) {
+        for _ in 0..n {
+            self.advance_frame();
         }
     }
 
@@ -100,29 +481,145 @@ impl Nes {
             if let Err(e) = self.screen.set_pixel(pixel.x, pixel.y, pixel.color) {
                 panic!("{}", e);
             }
+            if let Some(zapper) = self.cpu.borrow_mut().zapper_mut() {
+                zapper.sense(&pixel);
+            }
         }
 
-        if self.clock_count % 3 == 0 {
+        if self.region.is_cpu_clock(self.clock_count) {
+            #[cfg(feature = "io")]
+            if self.trace.is_some() {
+                self.trace_instruction();
+            }
+            let nmi_output_before = self.ppu.borrow().nmi_output();
             self.cpu.borrow_mut().clock();
             self.apu.borrow_mut().clock();
+
+            if let Some(addr) = self.cpu.borrow_mut().take_watch_hit() {
+                self.breakpoint_hit = Some(addr);
+            }
+
+            // Sampled after the CPU runs, so reading PPUSTATUS as vertical blank starts
+            // clears the flag before the NMI is seen
+            let nmi_output = self.ppu.borrow().nmi_output();
+            if nmi_output && !self.nmi_line {
+                if nmi_output_before {
+                    self.cpu.borrow_mut().request_nmi();
+                } else {
+                    // Turned on by the CPU enabling NMIs in PPUCTRL during vertical blank
+                    self.cpu.borrow_mut().request_nmi_after_next_instruction();
+                }
+            }
+            self.nmi_line = nmi_output;
+
+            let cartridge_irq = self.cpu.borrow().cartridge_irq();
+            if cartridge_irq && !self.cartridge_irq {
+                self.ppu.borrow_mut().mark_mapper_irq();
+            }
+            self.cartridge_irq = cartridge_irq;
         }
 
         if let Some(audio_output) = &mut self.audio_output {
-            audio_output.try_push_sample(self.apu.borrow().sample());
+            let apu = self.apu.borrow();
+            let cartridge = self.cartridge.as_ref().map(|cartridge| cartridge.borrow());
+            audio_output.try_push_sample(|| {
+                let expansion = cartridge.as_ref().map_or(0.0, |c| c.audio_sample());
+                apu.sample() + expansion
+            });
+        }
+    }
+
+    #[cfg(feature = "io")]
+    fn trace_instruction(&mut self) {
+        let mut cpu = self.cpu.borrow_mut();
+        if !cpu.at_instruction_boundary() {
+            return;
         }
 
-        if clock_res.nmi {
-            self.cpu.borrow_mut().nmi();
+        if let Some(trace) = &mut self.trace {
+            if let Err(e) = writeln!(trace, "{}", cpu.get_log_line()) {
+                log::error!("Failed to write CPU trace, disabling it: {}", e);
+                self.trace = None;
+            }
         }
     }
 
+    /// Clocks until the CPU has run the instruction at PC. Instructions take effect on their
+    /// first cycle, so this finishes the current one, then clocks until the CPU's next cycle.
     pub fn next_instruction(&mut self) {
-        let cycles = self.cpu.borrow().cycles();
-        let until_next_cpu_cycle = (3 - self.clock_count % 3) as u8;
-        let to_next = until_next_cpu_cycle + cycles * 3;
-        for _ in 0..to_next {
+        while !self.cpu.borrow().at_instruction_boundary() {
+            self.clock();
+        }
+        loop {
+            self.clock();
+            if self.region.is_cpu_clock(self.clock_count) {
+                break;
+            }
+        }
+    }
+
+    /// Same as `next_instruction`, except a JSR is run until the subroutine returns.
+    /// Returns false if it stopped early, see `step_until`.
+    pub fn step_over(&mut self) -> bool {
+        let (pc, sp) = {
+            let cpu = self.cpu.borrow();
+            (cpu.pc(), cpu.stkp())
+        };
+        let opcode = self.cpu.borrow().read_debug(pc);
+        if !matches!(
+            Instruction::lookup(opcode).instruction_type,
+            InstructionType::Jsr
+        ) {
+            self.next_instruction();
+            return true;
+        }
+
+        // Checking the stack too skips over recursive calls returning to the same place
+        let return_addr = pc.wrapping_add(3);
+        self.step_until(|cpu| cpu.pc() == return_addr && cpu.stkp() >= sp)
+    }
+
+    /// Runs until the current subroutine returns, which is when the stack pointer
+    /// rises above where it is now. Returns false if it stopped early, see `step_until`.
+    pub fn step_out(&mut self) -> bool {
+        let sp = self.cpu.borrow().stkp();
+        self.step_until(|cpu| cpu.stkp() > sp)
+    }
+
+    /// Runs instructions until `done` is true after one of them. Gives up after
+    /// `MAX_STEP_FRAMES` frames, or stops when a write watch is hit, returning false.
+    fn step_until<F: Fn(&Cpu) -> bool>(&mut self, done: F) -> bool {
+        self.breakpoint_hit = None;
+        let limit = self.clock_count + (self.region.frame_clocks() * MAX_STEP_FRAMES) as u64;
+        while self.clock_count < limit {
+            self.next_instruction();
+            if done(&self.cpu.borrow()) {
+                return true;
+            }
+            if self.breakpoint_hit.is_some() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Clocks until the PPU reaches the start of `scanline`, from -1 (pre-render) to the last line of vertical blank.
+    pub fn run_to_scanline(&mut self, scanline: i16) -> bool {
+        self.run_to_cycle(scanline, 0)
+    }
+
+    /// Clocks at least once, then until the PPU is about to render dot `cycle` of `scanline`.
+    /// Gives up and returns false after a full frame of clocks, so a dot that
+    /// doesn't exist can't hang the debugger.
+    pub fn run_to_cycle(&mut self, scanline: i16, cycle: u16) -> bool {
+        for _ in 0..self.region.frame_clocks() {
             self.clock();
+            let ppu = self.ppu.borrow();
+            if ppu.scanline() == scanline && ppu.cycle() == cycle {
+                return true;
+            }
         }
+        false
     }
 
     pub fn cpu_mem_page_str(&self, page: u8) -> String {
@@ -147,15 +644,16 @@ impl Nes {
     }
 }
 
-#[cfg(test)]
+// Every test here runs a ROM file
+#[cfg(all(test, feature = "io"))]
 mod test {
     use super::*;
 
     fn rom_test(path: &str) {
-        let mut nes = Nes::new(Palette::default());
+        let mut nes = Nes::new(Palette::default(), Region::Ntsc);
         let cartridge = Cartridge::new(path).unwrap();
         nes.load_cartridge(cartridge);
-        nes.reset();
+        nes.power_on();
 
         for _ in 0..50_000_000 {
             nes.clock();
@@ -180,6 +678,268 @@ mod test {
         assert_eq!(status, 0, "{}", message);
     }
 
+    fn run_rom(path: &str, frames: u32) -> Nes {
+        let mut nes = Nes::new(
+            Palette::load("assets/palettes/2C02G.pal").unwrap(),
+            Region::Ntsc,
+        );
+        nes.load_cartridge(Cartridge::new(path).unwrap());
+        nes.power_on();
+        nes.run_frames(frames);
+        nes
+    }
+
+    #[test]
+    fn ram_init_pattern() {
+        let mut nes =
+            Nes::new(Palette::default(), Region::Ntsc).with_ram_init(RamInit::Pattern(0xA5));
+        nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+        nes.power_on();
+        assert!((0x0000..0x0800).all(|addr| nes.cpu().read_debug(addr) == 0xA5));
+    }
+
+    #[test]
+    fn pull_frames_and_audio() {
+        let mut nes = Nes::new(
+            Palette::load("assets/palettes/2C02G.pal").unwrap(),
+            Region::Ntsc,
+        )
+        .with_pulled_audio(48000);
+        nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+        nes.power_on();
+
+        let mut samples = 0;
+        for _ in 0..10 {
+            assert_eq!(nes.next_frame().len(), 256 * 240);
+            samples += nes.drain_audio().count();
+        }
+        assert_eq!(
+            nes.screen_hash(),
+            run_rom("assets/test_roms/nestest.nes", 10).screen_hash()
+        );
+        // 800 samples a frame, give or take the last partly filled chunk
+        assert!(samples.abs_diff(8000) <= 256, "{}", samples);
+    }
+
+    #[test]
+    fn raster_markers_survive_whole_frames() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 2);
+        while nes.ppu().scanline() != 100 {
+            nes.clock();
+        }
+        nes.ppu.borrow_mut().mark_mapper_irq();
+
+        // Running a whole frame from mid frame ends after the next one cleared the markers
+        nes.advance_frame();
+        assert_eq!(nes.ppu().mapper_irq_scanline(), None);
+        assert_eq!(nes.ppu().last_frame_mapper_irq_scanline(), Some(100));
+
+        nes.advance_frame();
+        assert_eq!(nes.ppu().last_frame_mapper_irq_scanline(), None);
+    }
+
+    #[test]
+    fn screen_hash_is_deterministic() {
+        let blank = Nes::new(Palette::default(), Region::Ntsc).screen_hash();
+        let a = run_rom("assets/test_roms/nestest.nes", 10);
+        let b = run_rom("assets/test_roms/nestest.nes", 10);
+
+        assert_eq!(a.screen_hash(), b.screen_hash());
+        assert_ne!(a.screen_hash(), blank);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 1);
+        // JSR $6010, NOP, then at $6010: LDA #$01, PHA, PLA, RTS
+        let program = [
+            (0x6000, vec![0x20, 0x10, 0x60, 0xEA]),
+            (0x6010, vec![0xA9, 0x01, 0x48, 0x68, 0x60]),
+        ];
+        for (start, bytes) in program {
+            for (i, byte) in bytes.into_iter().enumerate() {
+                let cartridge = nes.cartridge.as_ref().unwrap();
+                cartridge
+                    .borrow_mut()
+                    .cpu_write(start + i as u16, byte)
+                    .unwrap();
+            }
+        }
+
+        nes.cpu.borrow_mut().reset_to(0x6000);
+        assert!(nes.step_over());
+        assert_eq!(nes.cpu().pc(), 0x6003);
+        assert_eq!(nes.cpu().a(), 0x01);
+
+        nes.cpu.borrow_mut().reset_to(0x6000);
+        nes.next_instruction();
+        assert_eq!(nes.cpu().pc(), 0x6010);
+        nes.next_instruction();
+        assert_eq!(nes.cpu().pc(), 0x6012);
+        assert!(nes.step_out());
+        assert_eq!(nes.cpu().pc(), 0x6003);
+    }
+
+    #[test]
+    fn nmi_enabled_during_vblank() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 1);
+        // LDA #$80, STA $2000, NOP, NOP
+        for (i, byte) in [0xA9, 0x80, 0x8D, 0x00, 0x20, 0xEA, 0xEA]
+            .into_iter()
+            .enumerate()
+        {
+            let cartridge = nes.cartridge.as_ref().unwrap();
+            cartridge
+                .borrow_mut()
+                .cpu_write(0x6000 + i as u16, byte)
+                .unwrap();
+        }
+        nes.ppu.borrow_mut().cpu_write(0x2000, 0x00);
+        assert!(nes.run_to_scanline(242));
+        nes.cpu.borrow_mut().reset_to(0x6000);
+
+        nes.next_instruction();
+        nes.next_instruction();
+        assert!(nes.cpu().pending_nmi());
+        // The NMI waits for the instruction after the write
+        nes.next_instruction();
+        assert_eq!(nes.cpu().pc(), 0x6006);
+        nes.next_instruction();
+        let nmi_vector =
+            nes.cpu().read_debug(0xFFFA) as u16 | (nes.cpu().read_debug(0xFFFB) as u16) << 8;
+        assert_eq!(nes.cpu().pc(), nmi_vector);
+    }
+
+    #[test]
+    fn swap_cartridge() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 2);
+        let hash = nes.cartridge().unwrap().rom_hash();
+
+        let cartridge = nes.eject_cartridge().unwrap();
+        assert_eq!(cartridge.rom_hash(), hash);
+        assert!(nes.cartridge().is_none());
+        assert!(nes.eject_cartridge().is_none());
+
+        // Loading over another cartridge starts it from power on
+        nes.load_cartridge(cartridge);
+        nes.run_frames(3);
+        nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+        assert_eq!(nes.clock_count(), 0);
+        nes.run_frames(2);
+        assert_eq!(
+            nes.screen_hash(),
+            run_rom("assets/test_roms/nestest.nes", 2).screen_hash()
+        );
+    }
+
+    #[test]
+    fn peek_and_poke() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 1);
+        nes.set_write_watch(0x0300);
+
+        nes.poke_cpu(0x0300, 0x42);
+        assert_eq!(nes.peek_cpu(0x0B00), 0x42);
+        assert_eq!(nes.cpu.borrow_mut().take_watch_hit(), None);
+
+        // Through PPUADDR and PPUDATA, like a game would
+        nes.poke_cpu(0x2006, 0x3F);
+        nes.poke_cpu(0x2006, 0x01);
+        nes.poke_cpu(0x2007, 0x21);
+        assert_eq!(nes.peek_ppu(0x3F01), Some(0x21));
+
+        nes.poke_ppu(0x2005, 0x7E).unwrap();
+        assert_eq!(nes.peek_ppu(0x6005), Some(0x7E));
+        // CHR ROM can't be changed
+        let tile = nes.peek_ppu(0x0010).unwrap();
+        nes.poke_ppu(0x0010, !tile).unwrap();
+        assert_eq!(nes.peek_ppu(0x0010), Some(tile));
+
+        nes.eject_cartridge();
+        assert_eq!(nes.peek_ppu(0x3F01), None);
+        assert!(nes.poke_ppu(0x3F01, 0x00).is_err());
+    }
+
+    #[test]
+    fn debug_state_json() {
+        let nes = run_rom("assets/test_roms/nestest.nes", 1);
+        nes.apu.borrow_mut().write(0x4015, 0x01);
+        nes.apu.borrow_mut().write(0x4003, 0x08);
+
+        let cpu = nes.cpu();
+        let ppu = nes.ppu();
+        let json = nes.debug_state_json();
+        assert!(json.starts_with(&format!(
+            r#"{{"cpu":{{"a":{},"x":{},"y":{},"sp":{},"pc":{},"#,
+            cpu.a(),
+            cpu.x(),
+            cpu.y(),
+            cpu.stkp(),
+            cpu.pc()
+        )));
+        assert!(json.contains(&format!(
+            r#""scanline":{},"cycle":{}}}"#,
+            ppu.scanline(),
+            ppu.cycle()
+        )));
+        assert!(json.ends_with(
+            r#""apu":{"pulse1":{"enabled":true,"length":254},"pulse2":{"enabled":false,"length":0},"triangle":{"enabled":false,"length":0},"noise":{"enabled":false,"length":0},"dmc":{"enabled":false,"length":0}}}"#
+        ));
+    }
+
+    #[test]
+    fn trace_logs_each_instruction() {
+        let path = std::env::temp_dir().join("nesrs_trace_test.log");
+
+        let mut nes = Nes::new(Palette::default(), Region::Ntsc);
+        nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+        nes.power_on();
+        nes.enable_trace(&path).unwrap();
+        nes.advance_frame();
+        nes.disable_trace().unwrap();
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<_> = trace.lines().collect();
+        // nestest's reset vector
+        assert!(lines[0].starts_with("C004 78"), "{}", lines[0]);
+        assert!(lines[0].ends_with("CYC:7"), "{}", lines[0]);
+        // Roughly 29780 CPU cycles per frame, and instructions take 2-7 cycles
+        assert!(lines.len() > 29780 / 7 && lines.len() < 29780 / 2);
+    }
+
+    #[test]
+    fn run_to_scanline_and_cycle() {
+        let mut nes = run_rom("assets/test_roms/nestest.nes", 1);
+
+        assert!(nes.run_to_scanline(-1));
+        assert_eq!((nes.ppu().scanline(), nes.ppu().cycle()), (-1, 0));
+        assert!(nes.run_to_scanline(100));
+        assert_eq!((nes.ppu().scanline(), nes.ppu().cycle()), (100, 0));
+
+        // Wraps around through the next frame
+        assert!(nes.run_to_cycle(20, 123));
+        assert_eq!((nes.ppu().scanline(), nes.ppu().cycle()), (20, 123));
+
+        assert!(!nes.run_to_scanline(300));
+        assert!(!nes.run_to_cycle(0, 341));
+    }
+
+    #[test]
+    fn pal_timing() {
+        let mut nes = Nes::new(Palette::default(), Region::Pal);
+        nes.load_cartridge(Cartridge::new("assets/test_roms/nestest.nes").unwrap());
+        nes.power_on();
+
+        // The PPU starts at the top of the picture
+        nes.advance_frame();
+        assert_eq!((nes.ppu().scanline(), nes.ppu().cycle()), (0, 0));
+        // 5 CPU clocks for every 16 PPU clocks
+        assert_eq!(nes.cpu().total_cycles(), 341 * 312 * 5 / 16);
+
+        assert!(nes.run_to_scanline(310));
+    }
+
     #[test]
     fn instr_test_v5_01_basics() {
         rom_test("assets/test_roms/instr_test-v5/01-basics.nes");
@@ -1,9 +1,12 @@
-use anyhow::Result;
-use std::fs::File;
-use std::io::prelude::*;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "io")]
+use std::{fs, path::Path};
 
 use crate::renderer::{Color, Sprite};
 
+/// 64 colors of 3 bytes each.
+const PAL_SIZE: usize = 64 * 3;
+
 #[derive(Debug, Clone)]
 pub struct Palette {
     colors: Vec<Color>,
@@ -18,14 +21,27 @@ impl Default for Palette {
 }
 
 impl Palette {
-    pub fn load(path: &str) -> Result<Self> {
-        let mut f = File::open(path)?;
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(&path)?;
+        Palette::from_pal_bytes(&bytes)
+            .map_err(|e| anyhow!("Invalid palette {}: {}", path.as_ref().display(), e))
+    }
 
-        // Palettes are exactly 192 bytes, 3 bytes per color (64 colors)
-        let mut buf = [0; 192];
-        f.read_exact(&mut buf)?;
+    /// Parses a .pal file, 64 colors of 3 bytes each.
+    /// Palettes with the 7 color emphasis variants after them are also accepted,
+    /// but only the base colors are used.
+    pub fn from_pal_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != PAL_SIZE && bytes.len() != PAL_SIZE * 8 {
+            return Err(anyhow!(
+                "Expected {} or {} bytes, got {}",
+                PAL_SIZE,
+                PAL_SIZE * 8,
+                bytes.len()
+            ));
+        }
 
-        let colors = buf
+        let colors = bytes[..PAL_SIZE]
             .chunks_exact(3)
             .map(|c| Color(c[0], c[1], c[2]))
             .collect::<Vec<_>>();
@@ -47,3 +63,23 @@ impl From<Palette> for Sprite {
         Sprite::new(value.colors().clone(), 16, 4).unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pal_bytes() {
+        let mut bytes: Vec<u8> = (0..PAL_SIZE as u8).collect();
+        let palette = Palette::from_pal_bytes(&bytes).unwrap();
+        assert_eq!(palette.get_color(0), Some(Color(0, 1, 2)));
+        assert_eq!(palette.get_color(63), Some(Color(189, 190, 191)));
+        assert_eq!(palette.get_color(64), None);
+
+        bytes.resize(PAL_SIZE * 8, 0xFF);
+        assert_eq!(Palette::from_pal_bytes(&bytes).unwrap().colors().len(), 64);
+
+        let err = Palette::from_pal_bytes(&bytes[..100]).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 192 or 1536 bytes, got 100");
+    }
+}
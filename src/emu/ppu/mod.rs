@@ -11,8 +11,9 @@ use self::vram_addr::VRAMAddr;
 use super::bits::{extend_bit, flip_byte};
 use super::{
     bits::IntoBit,
-    cartridge::{Cartridge, Mirroring},
+    cartridge::{Cartridge, Mirroring, CHR_BANK_SIZE},
     palette::Palette,
+    region::Region,
 };
 
 mod flags;
@@ -32,20 +33,54 @@ struct SpritePixel {
     pub palette: u8,
     pub pixel: u8,
     pub behind_background: bool,
-    pub sprite0_hit: bool,
+    /// Whether sprite 0 has an opaque pixel here, even if another sprite is drawn over it
+    pub sprite0: bool,
 }
 
 pub struct PpuClockResult {
     pub pixel: Option<Pixel>,
-    pub nmi: bool,
+}
+
+/// Progress through OAM during sprite evaluation, which runs over cycles 65-256.
+/// See: https://www.nesdev.org/wiki/PPU_sprite_evaluation
+#[derive(Default)]
+struct SpriteEvaluation {
+    /// The sprite (n) and byte within it (m) being read from OAM
+    n: usize,
+    m: usize,
+    /// How many sprites have been copied into secondary OAM
+    found: usize,
+    /// Byte read from OAM on the last odd cycle, handled on the next even cycle
+    data: u8,
+    done: bool,
+}
+
+impl SpriteEvaluation {
+    fn next_sprite(&mut self) {
+        self.n += 1;
+        if self.n == 64 {
+            self.done = true;
+        }
+    }
 }
 
 const PALETTE_RAM_SIZE: usize = 32;
 const NAMETABLE_SIZE: usize = 1024;
 const OAM_SIZE: usize = 256;
+// Every sprite in OAM can be on the same scanline with the sprite limit disabled
+const MAX_SPRITES: usize = OAM_SIZE / 4;
+// How long a bit of the I/O latch lasts without being refreshed
+const IO_LATCH_DECAY_SECONDS: f64 = 0.6;
+// What palette RAM holds when the console is turned on, as read by blargg's power_up_palette test.
+// Real consoles vary, but this is what most emulators use.
+const POWER_ON_PALETTE: [u8; PALETTE_RAM_SIZE] = [
+    0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00, 0x04, 0x2C,
+    0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02, 0x00, 0x20, 0x2C, 0x08,
+];
 
 pub struct Ppu {
     palette: Palette,
+    region: Region,
 
     // X and Y positions that the PPU is currently rendering
     cycle: u16,
@@ -77,11 +112,18 @@ pub struct Ppu {
     bg_tile_palette_shifter: ShiftRegister16,
 
     // Sprite rendering
+    secondary_oam: [u8; 32],
+    // The OAM index of each sprite in secondary OAM, to tell which one is sprite 0
+    secondary_oam_indices: [usize; 8],
+    sprite_evaluation: SpriteEvaluation,
     scanline_sprites: Vec<PpuSprite>,
-    sprite_tile_shifters: [ShiftRegister8; 8],
+    sprite_tile_shifters: [ShiftRegister8; MAX_SPRITES],
+    // Whether only 8 sprites are drawn on each scanline, like the real PPU
+    sprite_limit_enabled: bool,
 
     // Memory
-    nametables: [[u8; NAMETABLE_SIZE]; 2],
+    // Only 2 KB of VRAM is in the console, the other 2 nametables are on four-screen cartridges
+    nametables: [[u8; NAMETABLE_SIZE]; 4],
     palette_ram: [u8; PALETTE_RAM_SIZE],
 
     oam: [u8; OAM_SIZE],
@@ -90,12 +132,32 @@ pub struct Ppu {
     cartridge: Option<Rc<RefCell<Cartridge>>>,
 
     odd_frame: bool,
+    // Set by reading PPUSTATUS on the dot before vertical blank starts,
+    // which keeps the flag from being set that frame
+    suppress_vblank: bool,
+    // Writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are ignored after power on or reset,
+    // until the end of the next vertical blank
+    warming_up: bool,
+
+    // The PPU's data bus latch, which write-only registers and undriven bits read back.
+    // Each bit fades to 0 if it isn't refreshed, with the frames left for each one here.
+    // See: https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+    io_latch: u8,
+    io_latch_decay: [u8; 8],
+
+    // Where raster effects happened this frame, for debugging split screens,
+    // and where they happened in the last finished one, which is what gets drawn
+    sprite_zero_hit_scanline: Option<i16>,
+    mapper_irq_scanline: Option<i16>,
+    last_frame_sprite_zero_hit_scanline: Option<i16>,
+    last_frame_mapper_irq_scanline: Option<i16>,
 }
 
 impl Ppu {
-    pub fn new(palette: Palette) -> Self {
+    pub fn new(palette: Palette, region: Region) -> Self {
         Ppu {
             palette,
+            region,
 
             scanline: -1,
             cycle: 0,
@@ -120,17 +182,31 @@ impl Ppu {
             bg_tile_id_shifter: ShiftRegister16::new(true),
             bg_tile_palette_shifter: ShiftRegister16::new(false),
 
+            secondary_oam: [0xFF; 32],
+            secondary_oam_indices: [0; 8],
+            sprite_evaluation: SpriteEvaluation::default(),
             scanline_sprites: Vec::new(),
-            sprite_tile_shifters: [ShiftRegister8::new(false); 8],
+            sprite_tile_shifters: [ShiftRegister8::new(false); MAX_SPRITES],
+            sprite_limit_enabled: true,
 
-            nametables: [[0; NAMETABLE_SIZE]; 2],
-            palette_ram: [0; PALETTE_RAM_SIZE],
+            nametables: [[0; NAMETABLE_SIZE]; 4],
+            palette_ram: POWER_ON_PALETTE,
 
             oam: [0; OAM_SIZE],
 
             cartridge: None,
 
             odd_frame: false,
+            suppress_vblank: false,
+            warming_up: false,
+
+            io_latch: 0x00,
+            io_latch_decay: [0; 8],
+
+            sprite_zero_hit_scanline: None,
+            mapper_irq_scanline: None,
+            last_frame_sprite_zero_hit_scanline: None,
+            last_frame_mapper_irq_scanline: None,
         }
     }
 
@@ -158,30 +234,101 @@ impl Ppu {
         self.data_buffer
     }
 
-    #[allow(dead_code)]
     pub fn scanline(&self) -> i16 {
         self.scanline
     }
 
-    #[allow(dead_code)]
     pub fn cycle(&self) -> u16 {
         self.cycle
     }
 
-    #[allow(dead_code)]
-    pub fn nametables(&self) -> &[[u8; NAMETABLE_SIZE]; 2] {
+    /// The scanline sprite 0 hit happened on this frame, if it has yet.
+    pub fn sprite_zero_hit_scanline(&self) -> Option<i16> {
+        self.sprite_zero_hit_scanline
+    }
+
+    /// The scanline the cartridge last raised an IRQ on this frame, see `mark_mapper_irq`.
+    pub fn mapper_irq_scanline(&self) -> Option<i16> {
+        self.mapper_irq_scanline
+    }
+
+    /// `sprite_zero_hit_scanline` as of the end of the last frame. Frames are run a fixed
+    /// number of clocks at a time, which usually stops just after the markers are cleared,
+    /// so this is the one to show.
+    pub fn last_frame_sprite_zero_hit_scanline(&self) -> Option<i16> {
+        self.last_frame_sprite_zero_hit_scanline
+    }
+
+    /// `mapper_irq_scanline` as of the end of the last frame.
+    pub fn last_frame_mapper_irq_scanline(&self) -> Option<i16> {
+        self.last_frame_mapper_irq_scanline
+    }
+
+    /// Records that the cartridge raised an IRQ, which the PPU can't see itself.
+    pub fn mark_mapper_irq(&mut self) {
+        self.mapper_irq_scanline = Some(self.scanline);
+    }
+
+    pub fn nametables(&self) -> &[[u8; NAMETABLE_SIZE]; 4] {
         &self.nametables
     }
 
-    #[allow(dead_code)]
     pub fn oam(&self) -> &[u8; OAM_SIZE] {
         &self.oam
     }
 
+    /// Takes effect at the next power on, which rebuilds the PPU for the region.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// The PPU starts at the top of the picture, so the registers can't be written for a whole
+    /// frame, about 29658 CPU cycles on NTSC.
+    /// See: https://www.nesdev.org/wiki/PPU_power_up_state
+    pub fn power_on(&mut self) {
+        let cartridge = self.cartridge.take();
+        let sprite_limit_enabled = self.sprite_limit_enabled;
+        *self = Ppu::new(self.palette.clone(), self.region);
+        self.cartridge = cartridge;
+        self.sprite_limit_enabled = sprite_limit_enabled;
+        self.scanline = 0;
+        self.warming_up = true;
+    }
+
+    /// With the limit disabled, every sprite on a scanline is drawn instead of only the first 8,
+    /// which gets rid of the flicker games use to show more. This isn't accurate, but the
+    /// overflow flag and sprite 0 hits still happen the same way, so games can't tell.
+    pub fn set_sprite_limit_enabled(&mut self, enabled: bool) {
+        self.sprite_limit_enabled = enabled;
+    }
+
+    /// The reset button clears the control and scroll registers, but leaves memory alone.
+    /// See: https://www.nesdev.org/wiki/PPU_power_up_state
+    pub fn reset(&mut self) {
+        self.ctrl = PpuCtrl::empty();
+        self.mask = PpuMask::empty();
+        self.write_latch = false;
+        self.data_buffer = 0x00;
+        self.temp_vram_addr = VRAMAddr::new();
+        self.fine_x = 0x00;
+        self.odd_frame = false;
+        self.warming_up = true;
+    }
+
     pub fn load_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
         self.cartridge = Some(cartridge);
     }
 
+    pub fn eject_cartridge(&mut self) {
+        self.cartridge = None;
+    }
+
+    /// The PPU's NMI output, which is high while in vertical blank with NMIs enabled.
+    /// The CPU gets an NMI when it goes from low to high.
+    pub fn nmi_output(&self) -> bool {
+        self.status.contains(PpuStatus::VerticalBlank) && self.ctrl.contains(PpuCtrl::GenerateNMI)
+    }
+
     /// See: https://www.nesdev.org/wiki/PPU_rendering
     /// for details on how this works.
     pub fn clock(&mut self) -> PpuClockResult {
@@ -192,12 +339,20 @@ impl Ppu {
                 self.status.set(PpuStatus::VerticalBlank, false);
                 self.status.set(PpuStatus::Sprite0Hit, false);
                 self.status.set(PpuStatus::SpriteOverflow, false);
+                self.warming_up = false;
+                self.decay_io_latch();
+                self.last_frame_sprite_zero_hit_scanline = self.sprite_zero_hit_scanline.take();
+                self.last_frame_mapper_irq_scanline = self.mapper_irq_scanline.take();
 
                 self.scanline_sprites = Vec::new();
             }
 
-            // Odd frame cycle skip
-            if self.scanline == 0 && self.cycle == 0 && self.odd_frame {
+            // Odd frame cycle skip, which the PAL PPU doesn't do
+            if self.scanline == 0
+                && self.cycle == 0
+                && self.odd_frame
+                && self.region == Region::Ntsc
+            {
                 self.cycle = 1;
             }
 
@@ -239,13 +394,7 @@ impl Ppu {
 
             match self.cycle {
                 256 => self.increment_scroll_y(),
-                257 => {
-                    self.copy_horizontal_position();
-                    // garbage NT fetch
-                    self.next_bg_tile_id = self.fetch_nametable_tile_id();
-                }
-                // garbage NT fetch
-                259 => self.next_bg_tile_id = self.fetch_nametable_tile_id(),
+                257 => self.copy_horizontal_position(),
                 // Copy vertical position info at the end of VBlank
                 280..=304 if self.scanline == -1 => self.copy_vertical_position(),
                 // Unused nametable fetches
@@ -253,35 +402,51 @@ impl Ppu {
                 _ => {}
             }
 
-            // Sprite evaluation
-            if self.cycle == 257 && self.scanline >= 0 {
-                self.scanline_sprites = self.next_scanline_sprite_evaluation();
+            // The end of the line before each visible one, where MMC5 sees the repeated
+            // nametable fetches and counts a scanline
+            if self.cycle == 340 && self.scanline < 239 && self.rendering_enabled() {
+                self.scanline_hblank();
+            }
+
+            // Sprite evaluation for the next scanline, which only happens while rendering
+            if self.scanline >= 0 && self.rendering_enabled() {
+                self.evaluate_sprites();
+            } else if self.scanline >= 0 && self.cycle == 257 {
+                self.scanline_sprites = vec![PpuSprite::default(); 8];
             }
 
-            // Sprite rendering
-            if self.cycle == 340 {
-                for (i, sprite) in self.scanline_sprites.iter().enumerate() {
-                    let (lsb, msb) = if !self.ctrl.contains(PpuCtrl::SpriteSize) {
-                        self.fetch_8x8_sprite_row(sprite)
-                    } else {
-                        self.fetch_8x16_sprite_row(sprite)
-                    };
-                    self.sprite_tile_shifters[i].load(lsb, msb);
+            // Sprite pattern fetches for the next scanline
+            if (257..=320).contains(&self.cycle) {
+                // The sprite fetches use OAMADDR to index OAM, and leave it at 0 on every
+                // rendered line, so it's always 0 by the time sprite evaluation starts.
+                // Evaluation really starts from OAMADDR, and a non-zero OAMADDR when rendering
+                // starts makes the 2C02 copy the row of 8 bytes it points at over the first one.
+                // Neither is emulated, as it takes writing OAMADDR after dot 320 to see them.
+                // See: https://www.nesdev.org/wiki/PPU_registers#OAMADDR
+                if self.rendering_enabled() {
+                    self.oam_addr = 0;
                 }
+                self.fetch_sprite_slot();
+            }
+            // The PPU has no time to fetch sprites past the limit, so they're read all at once
+            if self.cycle == 320 {
+                self.load_sprites_past_limit();
             }
         }
 
-        let mut nmi = false;
         // Finished rendering visible portion, entering vertical blank
         if self.scanline == 241 && self.cycle == 1 {
-            self.status.set(PpuStatus::VerticalBlank, true);
-            nmi = self.ctrl.contains(PpuCtrl::GenerateNMI);
+            if !self.suppress_vblank {
+                self.status.set(PpuStatus::VerticalBlank, true);
+            }
+            self.suppress_vblank = false;
         }
 
         let pixel = match self.get_pixel() {
             Some((pixel, sprite0_hit)) => {
-                if sprite0_hit {
+                if sprite0_hit && !self.status.contains(PpuStatus::Sprite0Hit) {
                     self.status.set(PpuStatus::Sprite0Hit, true);
+                    self.sprite_zero_hit_scanline = Some(self.scanline);
                 }
                 Some(pixel)
             }
@@ -293,18 +458,49 @@ impl Ppu {
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline > 260 {
+            // The last scanline is the one before the pre-render line
+            if self.scanline > self.region.scanlines() - 2 {
                 self.scanline = -1;
                 self.odd_frame = !self.odd_frame
             }
         }
 
-        PpuClockResult { pixel, nmi }
+        PpuClockResult { pixel }
+    }
+
+    /// A read made by rendering, which puts the address on the PPU bus for the cartridge to see.
+    fn fetch(&self, addr: u16) -> u8 {
+        if self.rendering_enabled() {
+            self.set_bus_address(addr);
+        }
+        self.read(addr)
+    }
+
+    /// Lets the mapper watch the PPU address bus, like MMC3 does to count scanlines.
+    fn set_bus_address(&self, addr: u16) {
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().on_ppu_address(addr);
+        }
+    }
+
+    fn scanline_hblank(&self) {
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().on_scanline_hblank();
+        }
+    }
+
+    /// Outside of rendering, the PPU bus holds the VRAM address, so games can
+    /// toggle A12 through PPUADDR and PPUDATA.
+    fn vram_addr_changed(&self) {
+        let rendering = (-1..240).contains(&self.scanline) && self.rendering_enabled();
+        if !rendering {
+            self.set_bus_address(self.vram_addr.into());
+        }
     }
 
     fn fetch_nametable_tile_id(&self) -> u8 {
         let offset = u16::from(self.vram_addr) & 0x0FFF;
-        self.read(0x2000 + offset)
+        self.fetch(0x2000 + offset)
     }
 
     fn fetch_tile_palette_id(&self) -> u8 {
@@ -315,8 +511,9 @@ impl Ppu {
         let tile_group_y = (self.vram_addr.coarse_y() >> 2) as u16;
         let nametable = (self.vram_addr.nametable_y() << 1 | self.vram_addr.nametable_x()) as u16;
 
-        let mut attr_byte = self
-            .read(ATTRIBUTE_MEMORY_OFFSET | (nametable << 10) | (tile_group_y << 3) | tile_group_x);
+        let mut attr_byte = self.fetch(
+            ATTRIBUTE_MEMORY_OFFSET | (nametable << 10) | (tile_group_y << 3) | tile_group_x,
+        );
 
         // The byte holds the palettes for a 4x4 group of tiles,
         // but each palette only takes up 2 bits (used for a 2x2 group of tiles)
@@ -373,13 +570,82 @@ impl Ppu {
         let addr = pattern_table.addr() + tile_offset + (row as u16);
         // The high bit plane is located 8 bytes further
         if !high_plane {
-            self.read(addr)
+            self.fetch(addr)
         } else {
-            self.read(addr + 8)
+            self.fetch(addr + 8)
         }
     }
 
-    fn fetch_8x8_sprite_row(&self, sprite: &PpuSprite) -> (u8, u8) {
+    /// Runs one dot of the sprite fetches, which take 8 dots for each of the 8 sprite slots:
+    /// two garbage nametable reads, then the low and high bit planes of the sprite's row.
+    /// Empty slots fetch tile $FF and load a transparent row, and mappers watching
+    /// the address bus count on those fetches happening.
+    fn fetch_sprite_slot(&mut self) {
+        let slot = (self.cycle as usize - 257) / 8;
+        let sprite = self
+            .scanline_sprites
+            .get(slot)
+            .filter(|_| slot < self.sprite_evaluation.found);
+        let addr = match sprite {
+            Some(sprite) if !self.ctrl.contains(PpuCtrl::SpriteSize) => {
+                self.sprite_row_addr_8x8(sprite)
+            }
+            Some(sprite) => self.sprite_row_addr_8x16(sprite),
+            None if !self.ctrl.contains(PpuCtrl::SpriteSize) => {
+                let pattern_table =
+                    PatternTable::from(self.ctrl.contains(PpuCtrl::SpritePatternTable));
+                pattern_table.addr() + 0x0FF0
+            }
+            None => PatternTable::Right.addr() + 0x0FE0,
+        };
+
+        match (self.cycle - 257) % 8 {
+            0 | 2 => {
+                self.fetch_nametable_tile_id();
+            }
+            4 => {
+                self.fetch(addr);
+            }
+            6 => {
+                let lsb = self.read(addr);
+                let msb = self.fetch(addr + 8);
+                let (lsb, msb) = match sprite {
+                    Some(sprite)
+                        if sprite.attribute.contains(SpriteAttribute::FlipHorizontally) =>
+                    {
+                        (flip_byte(lsb), flip_byte(msb))
+                    }
+                    Some(_) => (lsb, msb),
+                    None => (0, 0),
+                };
+                if slot < self.scanline_sprites.len() {
+                    self.sprite_tile_shifters[slot].load(lsb, msb);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn load_sprites_past_limit(&mut self) {
+        for slot in 8..self.scanline_sprites.len() {
+            let sprite = &self.scanline_sprites[slot];
+            let addr = if !self.ctrl.contains(PpuCtrl::SpriteSize) {
+                self.sprite_row_addr_8x8(sprite)
+            } else {
+                self.sprite_row_addr_8x16(sprite)
+            };
+            let (lsb, msb) = (self.read(addr), self.read(addr + 8));
+            let (lsb, msb) = if sprite.attribute.contains(SpriteAttribute::FlipHorizontally) {
+                (flip_byte(lsb), flip_byte(msb))
+            } else {
+                (lsb, msb)
+            };
+            self.sprite_tile_shifters[slot].load(lsb, msb);
+        }
+    }
+
+    /// The address of the low bit plane of the sprite's row on the current scanline.
+    fn sprite_row_addr_8x8(&self, sprite: &PpuSprite) -> u16 {
         let pattern_table = PatternTable::from(self.ctrl.contains(PpuCtrl::SpritePatternTable));
         let dy = self.scanline - (sprite.y as i16);
         let row = if sprite.attribute.contains(SpriteAttribute::FlipVertically) {
@@ -388,17 +654,10 @@ impl Ppu {
             dy
         };
 
-        let lsb = self.fetch_tile_byte(pattern_table, sprite.tile_id, row as u8, false);
-        let msb = self.fetch_tile_byte(pattern_table, sprite.tile_id, row as u8, true);
-
-        if sprite.attribute.contains(SpriteAttribute::FlipHorizontally) {
-            (flip_byte(lsb), flip_byte(msb))
-        } else {
-            (lsb, msb)
-        }
+        pattern_table.addr() + ((sprite.tile_id as u16) << 4) + (row as u8 as u16)
     }
 
-    fn fetch_8x16_sprite_row(&self, sprite: &PpuSprite) -> (u8, u8) {
+    fn sprite_row_addr_8x16(&self, sprite: &PpuSprite) -> u16 {
         let pattern_table = PatternTable::from(sprite.tile_id & 0x01 == 1);
         let dy = self.scanline - (sprite.y as i16);
 
@@ -411,14 +670,7 @@ impl Ppu {
             dy
         };
 
-        let lsb = self.fetch_tile_byte(pattern_table, tile_id, row as u8, false);
-        let msb = self.fetch_tile_byte(pattern_table, tile_id, row as u8, true);
-
-        if sprite.attribute.contains(SpriteAttribute::FlipHorizontally) {
-            (flip_byte(lsb), flip_byte(msb))
-        } else {
-            (lsb, msb)
-        }
+        pattern_table.addr() + ((tile_id as u16) << 4) + (row as u8 as u16)
     }
 
     fn rendering_enabled(&self) -> bool {
@@ -517,67 +769,122 @@ impl Ppu {
         }
     }
 
-    // See: https://www.nesdev.org/wiki/PPU_sprite_evaluation
-    fn next_scanline_sprite_evaluation(&mut self) -> Vec<PpuSprite> {
-        let mut next_scanline_sprites = Vec::new();
-
-        let in_range = |y: u8| {
-            // Outside of visible region anyway, so a sprite wouldn't be visible here
-            if self.scanline == -1 || self.scanline >= 240 {
-                return false;
+    /// Runs one cycle of sprite evaluation, spread over the scanline like the real PPU does
+    /// so that the sprite overflow flag is set on the right dot.
+    /// See: https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    fn evaluate_sprites(&mut self) {
+        match self.cycle {
+            // Secondary OAM is cleared to $FF, one byte every 2 cycles
+            1..=64 if self.cycle.is_multiple_of(2) => {
+                self.secondary_oam[self.cycle as usize / 2 - 1] = 0xFF;
             }
+            // Odd cycles read from OAM, even cycles write to secondary OAM
+            65..=256 => {
+                if self.cycle == 65 {
+                    self.sprite_evaluation = SpriteEvaluation::default();
+                }
 
-            let height = if self.ctrl.contains(PpuCtrl::SpriteSize) {
-                16
-            } else {
-                8
-            };
-
-            let dy = self.scanline - y as i16;
-            dy >= 0 && dy < height
-        };
-
-        for (i, sprite) in self.oam.chunks_exact(4).enumerate() {
-            if next_scanline_sprites.len() == 8 {
-                break;
+                let eval = &mut self.sprite_evaluation;
+                if eval.done {
+                    // The real PPU keeps reading OAM, but nothing else can change
+                } else if self.cycle % 2 == 1 {
+                    eval.data = self.oam[eval.n * 4 + eval.m];
+                } else {
+                    self.evaluate_sprite_byte();
+                }
             }
+            257 => {
+                self.scanline_sprites = self.secondary_oam_sprites();
+                if !self.sprite_limit_enabled && self.sprite_evaluation.found == 8 {
+                    self.scanline_sprites.extend(self.sprites_past_limit());
+                }
+            }
+            _ => {}
+        }
+    }
 
-            let sprite = PpuSprite {
-                y: sprite[0],
-                tile_id: sprite[1],
-                attribute: SpriteAttribute::from_bits_truncate(sprite[2]),
-                x: sprite[3],
-                oam_index: i,
-            };
+    fn evaluate_sprite_byte(&mut self) {
+        let data = self.sprite_evaluation.data;
+        let in_range = self.sprite_in_range(data);
+        let eval = &mut self.sprite_evaluation;
+
+        if eval.found < 8 {
+            self.secondary_oam[eval.found * 4 + eval.m] = data;
+            if eval.m == 0 {
+                if !in_range {
+                    eval.next_sprite();
+                    return;
+                }
+                self.secondary_oam_indices[eval.found] = eval.n;
+            }
 
-            if in_range(sprite.y) {
-                next_scanline_sprites.push(sprite);
+            eval.m += 1;
+            if eval.m == 4 {
+                eval.m = 0;
+                eval.found += 1;
+                eval.next_sprite();
             }
+        } else if in_range {
+            self.status.insert(PpuStatus::SpriteOverflow);
+            eval.done = true;
+        } else {
+            // The sprite overflow bug: m is incremented along with n,
+            // so the other sprite bytes get treated as Y coordinates
+            eval.m = (eval.m + 1) % 4;
+            eval.next_sprite();
         }
+    }
 
-        if next_scanline_sprites.len() < 8 {
-            next_scanline_sprites.resize_with(8, PpuSprite::default);
-            return next_scanline_sprites;
+    fn sprite_in_range(&self, y: u8) -> bool {
+        // Outside of visible region anyway, so a sprite wouldn't be visible here
+        if self.scanline == -1 || self.scanline >= 240 {
+            return false;
         }
 
-        // Sprite overflow check
-        let mut n = next_scanline_sprites.last().unwrap().oam_index + 1;
-        let mut m = 0;
+        let height = if self.ctrl.contains(PpuCtrl::SpriteSize) {
+            16
+        } else {
+            8
+        };
 
-        while n * 4 + m <= OAM_SIZE - 4 {
-            let i = n * 4 + m;
-            let y = self.oam[i];
+        let dy = self.scanline - y as i16;
+        dy >= 0 && dy < height
+    }
 
-            // Emulate the sprite overflow bug
-            if in_range(y) {
-                self.status.insert(PpuStatus::SpriteOverflow);
-                break;
-            }
-            n += 1;
-            m += 1;
-        }
+    /// The sprites in range of the scanline after the 8 that fit in secondary OAM.
+    fn sprites_past_limit(&self) -> Vec<PpuSprite> {
+        let last = self.secondary_oam_indices[7];
+        (last + 1..MAX_SPRITES)
+            .filter(|&n| self.sprite_in_range(self.oam[n * 4]))
+            .map(|n| PpuSprite {
+                y: self.oam[n * 4],
+                tile_id: self.oam[n * 4 + 1],
+                attribute: SpriteAttribute::from_bits_truncate(self.oam[n * 4 + 2]),
+                x: self.oam[n * 4 + 3],
+                oam_index: n,
+            })
+            .collect()
+    }
+
+    /// The sprites found for the next scanline, padded to 8 with empty sprites.
+    fn secondary_oam_sprites(&self) -> Vec<PpuSprite> {
+        self.secondary_oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, sprite)| {
+                if i >= self.sprite_evaluation.found {
+                    return PpuSprite::default();
+                }
 
-        next_scanline_sprites
+                PpuSprite {
+                    y: sprite[0],
+                    tile_id: sprite[1],
+                    attribute: SpriteAttribute::from_bits_truncate(sprite[2]),
+                    x: sprite[3],
+                    oam_index: self.secondary_oam_indices[i],
+                }
+            })
+            .collect()
     }
 
     fn get_pixel(&self) -> Option<(Pixel, bool)> {
@@ -589,30 +896,47 @@ impl Ppu {
         let bg_pixel = self.get_bg_pixel();
         let sprite_pixel = self.get_sprite_pixel();
 
-        let (palette, pixel, sprite0_hit) = match (bg_pixel.pixel, sprite_pixel.pixel) {
-            (0, 0) => (0, 0, false),
-            (0, sp_px) => (sprite_pixel.palette, sp_px, false),
-            (bg_px, 0) => (bg_pixel.palette, bg_px, false),
+        let (palette, pixel) = match (bg_pixel.pixel, sprite_pixel.pixel) {
+            (0, 0) => (0, 0),
+            (0, sp_px) => (sprite_pixel.palette, sp_px),
+            (bg_px, 0) => (bg_pixel.palette, bg_px),
             (bg_px, sp_px) => {
-                let (palette, pixel) = if sprite_pixel.behind_background {
+                if sprite_pixel.behind_background {
                     (bg_pixel.palette, bg_px)
                 } else {
                     (sprite_pixel.palette, sp_px)
-                };
-
-                // Sprite 0 hit detection logic
-                // See https://www.nesdev.org/wiki/PPU_OAM#Sprite_0_hits
-                let left_clipping_enabled = !self.mask.contains(PpuMask::ShowBackgroundLeft)
-                    || !self.mask.contains(PpuMask::ShowSpritesLeft);
-                let in_left_clip_window = left_clipping_enabled && self.cycle < 9; // x < 8
-
-                let sprite0_hit =
-                    sprite_pixel.sprite0_hit && self.rendering_enabled() && !in_left_clip_window;
+                }
+            }
+        };
 
-                (palette, pixel, sprite0_hit)
+        // With rendering off the backdrop color is drawn, unless v points into palette RAM.
+        // Then the color at v is drawn instead, which games can use to show colors that
+        // aren't in the backdrop by setting PPUADDR during the frame
+        // See: https://www.nesdev.org/wiki/PPU_palettes#The_background_palette_hack
+        let (palette, pixel) = match u16::from(self.vram_addr) & 0x3FFF {
+            addr @ 0x3F00..=0x3FFF if !self.rendering_enabled() => {
+                let offset = (addr & 0x1F) as u8;
+                (offset >> 2, offset & 0x03)
             }
+            _ => (palette, pixel),
         };
 
+        // Sprite 0 hits only depend on sprite 0 and the background being opaque,
+        // not on which sprite or the background wins priority
+        // See https://www.nesdev.org/wiki/PPU_OAM#Sprite_0_hits
+        let left_clipping_enabled = !self.mask.contains(PpuMask::ShowBackgroundLeft)
+            || !self.mask.contains(PpuMask::ShowSpritesLeft);
+        let in_left_clip_window = left_clipping_enabled && self.cycle < 9; // x < 8
+
+        // Never hits at x = 255
+        let last_column = self.cycle == 256;
+
+        let sprite0_hit = sprite_pixel.sprite0
+            && bg_pixel.pixel != 0
+            && self.rendering_enabled()
+            && !in_left_clip_window
+            && !last_column;
+
         let px = Pixel {
             x: (self.cycle - 1) as usize,
             y: (self.scanline) as usize,
@@ -633,21 +957,24 @@ impl Ppu {
         BgPixel { palette, pixel }
     }
 
+    /// The sprite pixel with the highest priority, which is the first opaque one in OAM order.
     fn get_sprite_pixel(&self) -> SpritePixel {
         if !self.mask.contains(PpuMask::ShowSprites) {
             return SpritePixel::default();
         }
 
+        let opaque_pixel = |i: usize| {
+            let pixel = self.sprite_tile_shifters[i].get();
+            (self.scanline_sprites[i].x == 0 && pixel != 0).then_some(pixel)
+        };
+        let sprite0 = (0..self.scanline_sprites.len())
+            .any(|i| self.scanline_sprites[i].oam_index == 0 && opaque_pixel(i).is_some());
+
         for i in 0..self.scanline_sprites.len() {
             let sprite = &self.scanline_sprites[i];
-            if sprite.x != 0 {
+            let Some(pixel) = opaque_pixel(i) else {
                 continue;
-            }
-
-            let pixel = self.sprite_tile_shifters[i].get();
-            if pixel == 0 {
-                continue;
-            }
+            };
 
             let palette_low = sprite.attribute.contains(SpriteAttribute::PaletteLSB) as u8;
             let palette_high = sprite.attribute.contains(SpriteAttribute::PaletteMSB) as u8;
@@ -660,7 +987,7 @@ impl Ppu {
                 palette: palette + 4,
                 pixel,
                 behind_background,
-                sprite0_hit: sprite.oam_index == 0,
+                sprite0,
             };
         }
 
@@ -670,7 +997,12 @@ impl Ppu {
     pub fn cpu_write(&mut self, addr: u16, data: u8) {
         assert!((0x2000..=0x3FFF).contains(&addr), "Invalid PPU address");
 
+        self.refresh_io_latch(data, 0xFF);
         let register = addr % 8;
+        if self.warming_up && matches!(register, 0 | 1 | 5 | 6) {
+            return;
+        }
+
         match register {
             0 => {
                 self.ctrl = PpuCtrl::from_bits_truncate(data);
@@ -682,7 +1014,16 @@ impl Ppu {
             1 => self.mask = PpuMask::from_bits_truncate(data),
             2 => {}
             3 => self.oam_addr = data,
-            4 => self.oam[self.oam_addr as usize] = data,
+            4 => {
+                // OAM is busy with sprite evaluation while rendering, so the write is dropped,
+                // and only the sprite part of the address is incremented
+                if self.sprite_evaluation_active() {
+                    self.oam_addr = self.oam_addr.wrapping_add(4);
+                } else {
+                    self.write_oam(self.oam_addr, data);
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                }
+            }
             5 => {
                 if !self.write_latch {
                     self.fine_x = data & 0x07;
@@ -704,6 +1045,7 @@ impl Ppu {
                     let addr = (u16::from(self.temp_vram_addr) & 0xFF00) | data;
                     self.temp_vram_addr = VRAMAddr::from(addr);
                     self.vram_addr = self.temp_vram_addr;
+                    self.vram_addr_changed();
                 }
                 self.write_latch = !self.write_latch;
             }
@@ -714,49 +1056,62 @@ impl Ppu {
                 } else {
                     self.vram_addr = VRAMAddr::from(u16::from(self.vram_addr) + 1);
                 }
+                self.vram_addr_changed();
             }
             _ => unreachable!(),
         }
     }
 
+    /// Write-only registers and undriven bits read back the I/O latch, see `refresh_io_latch`.
     pub fn cpu_read(&mut self, addr: u16) -> u8 {
         assert!((0x2000..=0x3FFF).contains(&addr), "Invalid PPU address");
 
         let register = addr % 8;
         match register {
-            0 => 0,
-            1 => 0,
+            0 | 1 | 3 | 5 | 6 => self.io_latch,
             2 => {
-                let data = self.status.bits();
+                // Reading on the dot before vertical blank starts reads the flag as clear,
+                // and stops it from being set. Reading on the dot it's set or the one after
+                // clears it before the CPU sees the NMI, since `nmi_output` drops.
+                // See: https://www.nesdev.org/wiki/PPU_frame_timing#VBL_Flag_Timing
+                if self.scanline == 241 && self.cycle == 1 {
+                    self.suppress_vblank = true;
+                }
+
+                // Only the top 3 bits of PPUSTATUS are driven
+                self.refresh_io_latch(self.status.bits(), 0xE0);
+                let data = self.io_latch;
 
                 self.status.set(PpuStatus::VerticalBlank, false);
                 self.write_latch = false;
 
                 data
             }
-            3 => 0,
             4 => {
-                if (0..240).contains(&self.scanline) && (1..65).contains(&self.cycle) {
-                    return 0xFF;
-                }
-                self.oam[self.oam_addr as usize]
+                self.refresh_io_latch(self.read_oam_data(), 0xFF);
+                self.io_latch
             }
-            5 => 0,
-            6 => 0,
             7 => {
-                let mut temp = self.data_buffer;
-                self.data_buffer = self.read(self.vram_addr.into());
-
-                if u16::from(self.vram_addr) >= 0x3F00 {
-                    temp = self.data_buffer;
+                let addr = u16::from(self.vram_addr) & 0x3FFF;
+                // Palette reads skip the buffer, which is filled from the nametable
+                // underneath the palette instead. Palette entries are only 6 bits.
+                if addr >= 0x3F00 {
+                    self.data_buffer = self.read(addr - 0x1000);
+                    let color = self.read(addr);
+                    self.refresh_io_latch(color, 0x3F);
+                } else {
+                    self.refresh_io_latch(self.data_buffer, 0xFF);
+                    self.data_buffer = self.read(addr);
                 }
+                let data = self.io_latch;
                 if self.ctrl.contains(PpuCtrl::VRamAddressIncrement) {
                     self.vram_addr = VRAMAddr::from(u16::from(self.vram_addr) + 32);
                 } else {
                     self.vram_addr = VRAMAddr::from(u16::from(self.vram_addr) + 1);
                 }
+                self.vram_addr_changed();
 
-                temp
+                data
             }
             _ => unreachable!(),
         }
@@ -794,21 +1149,17 @@ impl Ppu {
                 let (nt, index) = map_addr_to_nametable(mirroring, addr);
                 self.nametables[nt][index] = data;
             }
-            0x3F00..=0x3FFF => {
-                // Palette ram is from 0x3F00 to 0x3F1F, but mirrored from 0x3F20-0x3FFF
-                let i = addr & 0x1F;
-                let i = match i {
-                    0x10 | 0x14 | 0x18 | 0x1C => i - 0x10,
-                    x => x,
-                };
-
-                self.palette_ram[i as usize] = data;
-            }
+            0x3F00..=0x3FFF => self.palette_ram[palette_ram_index(addr)] = data,
             // _ => panic!("Writing to PPU address {:04X} not implemented yet", addr),
             _ => {}
         }
     }
 
+    /// Reads PPU memory, like a tile in the pattern tables, without affecting any state.
+    pub fn read_debug(&self, addr: u16) -> u8 {
+        self.read(addr & 0x3FFF)
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         let cartridge = self.cartridge.as_ref().expect("Cartridge not attached");
         if let Ok(data) = cartridge.borrow().ppu_read(addr) {
@@ -822,50 +1173,162 @@ impl Ppu {
                 let (nt, index) = map_addr_to_nametable(mirroring, addr);
                 self.nametables[nt][index]
             }
-            0x3F00..=0x3FFF => {
-                // Palette ram is from 0x3F00 to 0x3F1F, but mirrored from 0x3F20-0x3FFF
-                let i = addr & 0x1F;
-                let i = match i {
-                    // Mirrored on these addresses
-                    0x10 | 0x14 | 0x18 | 0x1C => i - 0x10,
-                    x => x,
-                };
-
-                self.palette_ram[i as usize]
-            }
+            0x3F00..=0x3FFF => self.palette_ram[palette_ram_index(addr)],
             // _ => todo!("Reading from PPU address {:04X} not implemented yet", addr),
             _ => 0,
         }
     }
 
     pub fn dma_oam_write(&mut self, index: u8, data: u8) {
-        self.oam[index as usize] = data;
+        self.write_oam(index, data);
+    }
+
+    /// Bits 2-4 of the attribute byte don't exist in OAM, so they always read back as 0.
+    fn write_oam(&mut self, index: u8, data: u8) {
+        self.oam[index as usize] = if index % 4 == 2 { data & 0xE3 } else { data };
+    }
+
+    /// Sets the bits of the I/O latch in `mask` to `data`, restarting their decay.
+    fn refresh_io_latch(&mut self, data: u8, mask: u8) {
+        self.io_latch = (self.io_latch & !mask) | (data & mask);
+
+        let decay_frames = (IO_LATCH_DECAY_SECONDS * self.region.fps()) as u8;
+        for (bit, decay) in self.io_latch_decay.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *decay = decay_frames;
+            }
+        }
+    }
+
+    /// Called once a frame, clearing the bits of the I/O latch that haven't been refreshed.
+    fn decay_io_latch(&mut self) {
+        for (bit, decay) in self.io_latch_decay.iter_mut().enumerate() {
+            if *decay > 0 {
+                *decay -= 1;
+                if *decay == 0 {
+                    self.io_latch &= !(1 << bit);
+                }
+            }
+        }
+    }
+
+    fn sprite_evaluation_active(&self) -> bool {
+        (0..240).contains(&self.scanline) && self.rendering_enabled()
+    }
+
+    /// Reading OAMDATA while rendering returns whatever the PPU is reading from OAM at the time.
+    /// See: https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    fn read_oam_data(&self) -> u8 {
+        if !self.sprite_evaluation_active() {
+            return self.oam[self.oam_addr as usize];
+        }
+
+        match self.cycle {
+            // Secondary OAM is being cleared, which reads $FF
+            1..=64 => 0xFF,
+            // The last byte read from OAM by sprite evaluation
+            65..=256 => self.sprite_evaluation.data,
+            // The sprite fetches read each sprite's Y, tile, attribute and X from
+            // secondary OAM, then X for the other 4 cycles
+            257..=320 => {
+                let offset = self.cycle as usize - 257;
+                self.secondary_oam[offset / 8 * 4 + (offset % 8).min(3)]
+            }
+            // Idle until the next scanline, reading the first byte of secondary OAM
+            _ => self.secondary_oam[0],
+        }
     }
 
     pub fn get_palette_color(&self, palette: u8, pixel: u8) -> Color {
         let offset = (palette << 2) + pixel;
-        let color_index = self.read(0x3F00 + offset as u16);
+        let mut color_index = self.read(0x3F00 + offset as u16);
+
+        // Greyscale selects from the grey column of the palette
+        if self.mask.contains(PpuMask::Greyscale) {
+            color_index &= 0x30;
+        }
 
-        self.palette
+        let color = self
+            .palette
             .get_color(color_index)
-            .unwrap_or_else(|| panic!("Invalid palette color {}", color_index))
+            .unwrap_or_else(|| panic!("Invalid palette color {}", color_index));
+
+        self.apply_emphasis(color)
+    }
+
+    /// The colors of all 32 entries of palette RAM, including the unused mirrors of the backdrop color.
+    pub fn palette_colors(&self) -> [Color; PALETTE_RAM_SIZE] {
+        std::array::from_fn(|i| self.get_palette_color(i as u8 >> 2, i as u8 & 0x03))
     }
 
-    pub fn get_pattern_table(&self, table: PatternTable) -> Sprite {
-        let table_offset = match table {
-            PatternTable::Left => 0x0000,
-            PatternTable::Right => 0x1000,
+    /// Emphasis darkens the channels that aren't emphasized.
+    /// The PAL PPU has the red and green bits the other way around.
+    /// See: https://www.nesdev.org/wiki/NTSC_video#Color_Tint_Bits
+    fn apply_emphasis(&self, color: Color) -> Color {
+        const ATTENUATION: f32 = 0.816;
+
+        let (mut red, mut green) = (
+            self.mask.contains(PpuMask::EmphasizeRed),
+            self.mask.contains(PpuMask::EmphasizeGreen),
+        );
+        if self.region == Region::Pal {
+            (red, green) = (green, red);
+        }
+        let blue = self.mask.contains(PpuMask::EmphasizeBlue);
+
+        if !(red || green || blue) {
+            return color;
+        }
+
+        let attenuate = |channel: u8, attenuated: bool| {
+            if attenuated {
+                (channel as f32 * ATTENUATION) as u8
+            } else {
+                channel
+            }
         };
 
+        Color(
+            attenuate(color.0, green || blue),
+            attenuate(color.1, red || blue),
+            attenuate(color.2, red || green),
+        )
+    }
+
+    /// Draws the 256 tiles in a pattern table as a 128x128 sprite,
+    /// colored with one of the 8 palettes, where 0-3 are background and 4-7 are sprites.
+    pub fn get_pattern_table(&self, table: PatternTable, palette: u8) -> Sprite {
+        self.draw_pattern_table(|offset| self.read(table.addr() + offset), palette)
+    }
+
+    /// Same as `get_pattern_table`, but draws the given 1 KB CHR banks, one for each quarter
+    /// of the table, instead of whatever the mapper has mapped in. See `Cartridge::read_chr_bank`.
+    pub fn get_pattern_table_banked(&self, banks: [usize; 4], palette: u8) -> Sprite {
+        let cartridge = self
+            .cartridge
+            .as_ref()
+            .expect("Cartridge not attached")
+            .borrow();
+        self.draw_pattern_table(
+            |offset| {
+                let bank = banks[offset as usize / CHR_BANK_SIZE];
+                cartridge.read_chr_bank(bank, offset)
+            },
+            palette,
+        )
+    }
+
+    /// `read` reads a byte from the 4 KB pattern table being drawn.
+    fn draw_pattern_table<F: Fn(u16) -> u8>(&self, read: F, palette: u8) -> Sprite {
         let mut buf = [Color::default(); 128 * 128];
 
         for i in 0..16 {
             for j in 0..16 {
                 let tile_offset = i * 256 + j * 16;
                 for tile_row in 0..8 {
-                    let row_addr = table_offset + tile_offset + tile_row;
-                    let tile_lsb = self.read(row_addr);
-                    let tile_msb = self.read(row_addr + 8);
+                    let row_addr = tile_offset + tile_row;
+                    let tile_lsb = read(row_addr);
+                    let tile_msb = read(row_addr + 8);
 
                     for tile_col in 0..8 {
                         let lsb = (tile_lsb >> tile_col) & 0x01;
@@ -873,8 +1336,7 @@ impl Ppu {
 
                         let pixel = (msb << 1) | lsb;
                         let pixel_index = (i * 8 + tile_row) * 128 + (j * 8 + 7 - tile_col);
-                        // TODO: Don't hardcode palette
-                        buf[pixel_index as usize] = self.get_palette_color(0, pixel);
+                        buf[pixel_index as usize] = self.get_palette_color(palette & 0x07, pixel);
                     }
                 }
             }
@@ -882,9 +1344,66 @@ impl Ppu {
 
         Sprite::new(Vec::from(buf), 128, 128).expect("Failed to create sprite from pattern table")
     }
+
+    /// Renders one of the physical nametables as the background would appear,
+    /// where 2 and 3 are only used with four-screen mirroring,
+    /// using the pattern table currently selected for the background.
+    pub fn render_nametable(&self, index: usize) -> Sprite {
+        const ATTRIBUTE_TABLE_OFFSET: usize = 0x03C0;
+
+        let nametable = &self.nametables[index];
+        let pattern_table = PatternTable::from(self.ctrl.contains(PpuCtrl::BackgroundPatternTable));
+
+        let mut buf = vec![Color::default(); 256 * 240];
+
+        for tile_y in 0..30 {
+            for tile_x in 0..32 {
+                let tile_id = nametable[tile_y * 32 + tile_x];
+
+                // Same 2 bit selection as fetch_tile_palette_id
+                let mut attr_byte =
+                    nametable[ATTRIBUTE_TABLE_OFFSET + (tile_y / 4) * 8 + tile_x / 4];
+                if tile_x % 4 >= 2 {
+                    attr_byte >>= 2;
+                }
+                if tile_y % 4 >= 2 {
+                    attr_byte >>= 4;
+                }
+                let palette = attr_byte & 0x03;
+
+                for row in 0..8 {
+                    let lsb = self.fetch_tile_byte(pattern_table, tile_id, row as u8, false);
+                    let msb = self.fetch_tile_byte(pattern_table, tile_id, row as u8, true);
+
+                    for col in 0..8 {
+                        let pixel =
+                            (((msb >> (7 - col)) & 0x01) << 1) | ((lsb >> (7 - col)) & 0x01);
+                        let pixel_index = (tile_y * 8 + row) * 256 + tile_x * 8 + col;
+                        buf[pixel_index] = self.get_palette_color(palette, pixel);
+                    }
+                }
+            }
+        }
+
+        Sprite::new(buf, 256, 240).expect("Failed to create sprite from nametable")
+    }
+}
+
+/// Palette RAM is from $3F00 to $3F1F, but mirrored up to $3FFF.
+/// The first color of each sprite palette is shared with the background palette below it,
+/// so $3F10 is $3F00. $3F04, $3F08 and $3F0C have their own RAM, which is only drawn while
+/// rendering is off and v points at them (the background palette hack), since transparent
+/// pixels otherwise always show the backdrop at $3F00.
+/// See: https://www.nesdev.org/wiki/PPU_palettes#Memory_Map
+fn palette_ram_index(addr: u16) -> usize {
+    let i = (addr & 0x1F) as usize;
+    match i {
+        0x10 | 0x14 | 0x18 | 0x1C => i - 0x10,
+        i => i,
+    }
 }
 
-/// Returns nametable (0 or 1) as well as the index within the nametable
+/// Returns nametable (0-3) as well as the index within the nametable
 /// See: https://www.nesdev.org/wiki/Mirroring
 fn map_addr_to_nametable(mirroring: Mirroring, addr: u16) -> (usize, usize) {
     assert!(
@@ -911,5 +1430,665 @@ fn map_addr_to_nametable(mirroring: Mirroring, addr: u16) -> (usize, usize) {
         },
         Mirroring::SingleScreenLower => (0, addr & 0x03FF),
         Mirroring::SingleScreenUpper => (1, addr & 0x03FF),
+        Mirroring::FourScreen => ((addr - 0x2000) / 0x0400, addr & 0x03FF),
+        Mirroring::Mapped(nametables) => (nametables[(addr - 0x2000) / 0x0400], addr & 0x03FF),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ppu() -> Ppu {
+        // Built in, so the tests run without the io feature too
+        let palette = include_bytes!("../../../assets/palettes/2C02G.pal");
+        let mut ppu = Ppu::new(Palette::from_pal_bytes(palette).unwrap(), Region::Ntsc);
+        let rom = include_bytes!("../../../assets/test_roms/nestest.nes");
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        ppu.load_cartridge(Rc::new(RefCell::new(cartridge)));
+        ppu
+    }
+
+    #[test]
+    fn four_screen_mirroring() {
+        assert_eq!(map_addr_to_nametable(Mirroring::FourScreen, 0x2005), (0, 5));
+        assert_eq!(
+            map_addr_to_nametable(Mirroring::FourScreen, 0x27FF),
+            (1, 0x3FF)
+        );
+        assert_eq!(
+            map_addr_to_nametable(Mirroring::FourScreen, 0x2A00),
+            (2, 0x200)
+        );
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(map_addr_to_nametable(Mirroring::FourScreen, 0x3C01), (3, 1));
+    }
+
+    #[test]
+    fn nametable_mirror_at_3000() {
+        // nestest uses horizontal mirroring
+        let mut ppu = ppu();
+        ppu.write(0x2000, 0x11);
+        ppu.write(0x2C05, 0x22);
+        ppu.write(0x3EFF, 0x33);
+        assert_eq!(ppu.read(0x3000), 0x11);
+        assert_eq!(ppu.read(0x3400), 0x11);
+        assert_eq!(ppu.read(0x3C05), 0x22);
+        assert_eq!(ppu.read(0x2EFF), 0x33);
+        assert_eq!(ppu.read(0x2AFF), 0x33);
+    }
+
+    #[test]
+    fn background_palette_hack() {
+        let mut ppu = ppu();
+        ppu.write(0x3F00, 0x0F);
+        ppu.write(0x3F05, 0x21);
+        ppu.scanline = 10;
+        ppu.cycle = 5;
+        let color = |ppu: &Ppu| ppu.get_pixel().unwrap().0.color;
+        let backdrop = ppu.palette.get_color(0x0F).unwrap();
+
+        ppu.cpu_write(0x2006, 0x3F);
+        ppu.cpu_write(0x2006, 0x05);
+        assert_eq!(color(&ppu), ppu.palette.get_color(0x21).unwrap());
+
+        // $3F10 mirrors the backdrop
+        ppu.cpu_write(0x2006, 0x3F);
+        ppu.cpu_write(0x2006, 0x10);
+        assert_eq!(color(&ppu), backdrop);
+
+        // But $3F04 has its own RAM, which is only ever drawn like this
+        ppu.write(0x3F04, 0x16);
+        ppu.cpu_write(0x2006, 0x3F);
+        ppu.cpu_write(0x2006, 0x04);
+        assert_eq!(color(&ppu), ppu.palette.get_color(0x16).unwrap());
+        assert_ne!(color(&ppu), backdrop);
+
+        ppu.cpu_write(0x2006, 0x20);
+        ppu.cpu_write(0x2006, 0x05);
+        assert_eq!(color(&ppu), backdrop);
+
+        // Only while rendering is off
+        ppu.cpu_write(0x2006, 0x3F);
+        ppu.cpu_write(0x2006, 0x05);
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        assert_eq!(color(&ppu), backdrop);
+    }
+
+    #[test]
+    fn palette_mirrors() {
+        let mut ppu = ppu();
+        for i in 0..0x20 {
+            ppu.write(0x3F00 + i, i as u8);
+        }
+
+        // The sprite palettes' first colors are the background palettes'
+        assert_eq!(ppu.read(0x3F10), 0x10);
+        assert_eq!(ppu.read(0x3F00), 0x10);
+        assert_eq!(ppu.read(0x3F1C), 0x1C);
+        assert_eq!(ppu.read(0x3F0C), 0x1C);
+        // But the background palettes' own first colors aren't the backdrop
+        ppu.write(0x3F00, 0x0F);
+        assert_eq!(ppu.read(0x3F04), 0x14);
+        assert_eq!(ppu.read(0x3F08), 0x18);
+        // Palette RAM repeats every 32 bytes
+        assert_eq!(ppu.read(0x3F25), 0x05);
+        assert_eq!(ppu.read(0x3FF0), 0x0F);
+
+        // Rendering looks colors up through the same mirrors
+        assert_eq!(
+            ppu.get_palette_color(0, 0),
+            ppu.palette.get_color(0x0F).unwrap()
+        );
+        assert_eq!(
+            ppu.get_palette_color(1, 1),
+            ppu.palette.get_color(0x05).unwrap()
+        );
+    }
+
+    #[test]
+    fn mmc3_counts_scanlines() {
+        // Mapper 4 ROM with CHR RAM
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 2, 0, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.extend([0; 32 * 1024]);
+        let cartridge = Rc::new(RefCell::new(Cartridge::from_bytes(&rom).unwrap()));
+        let mut ppu = ppu();
+        ppu.load_cartridge(cartridge.clone());
+
+        // Background from $0000 and sprites from $1000, with an IRQ after 10 scanlines
+        ppu.cpu_write(0x2000, PpuCtrl::SpritePatternTable.bits());
+        ppu.cpu_write(
+            0x2001,
+            (PpuMask::ShowBackground | PpuMask::ShowSprites).bits(),
+        );
+        for (addr, data) in [(0xC000, 9), (0xC001, 0), (0xE001, 0)] {
+            cartridge.borrow_mut().cpu_write(addr, data).unwrap();
+        }
+
+        let mut dots = 0;
+        while ppu.scanline() < 240 {
+            ppu.clock();
+            dots += 1;
+            if dots % 3 == 0 && cartridge.borrow_mut().on_cpu_cycle() {
+                break;
+            }
+        }
+        // Counted on the pre-render line, then fired on the 9th visible one,
+        // just after the first sprite fetch
+        assert_eq!(ppu.scanline(), 8);
+        assert!((261..270).contains(&ppu.cycle()));
+    }
+
+    #[test]
+    fn mmc5_irq_at_compare_scanline() {
+        // Mapper 5 ROM with CHR RAM
+        let mut rom = vec![
+            b'N', b'E', b'S', 0x1A, 2, 0, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.extend([0; 32 * 1024]);
+        let cartridge = Rc::new(RefCell::new(Cartridge::from_bytes(&rom).unwrap()));
+        let mut ppu = ppu();
+        ppu.load_cartridge(cartridge.clone());
+
+        ppu.cpu_write(0x2001, PpuMask::ShowBackground.bits());
+        for (addr, data) in [(0x5203, 10), (0x5204, 0x80)] {
+            cartridge.borrow_mut().cpu_write(addr, data).unwrap();
+        }
+
+        let mut dots = 0;
+        while ppu.scanline() < 240 {
+            ppu.clock();
+            dots += 1;
+            if dots % 3 == 0 && cartridge.borrow_mut().on_cpu_cycle() {
+                break;
+            }
+        }
+        // Fired in the horizontal blank just before scanline 10
+        assert_eq!(ppu.scanline(), 10);
+        assert!(ppu.cycle() < 3);
+    }
+
+    #[test]
+    fn palette_read_fills_buffer_from_nametable() {
+        let mut ppu = ppu();
+        ppu.write(0x2F05, 0x42);
+        ppu.write(0x3F05, 0x17);
+
+        ppu.cpu_write(0x2006, 0x3F);
+        ppu.cpu_write(0x2006, 0x05);
+        assert_eq!(ppu.cpu_read(0x2007), 0x17);
+
+        // The next buffered read returns the nametable byte under the palette
+        ppu.cpu_write(0x2006, 0x20);
+        ppu.cpu_write(0x2006, 0x00);
+        assert_eq!(ppu.cpu_read(0x2007), 0x42);
+    }
+
+    #[test]
+    fn io_latch_decay() {
+        let mut ppu = ppu();
+        ppu.write(0x3F00, 0x01);
+        let run_frames = |ppu: &mut Ppu, frames: u32| {
+            for _ in 0..frames * Region::Ntsc.frame_clocks() {
+                ppu.clock();
+            }
+        };
+
+        // Write-only registers read back the last value written to any register
+        ppu.cpu_write(0x2003, 0xDE);
+        assert_eq!(ppu.cpu_read(0x2000), 0xDE);
+        assert_eq!(ppu.cpu_read(0x2002) & 0x1F, 0x1E);
+
+        // Palette reads only drive the low 6 bits
+        ppu.vram_addr = VRAMAddr::from(0x3F00);
+        ppu.cpu_write(0x2003, 0xC0);
+        assert_eq!(ppu.cpu_read(0x2007), 0xC1);
+
+        // Bits fade after about 36 frames without being refreshed
+        ppu.cpu_write(0x2003, 0xFF);
+        run_frames(&mut ppu, 20);
+        assert_eq!(ppu.cpu_read(0x2005), 0xFF);
+        ppu.vram_addr = VRAMAddr::from(0x3F00);
+        ppu.cpu_read(0x2007);
+        run_frames(&mut ppu, 20);
+        assert_eq!(ppu.cpu_read(0x2005), 0x01);
+        run_frames(&mut ppu, 20);
+        assert_eq!(ppu.cpu_read(0x2005), 0x00);
+    }
+
+    #[test]
+    fn emphasize_red_darkens_green_and_blue() {
+        let mut ppu = ppu();
+        // Light grey, so every channel is non-zero
+        ppu.write(0x3F01, 0x10);
+        let plain = ppu.get_palette_color(0, 1);
+
+        ppu.cpu_write(0x2001, PpuMask::EmphasizeRed.bits());
+        let emphasized = ppu.get_palette_color(0, 1);
+
+        assert_eq!(emphasized.0, plain.0);
+        assert!(emphasized.1 < plain.1);
+        assert!(emphasized.2 < plain.2);
+    }
+
+    #[test]
+    fn pal_swaps_red_and_green_emphasis() {
+        let mut ppu = ppu();
+        ppu.set_region(Region::Pal);
+        ppu.write(0x3F01, 0x10);
+        let plain = ppu.get_palette_color(0, 1);
+
+        // Bit 5 emphasizes green on PAL
+        ppu.cpu_write(0x2001, PpuMask::EmphasizeRed.bits());
+        let emphasized = ppu.get_palette_color(0, 1);
+        assert_eq!(emphasized.1, plain.1);
+        assert!(emphasized.0 < plain.0);
+        assert!(emphasized.2 < plain.2);
+
+        ppu.cpu_write(0x2001, PpuMask::EmphasizeGreen.bits());
+        let emphasized = ppu.get_palette_color(0, 1);
+        assert_eq!(emphasized.0, plain.0);
+        assert!(emphasized.1 < plain.1);
+    }
+
+    #[test]
+    fn greyscale_masks_palette_index() {
+        let mut ppu = ppu();
+        ppu.write(0x3F01, 0x16);
+        ppu.write(0x3F02, 0x10);
+
+        ppu.cpu_write(0x2001, PpuMask::Greyscale.bits());
+        assert_eq!(
+            ppu.get_palette_color(0, 1),
+            ppu.palette.get_color(0x10).unwrap()
+        );
+        assert_eq!(
+            ppu.get_palette_color(0, 2),
+            ppu.palette.get_color(0x10).unwrap()
+        );
+    }
+
+    /// Runs sprite evaluation for `scanline`, returning the cycle the overflow flag was set on.
+    fn evaluate_scanline(ppu: &mut Ppu, scanline: i16) -> Option<u16> {
+        ppu.scanline = scanline;
+        ppu.cycle = 0;
+        ppu.status.remove(PpuStatus::SpriteOverflow);
+
+        let mut overflow_cycle = None;
+        while ppu.cycle <= 257 {
+            ppu.clock();
+            if overflow_cycle.is_none() && ppu.status.contains(PpuStatus::SpriteOverflow) {
+                overflow_cycle = Some(ppu.cycle - 1);
+            }
+        }
+        overflow_cycle
+    }
+
+    #[test]
+    fn sprite_evaluation_overflow() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        ppu.oam = [0xF0; OAM_SIZE];
+
+        // 9 sprites on scanline 20, starting from sprite 1
+        for n in 1..10 {
+            ppu.oam[n * 4] = 15;
+        }
+        let overflow_cycle = evaluate_scanline(&mut ppu, 20);
+
+        let indices: Vec<_> = ppu.scanline_sprites.iter().map(|s| s.oam_index).collect();
+        assert_eq!(indices, [1, 2, 3, 4, 5, 6, 7, 8]);
+        // 2 cycles to skip sprite 0, 8 to copy each of the others, then 2 to check sprite 9
+        assert_eq!(overflow_cycle, Some(65 + 2 + 8 * 8 + 1));
+
+        // Only 8 sprites, so the flag is never set
+        ppu.oam[9 * 4] = 0xF0;
+        assert_eq!(evaluate_scanline(&mut ppu, 20), None);
+
+        // Not evaluated while rendering is disabled
+        ppu.oam[9 * 4] = 15;
+        ppu.cpu_write(0x2001, 0);
+        assert_eq!(evaluate_scanline(&mut ppu, 20), None);
+    }
+
+    #[test]
+    fn reset_keeps_memory_and_ignores_writes() {
+        let mut ppu = ppu();
+        ppu.power_on();
+        assert_eq!(ppu.palette_ram, POWER_ON_PALETTE);
+
+        // Registers can't be written until the end of the first frame's vertical blank
+        ppu.cpu_write(0x2000, PpuCtrl::GenerateNMI.bits());
+        assert_eq!(ppu.ctrl, PpuCtrl::empty());
+        while ppu.warming_up {
+            ppu.clock();
+        }
+        assert_eq!((ppu.scanline, ppu.cycle), (-1, 2));
+
+        ppu.oam[0] = 0x12;
+        ppu.palette_ram[0] = 0x0F;
+        ppu.cpu_write(0x2000, PpuCtrl::GenerateNMI.bits());
+        ppu.cpu_write(0x2005, 0x10);
+        ppu.reset();
+
+        assert_eq!(ppu.ctrl, PpuCtrl::empty());
+        assert!(!ppu.write_latch);
+        assert_eq!(ppu.oam[0], 0x12);
+        assert_eq!(ppu.palette_ram[0], 0x0F);
+        ppu.cpu_write(0x2001, PpuMask::ShowBackground.bits());
+        assert_eq!(ppu.mask, PpuMask::empty());
+        // OAM can still be written
+        ppu.cpu_write(0x2004, 0x34);
+        assert_eq!(ppu.oam[0], 0x34);
+    }
+
+    #[test]
+    fn sprite_limit_disabled() {
+        let mut ppu = ppu();
+        ppu.set_sprite_limit_enabled(false);
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        ppu.oam = [0xF0; OAM_SIZE];
+
+        // 12 sprites on scanline 20
+        for n in 0..12 {
+            ppu.oam[n * 4] = 15;
+        }
+        let overflow_cycle = evaluate_scanline(&mut ppu, 20);
+
+        let indices: Vec<_> = ppu.scanline_sprites.iter().map(|s| s.oam_index).collect();
+        assert_eq!(indices, (0..12).collect::<Vec<_>>());
+        // The overflow flag is still set as usual
+        assert_eq!(overflow_cycle, Some(65 + 8 * 8 + 1));
+    }
+
+    #[test]
+    fn sprite0_hit_under_another_sprite() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2001, 0x1E);
+        ppu.scanline = 20;
+        ppu.cycle = 100;
+
+        // Opaque background everywhere
+        ppu.bg_tile_id_shifter.load(0xFF, 0xFF);
+        for _ in 0..8 {
+            ppu.bg_tile_id_shifter.shift();
+        }
+
+        // Sprite 5 is in front of sprite 0, which is only in the second slot
+        let sprite = |oam_index| PpuSprite {
+            x: 0,
+            y: 20,
+            tile_id: 0,
+            attribute: SpriteAttribute::empty(),
+            oam_index,
+        };
+        ppu.scanline_sprites = vec![sprite(5), sprite(0)];
+        ppu.sprite_tile_shifters[0].load(0xFF, 0x00);
+        ppu.sprite_tile_shifters[1].load(0x00, 0xFF);
+
+        assert_eq!(ppu.get_sprite_pixel().pixel, 1);
+        let (_, hit) = ppu.get_pixel().unwrap();
+        assert!(hit);
+
+        // Transparent sprite 0 under an opaque sprite doesn't hit
+        ppu.sprite_tile_shifters[1].load(0x00, 0x00);
+        let (_, hit) = ppu.get_pixel().unwrap();
+        assert!(!hit);
+    }
+
+    #[test]
+    fn raster_markers() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2001, 0x1E);
+        ppu.scanline = 20;
+        ppu.cycle = 100;
+        ppu.bg_tile_id_shifter.load(0xFF, 0xFF);
+        for _ in 0..8 {
+            ppu.bg_tile_id_shifter.shift();
+        }
+        ppu.scanline_sprites = vec![PpuSprite {
+            x: 0,
+            y: 20,
+            tile_id: 0,
+            attribute: SpriteAttribute::empty(),
+            oam_index: 0,
+        }];
+        ppu.sprite_tile_shifters[0].load(0xFF, 0xFF);
+
+        ppu.clock();
+        assert_eq!(ppu.sprite_zero_hit_scanline(), Some(20));
+        assert_eq!(ppu.mapper_irq_scanline(), None);
+
+        ppu.scanline = 200;
+        ppu.mark_mapper_irq();
+        assert_eq!(ppu.mapper_irq_scanline(), Some(200));
+
+        // Both are cleared when the next frame starts, and kept for the finished one
+        while ppu.scanline() != -1 || ppu.cycle() < 2 {
+            ppu.clock();
+        }
+        assert_eq!(ppu.sprite_zero_hit_scanline(), None);
+        assert_eq!(ppu.mapper_irq_scanline(), None);
+        assert_eq!(ppu.last_frame_sprite_zero_hit_scanline(), Some(20));
+        assert_eq!(ppu.last_frame_mapper_irq_scanline(), Some(200));
+    }
+
+    #[test]
+    fn oam_data() {
+        let mut ppu = ppu();
+        ppu.scanline = 241;
+        ppu.cpu_write(0x2003, 0x00);
+        for data in [0x10, 0x20, 0xFF, 0x30] {
+            ppu.cpu_write(0x2004, data);
+        }
+
+        // The unused attribute bits read as 0
+        ppu.cpu_write(0x2003, 0x00);
+        let bytes: Vec<_> = (0..4)
+            .map(|i| {
+                ppu.cpu_write(0x2003, i);
+                ppu.cpu_read(0x2004)
+            })
+            .collect();
+        assert_eq!(bytes, [0x10, 0x20, 0xE3, 0x30]);
+
+        // Writes while rendering are dropped, and bump the address to the next sprite
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        ppu.scanline = 20;
+        ppu.cycle = 300;
+        ppu.cpu_write(0x2003, 0x01);
+        ppu.cpu_write(0x2004, 0x55);
+        assert_eq!(ppu.oam_addr(), 0x05);
+        assert_eq!(ppu.oam[1], 0x20);
+    }
+
+    #[test]
+    fn oam_addr_cleared_by_sprite_fetches() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2003, 0x12);
+        run_to(&mut ppu, 0, 300);
+        assert_eq!(ppu.oam_addr(), 0x12);
+
+        ppu.cpu_write(0x2001, PpuMask::ShowBackground.bits());
+        run_to(&mut ppu, 0, 256);
+        ppu.cpu_write(0x2003, 0x12);
+        ppu.clock();
+        assert_eq!(ppu.oam_addr(), 0x12);
+        ppu.clock();
+        assert_eq!(ppu.oam_addr(), 0x00);
+
+        // Written after the fetches, it lasts until the next line's
+        run_to(&mut ppu, 0, 321);
+        ppu.cpu_write(0x2003, 0x34);
+        run_to(&mut ppu, 1, 100);
+        assert_eq!(ppu.oam_addr(), 0x34);
+
+        // Not on lines that aren't rendered
+        run_to(&mut ppu, 241, 300);
+        assert_eq!(ppu.oam_addr(), 0x00);
+        ppu.cpu_write(0x2003, 0x34);
+        run_to(&mut ppu, 250, 300);
+        assert_eq!(ppu.oam_addr(), 0x34);
+    }
+
+    #[test]
+    fn oam_data_reads_while_rendering() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        ppu.oam = [0xF0; OAM_SIZE];
+        ppu.oam[..4].copy_from_slice(&[15, 0x42, 0x01, 0x80]);
+        evaluate_scanline(&mut ppu, 20);
+        ppu.cpu_write(0x2003, 0x01);
+
+        let read_at = |ppu: &mut Ppu, cycle| {
+            ppu.cycle = cycle;
+            ppu.cpu_read(0x2004)
+        };
+        assert_eq!(read_at(&mut ppu, 10), 0xFF);
+        // Sprite 0's tile, attribute and X, then the X for the remaining cycles
+        assert_eq!(read_at(&mut ppu, 258), 0x42);
+        assert_eq!(read_at(&mut ppu, 259), 0x01);
+        assert_eq!(read_at(&mut ppu, 260), 0x80);
+        assert_eq!(read_at(&mut ppu, 263), 0x80);
+        // Evaluation copies every sprite's Y before checking it, so the next slot has the last Y
+        assert_eq!(read_at(&mut ppu, 265), 0xF0);
+        assert_eq!(read_at(&mut ppu, 266), 0xFF);
+        assert_eq!(read_at(&mut ppu, 330), 15);
+
+        // Outside of rendering, OAM is read at OAMADDR
+        ppu.scanline = 241;
+        assert_eq!(ppu.cpu_read(0x2004), 0x42);
+    }
+
+    #[test]
+    fn sprite_overflow_bug() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2001, PpuMask::ShowSprites.bits());
+        ppu.oam = [0xF0; OAM_SIZE];
+
+        for n in 0..8 {
+            ppu.oam[n * 4] = 15;
+        }
+        // After 8 sprites are found, sprite 9's tile index is checked instead of its Y
+        ppu.oam[9 * 4 + 1] = 15;
+        assert!(evaluate_scanline(&mut ppu, 20).is_some());
+
+        // And sprite 9's real Y coordinate is missed
+        ppu.oam[9 * 4 + 1] = 0xF0;
+        ppu.oam[9 * 4] = 15;
+        assert_eq!(evaluate_scanline(&mut ppu, 20), None);
+    }
+
+    #[test]
+    fn render_nametable_uses_attribute_palettes() {
+        let mut ppu = ppu();
+        for i in 1..4 {
+            ppu.write(0x3F00 + i, 0x16);
+            ppu.write(0x3F04 + i, 0x2A);
+        }
+        // The letter "A" in nestest's font, once under palette 1 and once under palette 0
+        ppu.write(0x2000, 0x41);
+        ppu.write(0x2002, 0x41);
+        ppu.write(0x23C0, 0x01);
+
+        let sprite = ppu.render_nametable(0);
+        assert_eq!((sprite.width(), sprite.height()), (256, 240));
+
+        let tile_colors = |tile_x: usize| -> Vec<Color> {
+            (0..8)
+                .flat_map(|row| {
+                    let start = row * 256 + tile_x * 8;
+                    sprite.pixels()[start..start + 8].to_vec()
+                })
+                .collect()
+        };
+        let red = ppu.palette.get_color(0x16).unwrap();
+        let green = ppu.palette.get_color(0x2A).unwrap();
+
+        assert!(tile_colors(0).contains(&green));
+        assert!(!tile_colors(0).contains(&red));
+        assert!(tile_colors(2).contains(&red));
+        assert!(!tile_colors(2).contains(&green));
+    }
+
+    #[test]
+    fn pattern_table_palette_selection() {
+        let mut ppu = ppu();
+        for i in 1..4 {
+            ppu.write(0x3F00 + i, 0x16);
+            ppu.write(0x3F1C + i, 0x2A);
+        }
+        let red = ppu.palette.get_color(0x16).unwrap();
+        let green = ppu.palette.get_color(0x2A).unwrap();
+
+        let background = ppu.get_pattern_table(PatternTable::Left, 0);
+        assert!(background.pixels().contains(&red));
+        assert!(!background.pixels().contains(&green));
+
+        let sprites = ppu.get_pattern_table(PatternTable::Left, 7);
+        assert!(sprites.pixels().contains(&green));
+        assert!(!sprites.pixels().contains(&red));
+
+        let colors = ppu.palette_colors();
+        assert_eq!((colors[1], colors[29]), (red, green));
+        assert_eq!(sprites.to_rgba().len(), 128 * 128 * 4);
+    }
+
+    #[test]
+    fn pattern_table_banked() {
+        let mut ppu = ppu();
+        for (i, color) in [0x0F, 0x16, 0x2A, 0x30].into_iter().enumerate() {
+            ppu.write(0x3F00 + i as u16, color);
+        }
+        let pixels = |sprite: Sprite| sprite.pixels().to_vec();
+        let left = pixels(ppu.get_pattern_table(PatternTable::Left, 0));
+        let right = pixels(ppu.get_pattern_table(PatternTable::Right, 0));
+        assert_ne!(left, right);
+
+        // nestest has 8 KB of CHR ROM mapped straight in, so banks 4-7 are the right table
+        assert_eq!(pixels(ppu.get_pattern_table_banked([0, 1, 2, 3], 0)), left);
+        assert_eq!(pixels(ppu.get_pattern_table_banked([4, 5, 6, 7], 0)), right);
+        // Banks past the end wrap around
+        assert_eq!(
+            pixels(ppu.get_pattern_table_banked([8, 9, 10, 11], 0)),
+            left
+        );
+    }
+
+    fn run_to(ppu: &mut Ppu, scanline: i16, cycle: u16) {
+        while (ppu.scanline, ppu.cycle) != (scanline, cycle) {
+            ppu.clock();
+        }
+    }
+
+    #[test]
+    fn status_read_before_vblank_suppresses_it() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2000, PpuCtrl::GenerateNMI.bits());
+
+        // On the dot before the flag is set
+        run_to(&mut ppu, 241, 1);
+        assert_eq!(ppu.cpu_read(0x2002) & 0x80, 0x00);
+        ppu.clock();
+        assert!(!ppu.status.contains(PpuStatus::VerticalBlank));
+        assert!(!ppu.nmi_output());
+
+        // Only for that frame
+        ppu.clock();
+        run_to(&mut ppu, 241, 2);
+        assert!(ppu.nmi_output());
+    }
+
+    #[test]
+    fn status_read_as_vblank_starts_clears_nmi() {
+        let mut ppu = ppu();
+        ppu.cpu_write(0x2000, PpuCtrl::GenerateNMI.bits());
+
+        run_to(&mut ppu, 241, 2);
+        assert!(ppu.nmi_output());
+        assert_eq!(ppu.cpu_read(0x2002) & 0x80, 0x80);
+        assert!(!ppu.nmi_output());
     }
 }
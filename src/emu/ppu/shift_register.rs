@@ -31,11 +31,6 @@ impl ShiftRegister16 {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn get(&self) -> u8 {
-        self.get_at(0)
-    }
-
     pub fn get_at(&self, bit_num: u8) -> u8 {
         assert!(bit_num < 16);
 
@@ -46,12 +41,6 @@ impl ShiftRegister16 {
 
         (high << 1) | low
     }
-
-    #[allow(dead_code)]
-    pub fn clear(&mut self) {
-        self.low = 0x0000;
-        self.high = 0x0000;
-    }
 }
 
 /// A shift register with 8 slots that outputs 2 bits at a time.
@@ -99,10 +88,4 @@ impl ShiftRegister8 {
 
         (high << 1) | low
     }
-
-    #[allow(dead_code)]
-    pub fn clear(&mut self) {
-        self.low = 0x00;
-        self.high = 0x00;
-    }
 }
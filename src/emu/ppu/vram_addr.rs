@@ -1,3 +1,6 @@
+// modular_bitfield expands field types with redundant parentheses
+#![allow(unused_parens)]
+
 use modular_bitfield::prelude::*;
 
 #[bitfield]
@@ -0,0 +1,119 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+/// What the CPU's RAM holds when the console is switched on.
+/// Real RAM starts out with a mostly random pattern that differs between consoles,
+/// which some games use to seed their random numbers.
+/// See: https://www.nesdev.org/wiki/CPU_power_up_state#RAM_contents
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    Ones,
+    Pattern(u8),
+    /// The same seed always gives the same RAM, so runs can be reproduced.
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeros => ram.fill(0x00),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::Pattern(byte) => ram.fill(byte),
+            RamInit::Random(seed) => {
+                let mut state = seed;
+                for byte in ram {
+                    *byte = splitmix64(&mut state) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// See: https://prng.di.unimi.it/splitmix64.c
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Parses "zeros", "ones", "pattern:<hex byte>" or "random:<seed>". There's no clock to pick a
+/// seed from everywhere the emulator runs (like wasm), so a plain "random" has to be resolved
+/// to a seed by the frontend first.
+impl FromStr for RamInit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.to_ascii_lowercase();
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s.as_str(), None),
+        };
+
+        match (kind, arg) {
+            ("zeros", None) => Ok(RamInit::Zeros),
+            ("ones", None) => Ok(RamInit::Ones),
+            ("pattern", Some(byte)) => u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+                .map(RamInit::Pattern)
+                .map_err(|_| anyhow!("Invalid RAM pattern {:?}, expected a hex byte", byte)),
+            ("random", Some(seed)) => seed
+                .parse()
+                .map(RamInit::Random)
+                .map_err(|_| anyhow!("Invalid RAM seed {:?}, expected a number", seed)),
+            ("random", None) => Err(anyhow!("Random RAM needs a seed, like random:1234")),
+            _ => Err(anyhow!(
+                "Invalid RAM init {:?}, expected zeros, ones, pattern:<byte> or random[:<seed>]",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for RamInit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RamInit::Zeros => write!(f, "zeros"),
+            RamInit::Ones => write!(f, "ones"),
+            RamInit::Pattern(byte) => write!(f, "pattern:{:02X}", byte),
+            RamInit::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fill() {
+        let mut ram = [0x12; 16];
+        RamInit::Pattern(0xA5).fill(&mut ram);
+        assert!(ram.iter().all(|&byte| byte == 0xA5));
+        RamInit::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|&byte| byte == 0xFF));
+
+        let mut other = [0; 16];
+        RamInit::Random(7).fill(&mut ram);
+        RamInit::Random(7).fill(&mut other);
+        assert_eq!(ram, other);
+        RamInit::Random(8).fill(&mut other);
+        assert_ne!(ram, other);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("zeros".parse::<RamInit>().unwrap(), RamInit::Zeros);
+        assert_eq!(
+            "pattern:0xFF".parse::<RamInit>().unwrap(),
+            RamInit::Pattern(0xFF)
+        );
+        assert_eq!("Random:42".parse::<RamInit>().unwrap(), RamInit::Random(42));
+        assert!("random".parse::<RamInit>().is_err());
+        assert!("pattern".parse::<RamInit>().is_err());
+        assert!("pattern:1FF".parse::<RamInit>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+/// The TV standard a console was built for, which sets all of its timing.
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    /// PPU clocks per second.
+    pub fn clock_speed(self) -> usize {
+        match self {
+            Region::Ntsc => 5369318,
+            Region::Pal => 5320343,
+        }
+    }
+
+    pub fn fps(self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal => 50.0070,
+        }
+    }
+
+    pub fn frame_time(self) -> f64 {
+        1.0 / self.fps()
+    }
+
+    /// Scanlines per frame, including the pre-render line and vertical blank.
+    pub fn scanlines(self) -> i16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal => 312,
+        }
+    }
+
+    /// PPU clocks per frame.
+    pub fn frame_clocks(self) -> u32 {
+        341 * self.scanlines() as u32
+    }
+
+    /// Whether the CPU runs on the given PPU clock.
+    /// The NTSC CPU runs once every 3 PPU clocks, and the PAL CPU once every 3.2.
+    pub fn is_cpu_clock(self, ppu_clock: u64) -> bool {
+        match self {
+            Region::Ntsc => ppu_clock.is_multiple_of(3),
+            // 5 evenly spaced CPU clocks every 16 PPU clocks
+            Region::Pal => (ppu_clock * 5) % 16 < 5,
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            _ => Err(anyhow!("Invalid region {:?}, expected NTSC or PAL", s)),
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_clock_ratio() {
+        let cpu_clocks = |region: Region| (0..48).filter(|&i| region.is_cpu_clock(i)).count();
+        assert_eq!(cpu_clocks(Region::Ntsc), 16);
+        assert_eq!(cpu_clocks(Region::Pal), 15);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("pal".parse::<Region>().unwrap(), Region::Pal);
+        assert_eq!("NTSC".parse::<Region>().unwrap(), Region::Ntsc);
+        assert!("secam".parse::<Region>().is_err());
+    }
+}
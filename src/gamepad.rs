@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::emu::input::ControllerButtons;
+
+// The face buttons are laid out like the NES controller, with B to the left of A
+const BUTTONS: [(Button, ControllerButtons); 8] = [
+    (Button::East, ControllerButtons::A),
+    (Button::South, ControllerButtons::B),
+    (Button::Select, ControllerButtons::Select),
+    (Button::Start, ControllerButtons::Start),
+    (Button::DPadUp, ControllerButtons::Up),
+    (Button::DPadDown, ControllerButtons::Down),
+    (Button::DPadLeft, ControllerButtons::Left),
+    (Button::DPadRight, ControllerButtons::Right),
+];
+const TURBO_BUTTONS: [(Button, ControllerButtons); 2] = [
+    (Button::North, ControllerButtons::A),
+    (Button::West, ControllerButtons::B),
+];
+
+/// Player 1's buttons from a gamepad, read alongside the keyboard.
+/// The first connected gamepad is used, moving on to another one if it's unplugged.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+    // How far the left stick has to be pushed to count as the D-pad, from 0 to 1
+    stick_deadzone: f32,
+}
+
+impl Gamepads {
+    /// `stick_deadzone` is how far the left stick has to be pushed along an axis to press
+    /// that direction, from 0 to 1.
+    pub fn new(stick_deadzone: f32) -> Result<Self> {
+        let gilrs = Gilrs::new().map_err(|err| anyhow!("Failed to open gamepads: {err}"))?;
+        let active = gilrs.gamepads().next().map(|(id, gamepad)| {
+            log::info!("Using gamepad: {}", gamepad.name());
+            id
+        });
+
+        Ok(Gamepads {
+            gilrs,
+            active,
+            stick_deadzone,
+        })
+    }
+
+    /// Handles gamepads being plugged in and unplugged, call before reading the buttons.
+    pub fn update(&mut self) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected if self.active.is_none() => {
+                    log::info!("Using gamepad: {}", self.gilrs.gamepad(event.id).name());
+                    self.active = Some(event.id);
+                }
+                EventType::Disconnected if self.active == Some(event.id) => {
+                    log::info!("Gamepad disconnected");
+                    self.active = self
+                        .gilrs
+                        .gamepads()
+                        .map(|(id, _)| id)
+                        .find(|&id| id != event.id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The buttons held down, with the left stick working as the D-pad.
+    pub fn held_buttons(&self) -> ControllerButtons {
+        let Some(gamepad) = self.active.and_then(|id| self.gilrs.connected_gamepad(id)) else {
+            return ControllerButtons::empty();
+        };
+
+        pressed(&BUTTONS, |button| gamepad.is_pressed(button))
+            | stick_direction(
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+                self.stick_deadzone,
+            )
+    }
+
+    /// The held buttons that are bound to turbo A and B, see `TurboState::apply`.
+    pub fn held_turbo_buttons(&self) -> ControllerButtons {
+        match self.active.and_then(|id| self.gilrs.connected_gamepad(id)) {
+            Some(gamepad) => pressed(&TURBO_BUTTONS, |button| gamepad.is_pressed(button)),
+            None => ControllerButtons::empty(),
+        }
+    }
+}
+
+fn pressed<F>(mapping: &[(Button, ControllerButtons)], is_pressed: F) -> ControllerButtons
+where
+    F: Fn(Button) -> bool,
+{
+    mapping
+        .iter()
+        .filter(|&&(button, _)| is_pressed(button))
+        .fold(ControllerButtons::empty(), |held, &(_, nes_button)| {
+            held | nes_button
+        })
+}
+
+/// Positive Y is up, like gilrs reports it. Each axis is checked on its own,
+/// so pushing diagonally past the deadzone presses both directions.
+fn stick_direction(x: f32, y: f32, deadzone: f32) -> ControllerButtons {
+    let mut buttons = ControllerButtons::empty();
+    buttons.set(ControllerButtons::Left, x <= -deadzone);
+    buttons.set(ControllerButtons::Right, x >= deadzone);
+    buttons.set(ControllerButtons::Down, y <= -deadzone);
+    buttons.set(ControllerButtons::Up, y >= deadzone);
+    buttons
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn button_mapping() {
+        let held = pressed(&BUTTONS, |button| {
+            matches!(button, Button::South | Button::Start | Button::DPadLeft)
+        });
+        assert_eq!(
+            held,
+            ControllerButtons::B | ControllerButtons::Start | ControllerButtons::Left
+        );
+    }
+
+    #[test]
+    fn stick_dead_zone() {
+        assert_eq!(stick_direction(0.3, -0.2, 0.5), ControllerButtons::empty());
+        assert_eq!(
+            stick_direction(0.8, -0.6, 0.5),
+            ControllerButtons::Right | ControllerButtons::Down
+        );
+        assert_eq!(
+            stick_direction(-1.0, 1.0, 0.5),
+            ControllerButtons::Left | ControllerButtons::Up
+        );
+
+        // A smaller deadzone picks up lighter pushes
+        assert_eq!(
+            stick_direction(0.3, -0.2, 0.2),
+            ControllerButtons::Right | ControllerButtons::Down
+        );
+        assert_eq!(stick_direction(0.1, 0.25, 0.2), ControllerButtons::Up);
+    }
+}
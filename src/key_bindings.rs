@@ -0,0 +1,188 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{de::value::StrDeserializer, Deserialize};
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::emu::input::ControllerButtons;
+
+type Bindings = HashMap<KeyCode, ControllerButtons>;
+
+/// Which keyboard keys are bound to each controller's buttons.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    pub one: Bindings,
+    pub two: Bindings,
+    // Buttons that are pulsed on and off while their key is held
+    pub turbo_one: Bindings,
+    pub turbo_two: Bindings,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            one: HashMap::from([
+                (KeyCode::KeyX, ControllerButtons::A),
+                (KeyCode::KeyZ, ControllerButtons::B),
+                (KeyCode::KeyA, ControllerButtons::Select),
+                (KeyCode::KeyS, ControllerButtons::Start),
+                (KeyCode::ArrowUp, ControllerButtons::Up),
+                (KeyCode::ArrowDown, ControllerButtons::Down),
+                (KeyCode::ArrowLeft, ControllerButtons::Left),
+                (KeyCode::ArrowRight, ControllerButtons::Right),
+            ]),
+            two: HashMap::from([
+                (KeyCode::Numpad3, ControllerButtons::A),
+                (KeyCode::Numpad1, ControllerButtons::B),
+                (KeyCode::Numpad7, ControllerButtons::Select),
+                (KeyCode::Numpad9, ControllerButtons::Start),
+                (KeyCode::Numpad8, ControllerButtons::Up),
+                (KeyCode::Numpad5, ControllerButtons::Down),
+                (KeyCode::Numpad4, ControllerButtons::Left),
+                (KeyCode::Numpad6, ControllerButtons::Right),
+            ]),
+            turbo_one: HashMap::from([
+                (KeyCode::KeyC, ControllerButtons::A),
+                (KeyCode::KeyV, ControllerButtons::B),
+            ]),
+            turbo_two: HashMap::from([
+                (KeyCode::NumpadDecimal, ControllerButtons::A),
+                (KeyCode::Numpad0, ControllerButtons::B),
+            ]),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = fs::read_to_string(path)?;
+        KeyBindings::parse(&config)
+    }
+
+    /// Parses bindings from a config file that looks like:
+    /// ```text
+    /// [player1]
+    /// A = KeyX
+    /// Start = Enter
+    /// TurboA = KeyC
+    ///
+    /// [player2]
+    /// A = Numpad3
+    /// ```
+    /// Key names are the same as winit's `KeyCode` variants.
+    /// Each player section replaces that player's default bindings,
+    /// and unknown key names are skipped with a warning.
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut bindings = KeyBindings::default();
+        let mut current: Option<(&mut Bindings, &mut Bindings)> = None;
+
+        for (i, line) in config.lines().enumerate() {
+            let line_num = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let (player, turbo) = match section.trim() {
+                    "player1" => (&mut bindings.one, &mut bindings.turbo_one),
+                    "player2" => (&mut bindings.two, &mut bindings.turbo_two),
+                    other => return Err(anyhow!("Line {}: unknown section [{}]", line_num, other)),
+                };
+                player.clear();
+                turbo.clear();
+                current = Some((player, turbo));
+                continue;
+            }
+
+            let (button, key) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Line {}: expected <button> = <key>", line_num))?;
+            let (player, turbo) = current
+                .as_mut()
+                .ok_or_else(|| anyhow!("Line {}: binding outside of a player section", line_num))?;
+
+            let button = button.trim();
+            let (target, button) = match button.strip_prefix("Turbo") {
+                Some(button) => (turbo, button),
+                None => (player, button),
+            };
+
+            let button = parse_button(button)
+                .ok_or_else(|| anyhow!("Line {}: unknown button {:?}", line_num, button))?;
+            match parse_key(key.trim()) {
+                Some(key) => {
+                    target.insert(key, button);
+                }
+                None => log::warn!("Line {}: unknown key {:?}, skipping", line_num, key.trim()),
+            }
+        }
+
+        Ok(bindings)
+    }
+}
+
+/// Collects the buttons whose bound keys are currently held.
+pub fn held_buttons(input: &WinitInputHelper, bindings: &Bindings) -> ControllerButtons {
+    let mut buttons = ControllerButtons::empty();
+    for (&key, &button) in bindings {
+        if input.key_held(key) {
+            buttons.insert(button);
+        }
+    }
+    buttons
+}
+
+fn parse_button(name: &str) -> Option<ControllerButtons> {
+    ControllerButtons::all()
+        .iter_names()
+        .find(|(button, _)| button.eq_ignore_ascii_case(name))
+        .map(|(_, button)| button)
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let deserializer: StrDeserializer<serde::de::value::Error> = StrDeserializer::new(name);
+    KeyCode::deserialize(deserializer).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_bindings() {
+        let config = "
+            # Comments and blank lines are ignored
+            [player1]
+            A = KeyK
+            start = Enter
+            B = NotAKey
+            TurboB = KeyL
+        ";
+        let bindings = KeyBindings::parse(config).unwrap();
+
+        assert_eq!(
+            bindings.one,
+            HashMap::from([
+                (KeyCode::KeyK, ControllerButtons::A),
+                (KeyCode::Enter, ControllerButtons::Start),
+            ])
+        );
+        assert_eq!(
+            bindings.turbo_one,
+            HashMap::from([(KeyCode::KeyL, ControllerButtons::B)])
+        );
+        // Untouched players keep their defaults
+        assert_eq!(bindings.two, KeyBindings::default().two);
+        assert_eq!(bindings.turbo_two, KeyBindings::default().turbo_two);
+    }
+
+    #[test]
+    fn parse_invalid_bindings() {
+        assert!(KeyBindings::parse("A = KeyX").is_err());
+        assert!(KeyBindings::parse("[player3]").is_err());
+        assert!(KeyBindings::parse("[player1]\nTurbo = KeyX").is_err());
+        assert!(KeyBindings::parse("[player1]\nA KeyX").is_err());
+    }
+}
@@ -0,0 +1,8 @@
+//! The emulator core and the pieces shared between frontends.
+//! The windowed frontend lives in `main.rs`, and `examples/headless.rs` runs ROMs without a display.
+//! `renderer::Renderer` is only built with the `frontend` feature, which the binary needs.
+
+pub mod audio_output;
+pub mod emu;
+pub mod renderer;
+pub mod utils;
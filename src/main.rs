@@ -1,55 +1,295 @@
 use cpal::StreamConfig;
 use ringbuf::traits::*;
-use std::env;
-use std::process;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
-use ui::draw_cpu_info;
-use ui::draw_ppu_info;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
-use audio_output::AudioBufferConsumer;
+use audio_output::{AudioBufferConsumer, AudioOutput};
+use clap::{Parser, Subcommand};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     FromSample, SizedSample,
 };
 use error_iter::ErrorIter as _;
+use gamepad::Gamepads;
+use key_bindings::{held_buttons, KeyBindings};
 use log::error;
-use renderer::Renderer;
+use renderer::{post_process, Color, Overscan, Renderer, Scaling, Sprite};
 use rusttype::Font;
+use turbo::TurboState;
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{Event, MouseButton, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::KeyCode;
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-use emu::cartridge::Cartridge;
-use emu::consts::FRAME_TIME;
+use nesrs::{audio_output, emu, renderer, utils};
+use utils::{FpsCounter, FrameTimes};
+
+use emu::apu::ApuChannel;
+use emu::cartridge::{self, Cartridge, Header};
 use emu::input::{ControllerButtons, ControllerInput};
+use emu::movie::{MoviePlayer, MovieRecorder};
 use emu::nes::Nes;
 use emu::palette::Palette;
+use emu::ram_init::RamInit;
+use emu::region::Region;
 
-mod audio_output;
-mod emu;
-mod renderer;
+mod gamepad;
+mod key_bindings;
+mod test_roms;
+mod turbo;
 #[allow(dead_code)]
 mod ui;
-mod utils;
+
+// How long the ROM info overlay stays up
+const ROM_INFO_DURATION: Duration = Duration::from_secs(5);
+
+const CHANNEL_MUTE_KEYS: [(KeyCode, ApuChannel); 5] = [
+    (KeyCode::F5, ApuChannel::Pulse1),
+    (KeyCode::F6, ApuChannel::Pulse2),
+    (KeyCode::F7, ApuChannel::Triangle),
+    (KeyCode::F8, ApuChannel::Noise),
+    (KeyCode::F9, ApuChannel::Dmc),
+];
+
+// Emulation speed limits and step, in percent
+const MIN_SPEED: u32 = 25;
+const MAX_SPEED: u32 = 400;
+const SPEED_STEP: u32 = 25;
+
+/// Passed as the ROM path to read the ROM from stdin instead.
+const STDIN_PATH: &str = "-";
 
 const WIDTH: usize = 256;
 const HEIGHT: usize = 240;
 
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "An emulator for the Nintendo Entertainment System",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the iNES ROM to run, or - to read it from stdin
+    #[arg(required = true)]
+    rom_path: Option<String>,
+
+    /// Print what the ROM's header says, like its mapper and sizes, and exit without running it
+    #[arg(long)]
+    info: bool,
+
+    /// Pixels to hide at the screen edges, either "N" for the top and bottom
+    /// or "TOP,BOTTOM,LEFT,RIGHT"
+    #[arg(long, default_value = "8")]
+    overscan: Overscan,
+
+    /// Key bindings config file, see `KeyBindings::parse` for the format
+    #[arg(long)]
+    controls: Option<String>,
+
+    /// Don't read player 1's input from a gamepad, only the keyboard
+    #[arg(long)]
+    no_gamepad: bool,
+
+    /// How far the gamepad's left stick has to be pushed to press the D-pad, in percent
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(10..=90))]
+    stick_deadzone: u32,
+
+    /// How many times per second turbo buttons are pressed
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=30))]
+    turbo_rate: u32,
+
+    /// Log every CPU instruction to this file, in the same format as nestest.log
+    #[arg(long)]
+    trace_file: Option<String>,
+
+    /// Plug a Zapper into port 2 instead of a controller, aimed with the mouse
+    #[arg(long)]
+    zapper: bool,
+
+    /// Directory to keep battery saves in, defaults to the platform's config directory
+    #[arg(long)]
+    save_dir: Option<PathBuf>,
+
+    /// Load the battery RAM from this file of the cartridge's RAM size instead of the game's own
+    /// save. It's written to the game's own save from then on
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["record", "play"])]
+    import_save: Option<PathBuf>,
+
+    /// How often to write the battery save while playing, if the game has changed it,
+    /// so it isn't lost if the emulator crashes. 0 only saves on exit
+    #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+    autosave_interval: u64,
+
+    /// Draw every sprite on a scanline instead of only 8, which stops the flicker in busy scenes.
+    /// Not accurate, but games can't tell the difference
+    #[arg(long)]
+    no_sprite_limit: bool,
+
+    /// What RAM holds at power on: zeros, ones, pattern:<hex byte>, random:<seed>,
+    /// or random for a new seed each time. Movies only play back with the RAM they were recorded with
+    #[arg(long, default_value = "zeros", value_parser = parse_ram_init)]
+    ram_init: RamInit,
+
+    /// Blur and add scanlines to the picture, to look more like a TV over composite video
+    #[arg(long)]
+    ntsc: bool,
+
+    /// Stretch the picture to fill the window, instead of scaling it by the largest whole
+    /// number that fits
+    #[arg(long)]
+    stretch: bool,
+
+    /// Scale the picture to fill as much of the window as it can with non-square pixels of
+    /// this aspect ratio, like on a TV. Only 8:7 is supported
+    #[arg(long, value_name = "RATIO", value_parser = ["8:7"], conflicts_with = "stretch")]
+    aspect: Option<String>,
+
+    /// Only draw every N+1th frame, for machines too slow to draw every one.
+    /// Every frame is still emulated and heard
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    frame_skip: u32,
+
+    /// How many times faster than normal to run while fast-forwarding (holding Tab)
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=16))]
+    max_speed: u32,
+
+    /// Emulation speed as a percentage of normal, adjusted with - and = while running.
+    /// The audio is pitched up or down to match
+    #[arg(long, default_value_t = 100,
+          value_parser = clap::value_parser!(u32).range(MIN_SPEED as i64..=MAX_SPEED as i64))]
+    speed: u32,
+
+    /// Automatically adjust the volume so quiet games play as loud as possible without clipping
+    #[arg(long)]
+    normalize_audio: bool,
+
+    /// Round off peaks that are too loud for the output instead of letting them clip
+    #[arg(long)]
+    soft_clip: bool,
+
+    /// How much audio to buffer, in milliseconds. Lower is more responsive,
+    /// higher is less likely to crackle on a slow or busy machine
+    #[arg(long, default_value_t = AudioOutput::DEFAULT_LATENCY_MS,
+          value_parser = clap::value_parser!(u32).range(10..=1000))]
+    audio_latency: u32,
+
+    /// Colors to use, from a .pal file of 64 RGB colors
+    #[arg(long, default_value = "assets/palettes/2C02G.pal")]
+    palette: PathBuf,
+
+    /// The Famicom Disk System BIOS, which Disk System images (.fds) need to run.
+    /// It can't be distributed with the emulator
+    #[arg(long, default_value = "assets/bios/disksys.rom")]
+    fds_bios: PathBuf,
+
+    /// Run with NTSC or PAL timing instead of the region in the ROM header
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// Apply a 6 or 8 letter Game Genie code, like SXIOPO. Can be given more than once
+    #[arg(long)]
+    cheat: Vec<String>,
+
+    /// Pause when the CPU writes to this address, in hex. Can be given more than once
+    #[arg(long, value_parser = parse_address)]
+    watch: Vec<u16>,
+
+    /// Record the controller input of every frame to this file, in FCEUX's FM2 movie format
+    #[arg(long, conflicts_with_all = ["play", "zapper"])]
+    record: Option<PathBuf>,
+
+    /// Play back a movie made with --record, ignoring the keyboard until it ends
+    #[arg(long, conflicts_with = "zapper")]
+    play: Option<PathBuf>,
+
+    /// Record the audio output to this WAV file from the start, until F11 is pressed or the window is closed
+    #[arg(long)]
+    record_audio: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run every ROM in a directory without a window for a while, and print which ones work
+    TestRoms {
+        /// Directory of .nes and .zip ROMs
+        dir: PathBuf,
+
+        /// How many frames to run each ROM for
+        #[arg(long, default_value_t = 600)]
+        frames: u32,
+    },
+}
+
+/// Parses a hex address, with an optional `$` or `0x` prefix.
+fn parse_address(s: &str) -> Result<u16> {
+    let hex = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|e| anyhow!("Invalid address {:?}: {}", s, e))
+}
+
+/// Parses `--ram-init`, picking a seed from the current time for a plain `random`.
+fn parse_ram_init(s: &str) -> Result<RamInit> {
+    if !s.eq_ignore_ascii_case("random") {
+        return s.parse();
+    }
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    Ok(RamInit::Random(seed))
+}
+
+/// `<config dir>/nesrs/saves`, or `saves` in the working directory if the platform has none.
+fn default_save_dir() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("nesrs").join("saves"))
+        .unwrap_or_else(|| PathBuf::from("saves"))
+}
+
 pub fn main() -> Result<()> {
     env_logger::builder().format_timestamp_micros().init();
 
-    let args: Vec<_> = env::args().collect();
-    if args.len() <= 1 {
-        println!("Usage: {} <rom path>", args[0]);
-        process::exit(1);
+    let args = Args::parse();
+
+    if let Some(Command::TestRoms { dir, frames }) = &args.command {
+        return test_roms::run(dir, *frames);
+    }
+    // Clap requires it when there's no subcommand
+    let rom_path = args.rom_path.as_deref().unwrap_or_default();
+
+    if args.info {
+        let bytes = read_rom(rom_path)?;
+        println!("{}", Header::parse(&bytes)?);
+        return Ok(());
+    }
+
+    let bindings = match &args.controls {
+        Some(path) => KeyBindings::load(path)?,
+        None => KeyBindings::default(),
+    };
+
+    // See: https://www.nesdev.org/wiki/Overscan
+    let visible_width = args.overscan.visible_width(WIDTH);
+    let visible_height = args.overscan.visible_height(HEIGHT);
+    if visible_width == 0 || visible_height == 0 {
+        return Err(anyhow!(
+            "Overscan {:?} hides the whole screen",
+            args.overscan
+        ));
     }
 
     let event_loop = EventLoop::new().unwrap();
@@ -57,7 +297,7 @@ pub fn main() -> Result<()> {
 
     let mut input = WinitInputHelper::new();
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
+        let size = LogicalSize::new(visible_width as f64, visible_height as f64);
         WindowBuilder::new()
             .with_title("nes.rs")
             .with_inner_size(size)
@@ -69,26 +309,119 @@ pub fn main() -> Result<()> {
     let (device, config) = setup_audio()?;
     let stream_config: StreamConfig = config.into();
 
-    let rom_path = &args[1];
-
     let font_data = include_bytes!("../assets/fonts/nes-arcade-font-2-1-monospaced.ttf");
     let font = Font::try_from_bytes(font_data as &[u8]).ok_or(anyhow!("Error loading font"))?;
 
-    let palette = Palette::load("assets/palettes/2C02G.pal")?;
+    let palette = Palette::load(&args.palette)?;
+
+    // The renderer scales the buffer up to fill the window while keeping the aspect ratio,
+    // so sizing it to the visible region is all that's needed to crop the overscan
+    let mut renderer = Renderer::new(font, &window, visible_width, visible_height)?;
+    if args.stretch {
+        renderer.set_scaling(Scaling::Stretch);
+    } else if let Some(aspect) = &args.aspect {
+        renderer.set_scaling(aspect.parse()?);
+    }
+
+    // Movies start from power on with blank save RAM, so they play back the same every time
+    let movie_active = args.record.is_some() || args.play.is_some();
 
-    let mut renderer = Renderer::new(font, &window, WIDTH, HEIGHT)?;
+    let save_dir = args.save_dir.clone().unwrap_or_else(default_save_dir);
+    let mut cartridge = if rom_path == STDIN_PATH {
+        load_rom(STDIN_PATH, &args.fds_bios)
+            .map_err(|err| anyhow!("Invalid ROM on stdin: {err}"))?
+    } else {
+        load_rom(rom_path, &args.fds_bios)?
+    };
+    for code in &args.cheat {
+        cartridge.add_genie_code(code)?;
+    }
+    if let Some(path) = &args.import_save {
+        cartridge.import_save_file(path)?;
+    } else if !movie_active {
+        if let Err(err) = cartridge.load_save_file(&save_dir) {
+            error!("Failed to load save file: {err}");
+        }
+    }
 
-    let (mut nes, audio_consumer) =
-        Nes::new(palette.clone()).with_audio(stream_config.sample_rate.0 as usize);
+    let mut movie_player = match &args.play {
+        Some(path) => Some(MoviePlayer::load(path)?),
+        None => None,
+    };
+    if let Some(player) = &movie_player {
+        if player
+            .rom_hash()
+            .is_some_and(|hash| hash != cartridge.rom_hash())
+        {
+            log::warn!(
+                "Movie was recorded with a different ROM, it probably won't play back correctly"
+            );
+        }
+    }
 
-    let cartridge = Cartridge::new(rom_path)?;
+    let region = args
+        .region
+        .or(movie_player.as_ref().map(|player| player.region()))
+        .unwrap_or_else(|| cartridge.region());
+    let mut movie_recorder = match &args.record {
+        Some(path) => Some(MovieRecorder::create(path, cartridge.rom_hash(), region)?),
+        None => None,
+    };
+    let mut frame_time = region.frame_time();
+    log::info!("RAM init: {}", args.ram_init);
+    let (mut nes, audio_consumer) = Nes::new(palette.clone(), region)
+        .with_ram_init(args.ram_init)
+        .with_audio(stream_config.sample_rate.0 as usize, args.audio_latency);
+
+    nes.set_audio_normalized(args.normalize_audio);
+    nes.set_audio_soft_clip(args.soft_clip);
+    let mut speed_percent = args.speed;
+    nes.set_audio_speed(speed_percent as f64 / 100.0);
+
+    let rom_name = if rom_path == STDIN_PATH {
+        "stdin"
+    } else {
+        rom_path
+    };
+    let mut rom_info = ui::rom_info(rom_name, &cartridge);
+    let mut rom_info_shown_at: Option<Instant> = None;
     nes.load_cartridge(cartridge);
-    nes.reset();
+    nes.power_on();
+    if let Some(path) = &args.trace_file {
+        nes.enable_trace(path)?;
+    }
+    for &addr in &args.watch {
+        nes.set_write_watch(addr);
+    }
+    nes.set_sprite_limit_enabled(!args.no_sprite_limit);
+    if let Some(path) = &args.record_audio {
+        nes.start_audio_recording(path)?;
+    }
+
+    let mut turbo = TurboState::new(args.turbo_rate, region.fps());
+    let mut gamepads = if args.no_gamepad {
+        None
+    } else {
+        Gamepads::new(args.stick_deadzone as f32 / 100.0)
+            .inspect_err(|err| error!("{err}, only the keyboard will work"))
+            .ok()
+    };
 
     let paused = Arc::new(AtomicBool::new(false));
 
+    let mut show_perf = false;
+    let mut show_pattern_tables = false;
+    let mut show_raster_markers = false;
+    let mut rom_hex_view: Option<ui::RomMemory> = None;
+    let mut rom_hex_row = 0;
+    let mut fps_counter = FpsCounter::new();
+    let mut frame_times = FrameTimes::default();
+
+    let mut fast_forward = false;
     let mut acc = 0.0;
     let mut now = Instant::now();
+    let mut frames_since_draw = 0;
+    let mut last_autosave = Instant::now();
 
     let p = paused.clone();
     thread::spawn(move || {
@@ -103,31 +436,208 @@ pub fn main() -> Result<()> {
                 ..
             } => {
                 println!("Close button pressed, exiting");
+                if !movie_active {
+                    if let Err(err) = nes.write_save_file(&save_dir) {
+                        error!("Failed to write save file: {err}");
+                    }
+                }
+                if let Some(recorder) = &mut movie_recorder {
+                    if let Err(err) = recorder.finish() {
+                        error!("Failed to write movie: {err}");
+                    }
+                }
+                if let Err(err) = nes.stop_audio_recording() {
+                    error!("Failed to write audio recording: {err}");
+                }
                 target.exit();
             }
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(ref path),
+                ..
+            } => {
+                if movie_active {
+                    log::warn!("Can't load another ROM while a movie is recording or playing");
+                } else {
+                    match swap_rom(&mut nes, path, &args, &save_dir) {
+                        Ok(info) => {
+                            frame_time = nes.region().frame_time();
+                            turbo = TurboState::new(args.turbo_rate, nes.region().fps());
+                            rom_info = info;
+                            rom_info_shown_at = Some(Instant::now());
+                        }
+                        Err(err) => error!("Failed to load {}: {err}", path.display()),
+                    }
+                }
+            }
             Event::AboutToWait => {
                 if !paused.load(Ordering::Relaxed) {
-                    acc += now.elapsed().as_secs_f64();
+                    let speed = if fast_forward {
+                        args.max_speed as f64
+                    } else {
+                        speed_percent as f64 / 100.0
+                    };
+                    acc += now.elapsed().as_secs_f64() * speed;
                     now = Instant::now();
-                    while acc >= FRAME_TIME {
+                    if speed > 1.0 {
+                        // Drop whatever can't be caught up on in one step, instead of running a
+                        // stall's whole backlog as a burst when the host can't run that fast
+                        acc = acc.min(frame_time * speed);
+                    }
+                    while acc >= frame_time {
+                        // Movie input is applied on frame boundaries, so playback matches recording
+                        if let Some(player) = &mut movie_player {
+                            match player.next_frame() {
+                                Some(frame) => {
+                                    if frame.reset {
+                                        nes.reset();
+                                    }
+                                    nes.trigger_inputs(ControllerInput::One(frame.one));
+                                    nes.trigger_inputs(ControllerInput::Two(frame.two));
+                                }
+                                None => {
+                                    log::info!("Movie finished");
+                                    movie_player = None;
+                                }
+                            }
+                        } else {
+                            // Sampled right before every frame, so a press reaches the very next
+                            // frame instead of waiting for the ones being caught up on to finish.
+                            // The held keys are kept up to date as window events arrive, which all
+                            // come before this, while hotkeys are still handled once per event loop
+                            // step below
+                            for input in console_input(
+                                &input,
+                                &mut gamepads,
+                                &bindings,
+                                &turbo,
+                                &renderer,
+                                &args,
+                            ) {
+                                if let Some(recorder) = &mut movie_recorder {
+                                    recorder.notify_input(&input);
+                                }
+                                nes.trigger_inputs(input);
+                            }
+                        }
+                        if let Some(recorder) = &mut movie_recorder {
+                            if let Err(err) = recorder.record_frame() {
+                                error!("Failed to record movie, stopping: {err}");
+                                movie_recorder = None;
+                            }
+                        }
+
+                        let before_frame = Instant::now();
                         nes.advance_frame();
-                        acc -= FRAME_TIME;
+                        frame_times.push(before_frame.elapsed());
+                        fps_counter.tick();
+                        turbo.tick();
+                        frames_since_draw += 1;
+                        acc -= frame_time;
+
+                        if let Some(addr) = nes.take_breakpoint_hit() {
+                            log::info!(
+                                "Paused after a write to {:#06X}, next instruction at {:#06X}",
+                                addr,
+                                nes.cpu().pc()
+                            );
+                            paused.store(true, Ordering::Relaxed);
+                            acc = 0.0;
+                            break;
+                        }
                     }
                 }
 
-                renderer.clear();
+                if !movie_active
+                    && args.autosave_interval > 0
+                    && last_autosave.elapsed() >= Duration::from_secs(args.autosave_interval)
+                {
+                    last_autosave = Instant::now();
+                    if nes.save_ram_changed() {
+                        if let Err(err) = nes.write_save_file(&save_dir) {
+                            error!("Failed to write save file: {err}");
+                        }
+                    }
+                }
 
-                let screen = nes.screen();
-                // TODO: Implement not drawing overscan
-                // https://www.nesdev.org/wiki/Overscan
-                renderer.draw_sprite(screen, 0, 0);
+                // Skipped frames still run in full, so only the drawing is saved.
+                // Always drawn while paused, to show stepping
+                let draw = args.frame_skip == 0
+                    || frames_since_draw > args.frame_skip
+                    || paused.load(Ordering::Relaxed);
+                if draw {
+                    frames_since_draw = 0;
 
-                // draw_ppu_info(&mut renderer, &nes.ppu(), 0, 0);
-                // draw_cpu_info(&mut renderer, &nes, 480, 0);
+                    renderer.clear();
 
-                if let Err(err) = renderer.render() {
-                    log_error("pixels.render", err);
-                    target.exit();
+                    let screen = visible_screen(&nes, &args);
+                    renderer.draw_sprite(&screen, 0, 0);
+
+                    if show_raster_markers {
+                        ui::draw_raster_markers(&mut renderer, &nes.ppu(), args.overscan.top);
+                    }
+
+                    if let Some(shown_at) = rom_info_shown_at {
+                        if shown_at.elapsed() < ROM_INFO_DURATION {
+                            ui::draw_rom_info(&mut renderer, &rom_info, 0, 0);
+                        } else {
+                            rom_info_shown_at = None;
+                        }
+                    }
+
+                    if show_pattern_tables {
+                        let background =
+                            Sprite::monocolor(Color::BLACK, renderer.width(), renderer.height());
+                        renderer.draw_sprite(&background, 0, 0);
+                        ui::draw_pattern_tables(&mut renderer, &nes.ppu(), 0, 0, 0);
+
+                        let hovered = input
+                            .cursor()
+                            .and_then(|pos| renderer.window_pos_to_pixel(pos))
+                            .and_then(|(x, y)| {
+                                let (table, tile) = ui::pattern_table_tile_at(x, y, 0, 0)?;
+                                Some((table, tile, x, y))
+                            });
+                        if let Some((table, tile, x, y)) = hovered {
+                            ui::draw_tile_tooltip(&mut renderer, &nes.ppu(), table, tile, x, y);
+                        }
+                    }
+
+                    if let (Some(memory), Some(cartridge)) = (rom_hex_view, nes.cartridge()) {
+                        ui::draw_rom_hex(&mut renderer, &cartridge, memory, rom_hex_row, 0, 0);
+                    }
+
+                    if show_perf {
+                        ui::draw_perf_overlay(
+                            &mut renderer,
+                            fps_counter.get_fps(),
+                            &frame_times,
+                            Duration::from_secs_f64(frame_time),
+                            0,
+                            0,
+                        );
+                    }
+
+                    // The screen is drawn a pixel at a time, so stepping through dots shows the beam move
+                    if paused.load(Ordering::Relaxed) {
+                        let y = renderer.height().saturating_sub(24);
+                        ui::draw_beam_position(&mut renderer, &nes.ppu(), 0, y);
+                    }
+
+                    // ui::draw_ppu_info(&mut renderer, &nes.ppu(), 0, 0);
+                    // ui::draw_cpu_info(&mut renderer, &nes, 480, 0);
+                    // ui::draw_nametables(&mut renderer, &nes.ppu(), 0, 0);
+                    // if let Some(cartridge) = nes.cartridge() {
+                    //     ui::draw_bank_layout(&mut renderer, &cartridge, 0, 0);
+                    // }
+
+                    if let Err(err) = renderer.render() {
+                        log_error("pixels.render", err);
+                        target.exit();
+                    }
+                } else {
+                    // Drawing waits for the display, so without it the loop would spin
+                    // until the next frame is due
+                    thread::sleep(Duration::from_millis(1));
                 }
             }
 
@@ -139,41 +649,155 @@ pub fn main() -> Result<()> {
             if input.key_pressed(KeyCode::Space) {
                 paused.store(!paused.load(Ordering::Relaxed), Ordering::Relaxed);
                 now = Instant::now();
-            } else if input.key_pressed(KeyCode::KeyN) && paused.load(Ordering::Relaxed) {
-                nes.next_instruction();
+            } else if paused.load(Ordering::Relaxed) {
+                if input.key_pressed(KeyCode::KeyN) {
+                    nes.next_instruction();
+                } else if input.key_pressed(KeyCode::KeyO) {
+                    if !nes.step_over() {
+                        log::info!("Stopped stepping over at {:#06X}", nes.cpu().pc());
+                    }
+                } else if input.key_pressed(KeyCode::KeyU) {
+                    if !nes.step_out() {
+                        log::info!("Stopped stepping out at {:#06X}", nes.cpu().pc());
+                    }
+                } else if input.key_pressed(KeyCode::KeyM) {
+                    if movie_player.is_none() {
+                        for input in console_input(
+                            &input,
+                            &mut gamepads,
+                            &bindings,
+                            &turbo,
+                            &renderer,
+                            &args,
+                        ) {
+                            if let Some(recorder) = &mut movie_recorder {
+                                recorder.notify_input(&input);
+                            }
+                            nes.trigger_inputs(input);
+                        }
+                    }
+                    if let Some(recorder) = &mut movie_recorder {
+                        if let Err(err) = recorder.record_frame() {
+                            error!("Failed to record movie, stopping: {err}");
+                            movie_recorder = None;
+                        }
+                    }
+                    nes.advance_frame();
+                } else if input.key_pressed(KeyCode::KeyD) {
+                    nes.clock();
+                }
             }
 
-            // Console input
-            let mut buttons = ControllerButtons::empty();
-            if input.key_held(KeyCode::KeyX) {
-                buttons.insert(ControllerButtons::A);
+            if input.key_held(KeyCode::Tab) != fast_forward {
+                fast_forward = !fast_forward;
+                nes.set_audio_muted(fast_forward);
             }
-            if input.key_held(KeyCode::KeyZ) {
-                buttons.insert(ControllerButtons::B);
+
+            let speed_change = if input.key_pressed(KeyCode::Minus) {
+                Some(speed_percent.saturating_sub(SPEED_STEP).max(MIN_SPEED))
+            } else if input.key_pressed(KeyCode::Equal) {
+                Some((speed_percent + SPEED_STEP).min(MAX_SPEED))
+            } else {
+                None
+            };
+            if let Some(new_speed) = speed_change {
+                speed_percent = new_speed;
+                nes.set_audio_speed(speed_percent as f64 / 100.0);
+                log::info!("Speed {}%", speed_percent);
             }
-            if input.key_held(KeyCode::KeyA) {
-                buttons.insert(ControllerButtons::Select);
+
+            if input.key_pressed(KeyCode::F2) && movie_player.is_none() {
+                nes.reset();
+                if let Some(recorder) = &mut movie_recorder {
+                    recorder.notify_reset();
+                }
             }
-            if input.key_held(KeyCode::KeyS) {
-                buttons.insert(ControllerButtons::Start);
+
+            if input.key_pressed(KeyCode::F3) {
+                show_perf = !show_perf;
             }
-            if input.key_held(KeyCode::ArrowUp) {
-                buttons.insert(ControllerButtons::Up);
+
+            if input.key_pressed(KeyCode::F4) {
+                show_pattern_tables = !show_pattern_tables;
             }
-            if input.key_held(KeyCode::ArrowDown) {
-                buttons.insert(ControllerButtons::Down);
+
+            if input.key_pressed(KeyCode::Backquote) {
+                show_raster_markers = !show_raster_markers;
             }
-            if input.key_held(KeyCode::ArrowLeft) {
-                buttons.insert(ControllerButtons::Left);
+
+            if input.key_pressed(KeyCode::Backslash) {
+                let scaling = renderer.scaling().next();
+                renderer.set_scaling(scaling);
+                log::info!("Scaling: {}", scaling);
+            }
+
+            if input.key_pressed(KeyCode::F10) {
+                rom_hex_view = match rom_hex_view {
+                    None => Some(ui::RomMemory::Prg),
+                    Some(ui::RomMemory::Prg) => Some(ui::RomMemory::Chr),
+                    Some(ui::RomMemory::Chr) => None,
+                };
+                rom_hex_row = 0;
+            }
+
+            if let (Some(memory), Some(cartridge)) = (rom_hex_view, nes.cartridge()) {
+                let page = ui::rom_hex_visible_rows(renderer.height());
+                if input.key_pressed(KeyCode::PageDown) {
+                    rom_hex_row += page;
+                } else if input.key_pressed(KeyCode::PageUp) {
+                    rom_hex_row = rom_hex_row.saturating_sub(page);
+                } else if input.key_pressed(KeyCode::Home) {
+                    rom_hex_row = 0;
+                } else if input.key_pressed(KeyCode::End) {
+                    rom_hex_row = usize::MAX;
+                }
+                rom_hex_row = rom_hex_row.min(ui::rom_hex_max_row(&cartridge, memory, page));
+            }
+
+            if input.key_pressed(KeyCode::F1) {
+                rom_info_shown_at = match rom_info_shown_at {
+                    Some(_) => None,
+                    None => Some(Instant::now()),
+                };
             }
-            if input.key_held(KeyCode::ArrowRight) {
-                buttons.insert(ControllerButtons::Right);
+
+            for (key, channel) in CHANNEL_MUTE_KEYS {
+                if input.key_pressed(key) {
+                    let muted = !nes.is_channel_muted(channel);
+                    nes.set_channel_muted(channel, muted);
+                    log::info!("{:?} {}", channel, if muted { "muted" } else { "unmuted" });
+                }
+            }
+
+            if input.key_pressed(KeyCode::F12) {
+                let screen = visible_screen(&nes, &args);
+                match utils::save_screenshot(&screen, ".") {
+                    Ok(path) => log::info!("Saved screenshot to {}", path.display()),
+                    Err(err) => error!("Failed to save screenshot: {err}"),
+                }
+            }
+
+            if input.key_pressed(KeyCode::F11) {
+                if nes.is_recording_audio() {
+                    match nes.stop_audio_recording() {
+                        Ok(()) => log::info!("Stopped recording audio"),
+                        Err(err) => error!("Failed to write audio recording: {err}"),
+                    }
+                } else {
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |time| time.as_millis());
+                    let path = PathBuf::from(format!("audio-{}.wav", timestamp));
+                    match nes.start_audio_recording(&path) {
+                        Ok(()) => log::info!("Recording audio to {}", path.display()),
+                        Err(err) => error!("Failed to start recording audio: {err}"),
+                    }
+                }
             }
-            nes.trigger_inputs(ControllerInput::One(buttons));
 
             // Resize the window
             if let Some(size) = input.window_resized() {
-                if let Err(err) = renderer.pixels().resize_surface(size.width, size.height) {
+                if let Err(err) = renderer.resize_surface(size.width, size.height) {
                     log_error("pixels.resize_surface", err);
                     target.exit();
                 }
@@ -263,6 +887,125 @@ where
     Ok(())
 }
 
+/// Reads a ROM file like `Cartridge::new` does, or all of stdin when the path is `STDIN_PATH`.
+fn read_rom(path: &str) -> Result<Vec<u8>> {
+    if path != STDIN_PATH {
+        return cartridge::read_rom_file(path);
+    }
+
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        return Err(anyhow!("No ROM on stdin, pipe one in or pass its path"));
+    }
+    Ok(bytes)
+}
+
+/// Loads the ROM at `path` like `Cartridge::new` does, or from stdin when it's `STDIN_PATH`.
+/// Disk System images are loaded with the BIOS at `fds_bios`.
+fn load_rom(path: &str, fds_bios: &Path) -> Result<Cartridge> {
+    log::info!("Loading ROM: {}", path);
+    let bytes = read_rom(path)?;
+    if !cartridge::is_fds(&bytes) {
+        return Cartridge::from_bytes(&bytes);
+    }
+
+    let bios = fs::read(fds_bios).map_err(|err| {
+        anyhow!(
+            "Disk System games need the BIOS at {}: {err}",
+            fds_bios.display()
+        )
+    })?;
+    Cartridge::from_fds(&bytes, bios)
+}
+
+/// Replaces the running game with the ROM at `path`, writing the old game's save first.
+/// The current game keeps running if the new ROM can't be loaded.
+/// The new game runs in the `--region` if given, otherwise in the region its header asks for.
+fn swap_rom(nes: &mut Nes, path: &Path, args: &Args, save_dir: &Path) -> Result<Vec<String>> {
+    let rom_path = path.to_string_lossy().into_owned();
+    let mut cartridge = load_rom(&rom_path, &args.fds_bios)?;
+    if let Err(err) = cartridge.load_save_file(save_dir) {
+        error!("Failed to load save file: {err}");
+    }
+
+    if let Err(err) = nes.write_save_file(save_dir) {
+        error!("Failed to write save file: {err}");
+    }
+
+    let info = ui::rom_info(&rom_path, &cartridge);
+    // Replacing the cartridge powers on, which picks up the new region
+    nes.set_region(args.region.unwrap_or_else(|| cartridge.region()));
+    nes.load_cartridge(cartridge);
+    Ok(info)
+}
+
+/// The part of the frame to show, with the NTSC filter applied if enabled.
+fn visible_screen(nes: &Nes, args: &Args) -> Sprite {
+    let screen = nes.screen();
+    if !args.ntsc {
+        return screen.crop(&args.overscan);
+    }
+
+    Sprite::new(
+        post_process(screen.pixels()),
+        screen.width(),
+        screen.height(),
+    )
+    .unwrap()
+    .crop(&args.overscan)
+}
+
+/// What's pressed on the controllers plugged into each port, as of the latest window events.
+fn console_input(
+    input: &WinitInputHelper,
+    gamepads: &mut Option<Gamepads>,
+    bindings: &KeyBindings,
+    turbo: &TurboState,
+    renderer: &Renderer,
+    args: &Args,
+) -> [ControllerInput; 2] {
+    let (pad, pad_turbo) = match gamepads {
+        Some(gamepads) => {
+            gamepads.update();
+            (gamepads.held_buttons(), gamepads.held_turbo_buttons())
+        }
+        None => (ControllerButtons::empty(), ControllerButtons::empty()),
+    };
+    let one = held_buttons(input, &bindings.one)
+        | pad
+        | turbo.apply(held_buttons(input, &bindings.turbo_one) | pad_turbo);
+    let port_two = if args.zapper {
+        zapper_input(input, renderer, &args.overscan)
+    } else {
+        let two = held_buttons(input, &bindings.two)
+            | turbo.apply(held_buttons(input, &bindings.turbo_two));
+        ControllerInput::Two(two)
+    };
+
+    [ControllerInput::One(one), port_two]
+}
+
+/// Aims the Zapper at the screen pixel under the mouse cursor, with left click as the trigger.
+fn zapper_input(
+    input: &WinitInputHelper,
+    renderer: &Renderer,
+    overscan: &Overscan,
+) -> ControllerInput {
+    let (x, y) = input
+        .cursor()
+        .and_then(|pos| renderer.window_pos_to_pixel(pos))
+        .map(|(x, y)| (x + overscan.left, y + overscan.top))
+        // Aiming off screen, which never senses any light
+        .unwrap_or((usize::MAX, usize::MAX));
+
+    ControllerInput::Zapper {
+        x,
+        y,
+        trigger: input.mouse_held(MouseButton::Left),
+    }
+}
+
 fn log_error<E: std::error::Error + 'static>(method_name: &str, err: E) {
     error!("{method_name}() failed: {err}");
     for source in err.sources().skip(1) {
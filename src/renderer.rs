@@ -1,15 +1,38 @@
+use std::str::FromStr;
+
 use anyhow::{anyhow, Result};
+#[cfg(feature = "frontend")]
 use pixels::{Pixels, SurfaceTexture};
+#[cfg(feature = "frontend")]
 use rusttype::{point, Font, Scale};
+#[cfg(feature = "frontend")]
 use winit::window::Window;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[cfg(feature = "frontend")]
+mod scaling;
+
+#[cfg(feature = "frontend")]
+use scaling::RectRenderer;
+#[cfg(feature = "frontend")]
+pub use scaling::Scaling;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct Color(pub u8, pub u8, pub u8);
 
 impl Color {
     pub const WHITE: Self = Color(255, 255, 255);
     pub const GRAY: Self = Color(128, 128, 128);
     pub const BLACK: Self = Color(0, 0, 0);
+    pub const GREEN: Self = Color(0, 200, 0);
+    pub const RED: Self = Color(220, 0, 0);
+}
+
+/// Flattens colors to opaque RGBA bytes, the layout pixel buffers and image encoders expect.
+pub fn rgba_bytes(colors: &[Color]) -> Vec<u8> {
+    colors
+        .iter()
+        .flat_map(|color| [color.0, color.1, color.2, 255])
+        .collect()
 }
 
 pub struct Pixel {
@@ -65,6 +88,15 @@ impl Sprite {
         self.height
     }
 
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// The pixels as 4 bytes each, in RGBA order.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        rgba_bytes(&self.pixels)
+    }
+
     pub fn scale(self, scale: usize) -> Sprite {
         let new_width = self.width * scale;
         let new_height = self.height * scale;
@@ -79,6 +111,28 @@ impl Sprite {
         Sprite::new(scaled, self.width * scale, self.height * scale).unwrap()
     }
 
+    /// Returns the part of the sprite left visible after cropping the overscan.
+    pub fn crop(&self, overscan: &Overscan) -> Sprite {
+        let width = overscan.visible_width(self.width);
+        let height = overscan.visible_height(self.height);
+        let mut cropped = Vec::with_capacity(width * height);
+
+        for row in self
+            .pixels
+            .chunks(self.width)
+            .skip(overscan.top)
+            .take(height)
+        {
+            cropped.extend_from_slice(&row[overscan.left..overscan.left + width]);
+        }
+
+        Sprite {
+            pixels: cropped,
+            width,
+            height,
+        }
+    }
+
     pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<()> {
         if !(0..self.width).contains(&x) || !(0..self.height).contains(&y) {
             return Err(anyhow!(
@@ -93,6 +147,110 @@ impl Sprite {
     }
 }
 
+/// Width of the frames that `post_process` works on.
+const SCREEN_WIDTH: usize = 256;
+
+/// Roughly approximates how a frame looks over composite video.
+/// The limited bandwidth of the signal makes each pixel bleed into its horizontal neighbours,
+/// and every other line is darkened to mimic the gaps between scanlines on a CRT.
+pub fn post_process(pixels: &[Color]) -> Vec<Color> {
+    let mut processed = Vec::with_capacity(pixels.len());
+
+    for (y, row) in pixels.chunks(SCREEN_WIDTH).enumerate() {
+        for x in 0..row.len() {
+            // Edge pixels blend with themselves instead of wrapping to the next row
+            let left = row[x.saturating_sub(1)];
+            let right = row[(x + 1).min(row.len() - 1)];
+            let color = blend(left, row[x], right);
+
+            processed.push(if y % 2 == 1 { darken(color) } else { color });
+        }
+    }
+
+    processed
+}
+
+fn blend(left: Color, center: Color, right: Color) -> Color {
+    let mix = |l: u8, c: u8, r: u8| ((l as u16 + 2 * c as u16 + r as u16) / 4) as u8;
+    Color(
+        mix(left.0, center.0, right.0),
+        mix(left.1, center.1, right.1),
+        mix(left.2, center.2, right.2),
+    )
+}
+
+fn darken(color: Color) -> Color {
+    let scale = |c: u8| (c as u16 * 3 / 4) as u8;
+    Color(scale(color.0), scale(color.1), scale(color.2))
+}
+
+/// How many pixels to hide from each edge of the screen.
+/// See: https://www.nesdev.org/wiki/Overscan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    /// Width of the region left visible after cropping.
+    pub fn visible_width(&self, width: usize) -> usize {
+        width.saturating_sub(self.left + self.right)
+    }
+
+    /// Height of the region left visible after cropping.
+    pub fn visible_height(&self, height: usize) -> usize {
+        height.saturating_sub(self.top + self.bottom)
+    }
+}
+
+impl Default for Overscan {
+    /// Most TVs hide around 8 scanlines at the top and bottom.
+    fn default() -> Self {
+        Overscan {
+            top: 8,
+            bottom: 8,
+            left: 0,
+            right: 0,
+        }
+    }
+}
+
+impl FromStr for Overscan {
+    type Err = anyhow::Error;
+
+    /// Accepts either a single value for the top and bottom,
+    /// or four comma separated values for top, bottom, left and right.
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Invalid overscan {:?}: {}", s, e))?;
+
+        match values[..] {
+            [n] => Ok(Overscan {
+                top: n,
+                bottom: n,
+                left: 0,
+                right: 0,
+            }),
+            [top, bottom, left, right] => Ok(Overscan {
+                top,
+                bottom,
+                left,
+                right,
+            }),
+            _ => Err(anyhow!(
+                "Overscan must be 1 or 4 comma separated values, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
 // pub fn outline(width: usize, height: usize, color: Color) -> Sprite {
 //     let mut buf = vec![];
 //     for i in 0..height {
@@ -107,32 +265,76 @@ impl Sprite {
 //     Sprite::new(buf, width, height).unwrap()
 // }
 
+/// Draws to a window with pixels, see the `frontend` feature.
+#[cfg(feature = "frontend")]
 pub struct Renderer {
     font: Font<'static>,
     pixels: Pixels,
     width: usize,
     height: usize,
+
+    scaling: Scaling,
+    rect_renderer: RectRenderer,
+    surface_size: (u32, u32),
 }
 
+#[cfg(feature = "frontend")]
 impl Renderer {
     const FONT_SIZE: usize = 20;
 
     pub fn new(font: Font<'static>, window: &Window, width: usize, height: usize) -> Result<Self> {
+        let window_size = window.inner_size();
         let pixels = {
-            let window_size = window.inner_size();
             let surface_texture =
                 SurfaceTexture::new(window_size.width, window_size.height, window);
             Pixels::new(width as u32, height as u32, surface_texture)?
         };
+        let rect_renderer = RectRenderer::new(pixels.device(), pixels.render_texture_format());
 
         Ok(Renderer {
             font,
             pixels,
             width,
             height,
+
+            scaling: Scaling::default(),
+            rect_renderer,
+            surface_size: (window_size.width, window_size.height),
         })
     }
 
+    pub fn scaling(&self) -> Scaling {
+        self.scaling
+    }
+
+    /// Takes effect from the next `render`.
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    /// Call when the window is resized, with its new inner size.
+    pub fn resize_surface(&mut self, width: u32, height: u32) -> Result<(), pixels::TextureError> {
+        self.pixels.resize_surface(width, height)?;
+        self.surface_size = (width, height);
+        Ok(())
+    }
+
+    /// Where the picture is drawn in the window, see `Scaling::fit`.
+    fn picture_rect(&self) -> (f32, f32, f32, f32) {
+        self.scaling.fit(
+            (self.width as f32, self.height as f32),
+            (self.surface_size.0 as f32, self.surface_size.1 as f32),
+        )
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn clear(&mut self) {
         let frame = self.pixels.frame_mut();
         for x in frame.iter_mut() {
@@ -144,8 +346,31 @@ impl Renderer {
         &mut self.pixels
     }
 
+    /// The buffer pixel under a position in the window, if it's over the picture.
+    pub fn window_pos_to_pixel(&self, pos: (f32, f32)) -> Option<(usize, usize)> {
+        if self.scaling == Scaling::Integer {
+            return self.pixels.window_pos_to_pixel(pos).ok();
+        }
+
+        let (x, y, width, height) = self.picture_rect();
+        let px = (pos.0 - x) / width * self.width as f32;
+        let py = (pos.1 - y) / height * self.height as f32;
+        let in_picture =
+            (0.0..self.width as f32).contains(&px) && (0.0..self.height as f32).contains(&py);
+        in_picture.then_some((px as usize, py as usize))
+    }
+
     pub fn render(&self) -> Result<(), pixels::Error> {
-        self.pixels.render()
+        if self.scaling == Scaling::Integer {
+            return self.pixels.render();
+        }
+
+        let rect = self.picture_rect();
+        self.pixels.render_with(|encoder, render_target, context| {
+            self.rect_renderer
+                .render(encoder, render_target, context, rect);
+            Ok(())
+        })
     }
 
     pub fn draw_sprite(&mut self, sprite: &Sprite, x: usize, y: usize) {
@@ -244,3 +469,60 @@ impl Renderer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_overscan() {
+        assert_eq!("8".parse::<Overscan>().unwrap(), Overscan::default());
+        assert_eq!(
+            "4, 8, 2, 1".parse::<Overscan>().unwrap(),
+            Overscan {
+                top: 4,
+                bottom: 8,
+                left: 2,
+                right: 1
+            }
+        );
+        assert!("1,2".parse::<Overscan>().is_err());
+        assert!("a".parse::<Overscan>().is_err());
+    }
+
+    #[test]
+    fn crop_sprite() {
+        let pixels = (0..16).map(|i| Color(i, 0, 0)).collect();
+        let sprite = Sprite::new(pixels, 4, 4).unwrap();
+
+        let cropped = sprite.crop(&Overscan {
+            top: 1,
+            bottom: 2,
+            left: 1,
+            right: 0,
+        });
+        assert_eq!(cropped.width(), 3);
+        assert_eq!(cropped.height(), 1);
+        assert_eq!(
+            cropped.pixels(),
+            &[Color(5, 0, 0), Color(6, 0, 0), Color(7, 0, 0)]
+        );
+
+        assert_eq!(sprite.crop(&"0".parse().unwrap()).pixels(), sprite.pixels());
+    }
+
+    #[test]
+    fn post_process_blends_and_darkens() {
+        let mut pixels = vec![Color::BLACK; SCREEN_WIDTH * 2];
+        pixels[SCREEN_WIDTH - 1] = Color::WHITE;
+        pixels[SCREEN_WIDTH * 2 - 1] = Color::WHITE;
+
+        let processed = post_process(&pixels);
+        assert_eq!(processed.len(), pixels.len());
+        assert_eq!(processed[SCREEN_WIDTH - 2], Color(63, 63, 63));
+        assert_eq!(processed[SCREEN_WIDTH - 1], Color(191, 191, 191));
+        // The last pixel of a row doesn't bleed into the start of the next one
+        assert_eq!(processed[SCREEN_WIDTH], Color::BLACK);
+        assert_eq!(processed[SCREEN_WIDTH * 2 - 1], Color(143, 143, 143));
+    }
+}
@@ -0,0 +1,282 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use pixels::{wgpu, PixelsContext};
+
+/// The NES draws pixels slightly wider than they are tall, 8:7 on an NTSC TV.
+/// See: https://www.nesdev.org/wiki/Overscan#For_emulator_developers
+const PIXEL_ASPECT_RATIO: f32 = 8.0 / 7.0;
+
+const SHADER: &str = r"
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coord = fma(position, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0) var r_tex_color: texture_2d<f32>;
+@group(0) @binding(1) var r_tex_sampler: sampler;
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(r_tex_color, r_tex_sampler, tex_coord);
+}
+";
+
+/// How the picture is scaled up to fill the window.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// The largest whole multiple that fits, so every pixel is the same size.
+    #[default]
+    Integer,
+    /// Fills the whole window, whatever its shape.
+    Stretch,
+    /// As large as fits, with the 8:7 pixels the picture had on a TV.
+    Aspect,
+}
+
+impl Scaling {
+    /// The next mode, for cycling through them with a key.
+    pub fn next(self) -> Self {
+        match self {
+            Scaling::Integer => Scaling::Stretch,
+            Scaling::Stretch => Scaling::Aspect,
+            Scaling::Aspect => Scaling::Integer,
+        }
+    }
+
+    /// Where a buffer of `buffer` size goes on a surface of `surface` size,
+    /// as `(x, y, width, height)` in surface pixels.
+    pub fn fit(self, buffer: (f32, f32), surface: (f32, f32)) -> (f32, f32, f32, f32) {
+        let (width, height) = match self {
+            // Never smaller than the buffer, like pixels' own scaling
+            Scaling::Integer => {
+                let scale = (surface.0 / buffer.0)
+                    .min(surface.1 / buffer.1)
+                    .floor()
+                    .max(1.0);
+                (buffer.0 * scale, buffer.1 * scale)
+            }
+            Scaling::Stretch => surface,
+            Scaling::Aspect => {
+                let width = buffer.0 * PIXEL_ASPECT_RATIO;
+                let scale = (surface.0 / width).min(surface.1 / buffer.1);
+                (width * scale, buffer.1 * scale)
+            }
+        };
+
+        (
+            ((surface.0 - width) / 2.0).floor(),
+            ((surface.1 - height) / 2.0).floor(),
+            width,
+            height,
+        )
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "integer" => Ok(Scaling::Integer),
+            "stretch" => Ok(Scaling::Stretch),
+            "aspect" | "8:7" => Ok(Scaling::Aspect),
+            _ => Err(anyhow!(
+                "Invalid scaling {:?}, expected integer, stretch or aspect",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Scaling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scaling::Integer => write!(f, "integer"),
+            Scaling::Stretch => write!(f, "stretch"),
+            Scaling::Aspect => write!(f, "8:7 aspect"),
+        }
+    }
+}
+
+/// Draws the pixels' texture into any rectangle of the surface, for the modes that
+/// pixels' `ScalingRenderer` can't do since it only scales by whole numbers.
+pub(super) struct RectRenderer {
+    vertex_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl RectRenderer {
+    pub fn new(device: &wgpu::Device, render_texture_format: wgpu::TextureFormat) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("rect_renderer_shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("rect_renderer_sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        // One triangle covering the whole viewport
+        let vertex_data: Vec<u8> = [-1.0f32, -1.0, 3.0, -1.0, -1.0, 3.0]
+            .iter()
+            .flat_map(|f| f.to_ne_bytes())
+            .collect();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("rect_renderer_vertex_buffer"),
+            contents: &vertex_data,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("rect_renderer_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("rect_renderer_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("rect_renderer_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+                }],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: render_texture_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            vertex_buffer,
+            sampler,
+            bind_group_layout,
+            render_pipeline,
+        }
+    }
+
+    /// Clears the surface to black and draws the texture into `rect`, see `Scaling::fit`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        context: &PixelsContext,
+        rect: (f32, f32, f32, f32),
+    ) {
+        // Made each frame, since pixels replaces the texture when the buffer is resized
+        let texture_view = context
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("rect_renderer_bind_group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("rect_renderer_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        let (x, y, width, height) = rect;
+        rpass.set_viewport(x, y, width, height, 0.0, 1.0);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fit() {
+        let buffer = (256.0, 224.0);
+        let surface = (1000.0, 700.0);
+
+        // 3x, centered
+        assert_eq!(
+            Scaling::Integer.fit(buffer, surface),
+            (116.0, 14.0, 768.0, 672.0)
+        );
+        assert_eq!(
+            Scaling::Stretch.fit(buffer, surface),
+            (0.0, 0.0, 1000.0, 700.0)
+        );
+        // 8:7 pixels make 224 pixels as wide as 256 square ones, and it fills the height
+        let (x, y, width, height) = Scaling::Aspect.fit((224.0, 200.0), surface);
+        assert_eq!((x, y), (52.0, 0.0));
+        assert!((width - 896.0).abs() < 0.01);
+        assert!((height - 700.0).abs() < 0.01);
+    }
+}
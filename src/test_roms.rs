@@ -0,0 +1,188 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use nesrs::emu::{
+    cartridge::{self, Cartridge, Header},
+    nes::Nes,
+    palette::Palette,
+};
+
+/// What happened when a ROM was run, from best to worst.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Ran every frame, and ended on the same picture when run again, with this hash.
+    Ok(u64),
+    /// Ran every frame, but ended on a single color, which usually means the game is stuck.
+    Blank,
+    /// Ended on a different picture when run again from power on.
+    Unstable,
+    Panicked(String),
+    /// Couldn't be loaded, usually because the mapper isn't supported.
+    Unsupported(String),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Ok(hash) => write!(f, "ok {:016x}", hash),
+            Outcome::Blank => write!(f, "blank"),
+            Outcome::Unstable => write!(f, "unstable"),
+            Outcome::Panicked(message) => write!(f, "panicked: {}", message),
+            Outcome::Unsupported(err) => write!(f, "unsupported: {}", err),
+        }
+    }
+}
+
+/// Runs every `.nes` and `.zip` file in `dir` for `frames` frames without a window or audio,
+/// and prints a table of how each one did, for sorting out which games work.
+pub fn run(dir: &Path, frames: u32) -> Result<()> {
+    if cfg!(panic = "abort") {
+        return Err(anyhow!(
+            "test-roms can't catch panics in this build, run it with `cargo run --profile test-roms -- test-roms <dir>`"
+        ));
+    }
+
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nes") || ext.eq_ignore_ascii_case("zip"))
+    });
+    paths.sort();
+    if paths.is_empty() {
+        return Err(anyhow!("No ROMs found in {}", dir.display()));
+    }
+
+    let names: Vec<_> = paths
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+        .collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    println!("{:<width$}  Mapper  Result", "ROM");
+
+    // The panics are reported in the table, so don't print them as they happen too
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut outcomes = Vec::new();
+    for (path, name) in paths.iter().zip(&names) {
+        let (mapper, outcome) = match cartridge::read_rom_file(path) {
+            Ok(bytes) => {
+                let mapper = Header::parse(&bytes)
+                    .map_or("-".to_string(), |header| header.mapper_num().to_string());
+                (mapper, test_rom(&bytes, frames))
+            }
+            Err(err) => ("-".to_string(), Outcome::Unsupported(err.to_string())),
+        };
+        println!("{:<width$}  {:>6}  {}", name, mapper, outcome);
+        outcomes.push(outcome);
+    }
+
+    panic::set_hook(hook);
+
+    let count = |matches: fn(&Outcome) -> bool| outcomes.iter().filter(|o| matches(o)).count();
+    println!(
+        "\n{} ok, {} blank, {} unstable, {} panicked, {} unsupported",
+        count(|o| matches!(o, Outcome::Ok(_))),
+        count(|o| matches!(o, Outcome::Blank)),
+        count(|o| matches!(o, Outcome::Unstable)),
+        count(|o| matches!(o, Outcome::Panicked(_))),
+        count(|o| matches!(o, Outcome::Unsupported(_))),
+    );
+
+    Ok(())
+}
+
+/// Runs the ROM twice from power on, to check that it ends on the same picture both times.
+fn test_rom(bytes: &[u8], frames: u32) -> Outcome {
+    // Built in so it runs from anywhere, and the default palette is all black
+    let palette_data = include_bytes!("../assets/palettes/2C02G.pal");
+    let palette = Palette::from_pal_bytes(palette_data).expect("the built in palette is valid");
+
+    let run = || -> Result<(u64, bool)> {
+        let cartridge = Cartridge::from_bytes(bytes)?;
+        let mut nes = Nes::new(palette.clone(), cartridge.region());
+        nes.load_cartridge(cartridge);
+        nes.power_on();
+        nes.run_frames(frames);
+
+        let pixels = nes.screen().pixels();
+        let blank = pixels.iter().all(|&color| color == pixels[0]);
+        Ok((nes.screen_hash(), blank))
+    };
+
+    let first = match catch_panic(run) {
+        Ok(Ok(result)) => result,
+        Ok(Err(err)) => return Outcome::Unsupported(err.to_string()),
+        Err(message) => return Outcome::Panicked(message),
+    };
+    let second = match catch_panic(run) {
+        Ok(Ok(result)) => result,
+        // It already loaded and ran once, so this is as unstable as a different picture
+        Ok(Err(_)) | Err(_) => return Outcome::Unstable,
+    };
+
+    match (first, second) {
+        ((first, _), (second, _)) if first != second => Outcome::Unstable,
+        ((_, true), _) => Outcome::Blank,
+        ((hash, false), _) => Outcome::Ok(hash),
+    }
+}
+
+/// Runs `f`, turning a panic into its message.
+fn catch_panic<T, F: FnOnce() -> T>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(&*payload))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let rom = fs::read("assets/test_roms/nestest.nes").unwrap();
+        assert!(matches!(test_rom(&rom, 5), Outcome::Ok(_)));
+
+        // Mapper 255 doesn't exist
+        let mut unsupported = rom.clone();
+        unsupported[6] |= 0xF0;
+        unsupported[7] |= 0xF0;
+        assert_eq!(
+            test_rom(&unsupported, 5),
+            Outcome::Unsupported("Unimplemented mapper 255".to_string())
+        );
+
+        // A NES 2.0 PRG ROM size of 2^63 * 7 bytes
+        let mut oversized = rom.clone();
+        oversized[4] = 0xFF;
+        oversized[7] = 0x08;
+        oversized[9] = 0x0F;
+        assert_eq!(
+            test_rom(&oversized, 5),
+            Outcome::Unsupported("PRG ROM size of 2^63 * 7 is too large".to_string())
+        );
+
+        assert_eq!(catch_panic(|| 1), Ok(1));
+        assert_eq!(
+            catch_panic(|| -> u8 { panic!("Invalid APU address {:#06X}", 0x4018) }),
+            Err("Invalid APU address 0x4018".to_string())
+        );
+    }
+}
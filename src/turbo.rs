@@ -0,0 +1,69 @@
+use crate::emu::input::ControllerButtons;
+
+/// Pulses held turbo buttons on and off at a fixed rate.
+pub struct TurboState {
+    // Length of one on/off cycle in frames
+    period: u32,
+    frame: u32,
+}
+
+impl TurboState {
+    /// `rate` is the number of presses per second.
+    /// Games read input once a frame, so it's capped at half the frame rate.
+    pub fn new(rate: u32, fps: f64) -> Self {
+        let period = (fps / rate.max(1) as f64).round() as u32;
+
+        TurboState {
+            period: period.max(2),
+            frame: 0,
+        }
+    }
+
+    /// Called once for every emulated frame.
+    pub fn tick(&mut self) {
+        self.frame = (self.frame + 1) % self.period;
+    }
+
+    /// Returns the held turbo buttons if they are in the pressed half of the cycle.
+    pub fn apply(&self, held: ControllerButtons) -> ControllerButtons {
+        if self.frame < self.period / 2 {
+            held
+        } else {
+            ControllerButtons::empty()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emu::region::Region;
+
+    fn pressed_frames(turbo: &mut TurboState, frames: usize) -> Vec<bool> {
+        (0..frames)
+            .map(|_| {
+                let pressed = !turbo.apply(ControllerButtons::A).is_empty();
+                turbo.tick();
+                pressed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turbo_toggles_at_rate() {
+        let mut turbo = TurboState::new(30, Region::Ntsc.fps());
+        assert_eq!(pressed_frames(&mut turbo, 4), [true, false, true, false]);
+
+        let mut turbo = TurboState::new(15, Region::Ntsc.fps());
+        assert_eq!(
+            pressed_frames(&mut turbo, 8),
+            [true, true, false, false, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn turbo_rate_capped() {
+        let mut turbo = TurboState::new(60, Region::Ntsc.fps());
+        assert_eq!(pressed_frames(&mut turbo, 4), [true, false, true, false]);
+    }
+}
@@ -1,15 +1,96 @@
+use std::{path::Path, time::Duration};
+
 use crate::{
     emu::{
-        cpu::{
-            instructions::{AddressMode, Instruction},
-            Cpu,
-        },
+        cartridge::Cartridge,
+        cpu::{disasm::format_instruction, instructions::Instruction, Cpu},
         nes::Nes,
         ppu::{PatternTable, Ppu},
     },
     renderer::{Color, Renderer, Sprite},
+    utils::FrameTimes,
 };
 
+const PATTERN_TABLE_SIZE: usize = 128;
+const HEX_BYTES_PER_ROW: usize = 8;
+const HEX_ROW_HEIGHT: usize = 20;
+
+/// Which part of the cartridge the hex view shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomMemory {
+    Prg,
+    Chr,
+}
+
+impl RomMemory {
+    fn len(self, cartridge: &Cartridge) -> usize {
+        match self {
+            RomMemory::Prg => cartridge.prg_len(),
+            RomMemory::Chr => cartridge.chr_len(),
+        }
+    }
+
+    fn byte(self, cartridge: &Cartridge, offset: usize) -> Option<u8> {
+        match self {
+            RomMemory::Prg => cartridge.prg_byte(offset),
+            RomMemory::Chr => cartridge.chr_byte(offset),
+        }
+    }
+}
+
+/// Lines describing the loaded ROM for the info overlay.
+pub fn rom_info(rom_path: &str, cartridge: &Cartridge) -> Vec<String> {
+    let file_name = Path::new(rom_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let chr = match cartridge.chr_size() {
+        0 => "CHR RAM".to_string(),
+        size => format!("CHR {} KB", size / 1024),
+    };
+
+    let mut lines = vec![
+        file_name,
+        format!("Mapper {}", cartridge.mapper_num()),
+        format!("PRG {} KB", cartridge.prg_size() / 1024),
+        chr,
+        format!("{:?}", cartridge.mirroring()),
+        cartridge.region().to_string(),
+    ];
+    if cartridge.is_battery_backed() {
+        lines.push("Battery".to_string());
+    }
+    lines
+}
+
+pub fn draw_rom_info(renderer: &mut Renderer, lines: &[String], x: usize, y: usize) {
+    let background = Sprite::monocolor(Color::BLACK, renderer.width() - x, lines.len() * 20 + 4);
+    renderer.draw_sprite(&background, x, y);
+
+    for (i, line) in lines.iter().enumerate() {
+        renderer.draw_text(line, x + 2, y + 2 + i * 20);
+    }
+}
+
+pub fn draw_bank_layout(renderer: &mut Renderer, cartridge: &Cartridge, x: usize, y: usize) {
+    let layout = cartridge.bank_layout();
+
+    renderer.draw_text("PRG Banks", x, y);
+    for (i, (start, bank)) in layout.prg.iter().enumerate() {
+        renderer.draw_text(&format!("${:04X}: {}", start, bank), x, y + 20 + i * 20);
+    }
+
+    renderer.draw_text("CHR Banks", x + 160, y);
+    for (i, (start, bank)) in layout.chr.iter().enumerate() {
+        renderer.draw_text(
+            &format!("${:04X}: {}", start, bank),
+            x + 160,
+            y + 20 + i * 20,
+        );
+    }
+}
+
 pub fn draw_oam(renderer: &mut Renderer, oam: &[u8], x: usize, y: usize) {
     for (i, sprite) in oam.chunks_exact(4).enumerate().take(8) {
         let sp_y = sprite[0];
@@ -38,6 +119,52 @@ pub fn draw_mem_page(renderer: &mut Renderer, nes: &Nes, page: u8, x: usize, y:
     }
 }
 
+/// How many rows of the hex view fit below its title in a given height.
+pub fn rom_hex_visible_rows(height: usize) -> usize {
+    (height / HEX_ROW_HEIGHT).saturating_sub(1).max(1)
+}
+
+/// The last row the hex view can scroll to while still filling the screen.
+pub fn rom_hex_max_row(cartridge: &Cartridge, memory: RomMemory, visible_rows: usize) -> usize {
+    memory
+        .len(cartridge)
+        .div_ceil(HEX_BYTES_PER_ROW)
+        .saturating_sub(visible_rows)
+}
+
+/// Draws the raw contents of PRG or CHR memory as it is in the ROM file, starting at a row of
+/// 8 bytes. Unlike `draw_mem_page` this doesn't go through the mapper, so it shows every bank.
+pub fn draw_rom_hex(
+    renderer: &mut Renderer,
+    cartridge: &Cartridge,
+    memory: RomMemory,
+    first_row: usize,
+    x: usize,
+    y: usize,
+) {
+    let background = Sprite::monocolor(Color::BLACK, renderer.width() - x, renderer.height() - y);
+    renderer.draw_sprite(&background, x, y);
+
+    let len = memory.len(cartridge);
+    renderer.draw_text(&format!("{:?} {} bytes", memory, len), x, y);
+
+    let rows = rom_hex_visible_rows(renderer.height() - y);
+    for i in 0..rows {
+        let start = (first_row + i) * HEX_BYTES_PER_ROW;
+        if start >= len {
+            break;
+        }
+
+        let mut line = format!("{:05X}  ", start);
+        for offset in start..(start + HEX_BYTES_PER_ROW).min(len) {
+            if let Some(byte) = memory.byte(cartridge, offset) {
+                line.push_str(&format!("{:02X} ", byte));
+            }
+        }
+        renderer.draw_text(&line, x, y + (i + 1) * HEX_ROW_HEIGHT);
+    }
+}
+
 pub fn draw_flags(renderer: &mut Renderer, flags: u8, text: &str, x: usize, y: usize) {
     renderer.draw_text_with_computed_color(text, x, y, |i| {
         if flags & (1 << (7 - i)) != 0 {
@@ -59,6 +186,12 @@ pub fn draw_cpu_info(renderer: &mut Renderer, nes: &Nes, x: usize, y: usize) {
     renderer.draw_text("Status:", x, y + 100);
 
     draw_flags(renderer, cpu.status().bits(), "NVUBDIZC", x + 96, y + 100);
+    // Pending interrupts, and whether an IRQ would be ignored
+    let interrupts = ((cpu.pending_nmi() as u8) << 7)
+        | ((cpu.pending_irq() as u8) << 6)
+        | ((cpu.interrupts_disabled() as u8) << 5);
+    renderer.draw_text("Interrupts:", x, y + 120);
+    draw_flags(renderer, interrupts, "NQI", x + 144, y + 120);
     renderer.draw_text(&format!("PC: {:#06X}", cpu.pc()), x, y + 140);
 
     renderer.draw_text(&format!("Cycles: {}", cpu.cycles()), x, y + 160);
@@ -79,34 +212,7 @@ pub fn draw_cpu_info(renderer: &mut Renderer, nes: &Nes, x: usize, y: usize) {
 }
 
 pub fn get_instruction_repr(cpu: &Cpu, addr: u16) -> String {
-    let instruction = Instruction::lookup(cpu.read_debug(addr));
-    let arg_addr = addr + 1;
-
-    let name = instruction.instruction_type.as_ref().to_uppercase();
-
-    match instruction.address_mode {
-        AddressMode::Imp => name,
-        AddressMode::Acc => format!("{} A", name),
-        AddressMode::Imm => format!("{} #${:02X}", name, cpu.read_debug(arg_addr)),
-        AddressMode::Zp0 => format!("{} ${:02X}", name, cpu.read_debug(arg_addr),),
-        AddressMode::Zpx => format!("{} ${:02X},X", name, cpu.read_debug(arg_addr)),
-        AddressMode::Zpy => format!("{} ${:02X},Y", name, cpu.read_debug(arg_addr)),
-        AddressMode::Rel => {
-            let offset = cpu.read_debug(arg_addr) as i8;
-            let computed_addr = if offset < 0 {
-                addr - (offset.unsigned_abs() as u16)
-            } else {
-                addr + offset as u16
-            };
-            format!("{} ${:02X}", name, computed_addr + 2)
-        }
-        AddressMode::Abs => format!("{} ${:04X}", name, cpu.read_debug_u16(arg_addr)),
-        AddressMode::Abx => format!("{} ${:04X},X", name, cpu.read_debug_u16(arg_addr)),
-        AddressMode::Aby => format!("{} ${:04X},Y", name, cpu.read_debug_u16(arg_addr)),
-        AddressMode::Ind => format!("{} (${:04X})", name, cpu.read_debug_u16(arg_addr)),
-        AddressMode::Izx => format!("{} (${:02X},X)", name, cpu.read_debug(arg_addr)),
-        AddressMode::Izy => format!("{} (${:02X}),Y", name, cpu.read_debug(arg_addr)),
-    }
+    format_instruction(addr, |addr| cpu.read_debug(addr))
 }
 
 pub fn draw_ppu_info(renderer: &mut Renderer, ppu: &Ppu, x: usize, y: usize) {
@@ -125,13 +231,140 @@ pub fn draw_ppu_info(renderer: &mut Renderer, ppu: &Ppu, x: usize, y: usize) {
     renderer.draw_text(&format!("DATA: {:#06X}", ppu.data()), x, y + 120);
 }
 
-pub fn draw_pattern_tables(renderer: &mut Renderer, ppu: &Ppu, x: usize, y: usize) {
-    let left_pattern_table = ppu.get_pattern_table(PatternTable::Left);
-    let right_pattern_table = ppu.get_pattern_table(PatternTable::Right);
+/// FPS and a graph of how long each recent frame took to emulate, where a full height bar
+/// takes the whole `frame_budget`. Red bars took too long to keep up with real time.
+pub fn draw_perf_overlay(
+    renderer: &mut Renderer,
+    fps: f32,
+    frame_times: &FrameTimes,
+    frame_budget: Duration,
+    x: usize,
+    y: usize,
+) {
+    const GRAPH_HEIGHT: usize = 32;
+    const BAR_WIDTH: usize = 2;
+
+    let width = frame_times.capacity() * BAR_WIDTH + 4;
+    let background = Sprite::monocolor(Color::BLACK, width, GRAPH_HEIGHT + 48);
+    renderer.draw_sprite(&background, x, y);
+
+    renderer.draw_text(&format!("FPS {:.1}", fps), x + 2, y + 2);
+    renderer.draw_text(
+        &format!("Max {:.1} ms", frame_times.max().as_secs_f64() * 1000.0),
+        x + 2,
+        y + 22,
+    );
+
+    let graph_y = y + 44 + GRAPH_HEIGHT;
+    for (i, time) in frame_times.iter().enumerate() {
+        let fraction = time.as_secs_f64() / frame_budget.as_secs_f64();
+        let height = ((fraction * GRAPH_HEIGHT as f64).ceil() as usize).clamp(1, GRAPH_HEIGHT);
+        let color = if time > frame_budget {
+            Color::RED
+        } else {
+            Color::GREEN
+        };
+        let bar = Sprite::monocolor(color, BAR_WIDTH, height);
+        renderer.draw_sprite(&bar, x + 2 + i * BAR_WIDTH, graph_y - height);
+    }
+}
+
+/// The dot the PPU is about to draw, for stepping through a frame while paused.
+pub fn draw_beam_position(renderer: &mut Renderer, ppu: &Ppu, x: usize, y: usize) {
+    let background = Sprite::monocolor(Color::BLACK, renderer.width() - x, 24);
+    renderer.draw_sprite(&background, x, y);
+    renderer.draw_text(
+        &format!("Line {} Dot {}", ppu.scanline(), ppu.cycle()),
+        x + 2,
+        y + 2,
+    );
+}
+
+/// Lines across the picture where sprite 0 hit (green) and the mapper's IRQ (red) happened
+/// in the last frame, which is where games usually split the screen. `top` is how many scanlines
+/// the overscan hides above the picture.
+pub fn draw_raster_markers(renderer: &mut Renderer, ppu: &Ppu, top: usize) {
+    let markers = [
+        (ppu.last_frame_sprite_zero_hit_scanline(), Color::GREEN),
+        (ppu.last_frame_mapper_irq_scanline(), Color::RED),
+    ];
+    for (scanline, color) in markers {
+        let Some(y) = scanline
+            .and_then(|scanline| usize::try_from(scanline).ok())
+            .and_then(|scanline| scanline.checked_sub(top))
+            .filter(|&y| y < renderer.height())
+        else {
+            continue;
+        };
+        renderer.draw_sprite(&Sprite::monocolor(color, renderer.width(), 1), 0, y);
+    }
+}
+
+pub fn draw_pattern_tables(renderer: &mut Renderer, ppu: &Ppu, palette: u8, x: usize, y: usize) {
+    let left_pattern_table = ppu.get_pattern_table(PatternTable::Left, palette);
+    let right_pattern_table = ppu.get_pattern_table(PatternTable::Right, palette);
 
     renderer.draw_text("Pattern Tables", x, y);
+    // Side by side, so both fit across the screen
     renderer.draw_sprite(&left_pattern_table, x, y + 24);
-    renderer.draw_sprite(&right_pattern_table, x + 144, y + 24);
+    renderer.draw_sprite(&right_pattern_table, x + PATTERN_TABLE_SIZE, y + 24);
+}
+
+/// The tile under the pixel at `(px, py)`, when the pattern tables are drawn at `(x, y)`
+/// with `draw_pattern_tables`.
+pub fn pattern_table_tile_at(
+    px: usize,
+    py: usize,
+    x: usize,
+    y: usize,
+) -> Option<(PatternTable, u8)> {
+    let dx = px.checked_sub(x)?;
+    let dy = py.checked_sub(y + 24)?;
+    if dx >= PATTERN_TABLE_SIZE * 2 || dy >= PATTERN_TABLE_SIZE {
+        return None;
+    }
+
+    let table = PatternTable::from(dx >= PATTERN_TABLE_SIZE);
+    let tile = (dy / 8) * 16 + (dx % PATTERN_TABLE_SIZE) / 8;
+    Some((table, tile as u8))
+}
+
+/// The tile's address and index, then its 16 bytes, low bit plane first.
+/// Drawn next to the cursor at `(x, y)`, moved to stay on screen.
+pub fn draw_tile_tooltip(
+    renderer: &mut Renderer,
+    ppu: &Ppu,
+    table: PatternTable,
+    tile: u8,
+    x: usize,
+    y: usize,
+) {
+    const WIDTH: usize = 184;
+    const HEIGHT: usize = 104;
+
+    let x = (x + 8).min(renderer.width().saturating_sub(WIDTH));
+    let y = if y + 8 + HEIGHT <= renderer.height() {
+        y + 8
+    } else {
+        y.saturating_sub(HEIGHT + 8)
+    };
+
+    let addr = table.addr() + ((tile as u16) << 4);
+    let background = Sprite::monocolor(Color::BLACK, WIDTH, HEIGHT);
+    renderer.draw_sprite(&background, x, y);
+    renderer.draw_text(&format!("${:04X}: Tile ${:02X}", addr, tile), x + 2, y + 2);
+    for row in 0..4 {
+        let bytes: Vec<String> = (0..4)
+            .map(|i| format!("{:02X}", ppu.read_debug(addr + row * 4 + i)))
+            .collect();
+        renderer.draw_text(&bytes.join(" "), x + 2, y + 22 + row as usize * 20);
+    }
+}
+
+pub fn draw_nametables(renderer: &mut Renderer, ppu: &Ppu, x: usize, y: usize) {
+    renderer.draw_text("Nametables", x, y);
+    renderer.draw_sprite(&ppu.render_nametable(0), x, y + 24);
+    renderer.draw_sprite(&ppu.render_nametable(1), x + 264, y + 24);
 }
 
 fn palette_sprite(ppu: &Ppu, palette_index: u8) -> Sprite {
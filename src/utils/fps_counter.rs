@@ -1,4 +1,7 @@
-use std::{collections::VecDeque, time::Instant};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
 
 const MAX_SAMPLES: usize = 60;
 
@@ -8,6 +11,12 @@ pub struct FpsCounter {
     tick_sum: f32,
 }
 
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FpsCounter {
     pub fn new() -> Self {
         FpsCounter {
@@ -31,6 +40,54 @@ impl FpsCounter {
     }
 
     pub fn get_fps(&self) -> f32 {
-        1.0 / (self.tick_sum / MAX_SAMPLES as f32)
+        if self.ticks.is_empty() {
+            return 0.0;
+        }
+        1.0 / (self.tick_sum / self.ticks.len() as f32)
+    }
+}
+
+/// The time taken by each of the last `MAX_SAMPLES` frames, oldest first.
+#[derive(Default)]
+pub struct FrameTimes {
+    times: VecDeque<Duration>,
+}
+
+impl FrameTimes {
+    pub fn push(&mut self, time: Duration) {
+        self.times.push_back(time);
+        if self.times.len() > MAX_SAMPLES {
+            self.times.pop_front();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.times.iter().copied()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.iter().max().unwrap_or_default()
+    }
+
+    /// How many frames are kept, and how many bars a graph of them needs.
+    pub fn capacity(&self) -> usize {
+        MAX_SAMPLES
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_times_keep_the_latest() {
+        let mut times = FrameTimes::default();
+        for ms in 0..100 {
+            times.push(Duration::from_millis(ms));
+        }
+
+        assert_eq!(times.iter().count(), MAX_SAMPLES);
+        assert_eq!(times.iter().next(), Some(Duration::from_millis(40)));
+        assert_eq!(times.max(), Duration::from_millis(99));
     }
 }
@@ -1,4 +1,7 @@
-#[allow(dead_code)]
 mod fps_counter;
+mod screenshot;
+mod wav;
 
-pub use self::fps_counter::FpsCounter;
+pub use self::fps_counter::{FpsCounter, FrameTimes};
+pub use self::screenshot::{encode_png, save_screenshot};
+pub use self::wav::WavWriter;
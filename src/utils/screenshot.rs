@@ -0,0 +1,64 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::renderer::Sprite;
+
+/// Saves the sprite as a PNG in `dir`, named after the current time.
+/// Returns the path of the saved file.
+pub fn save_screenshot<P: AsRef<Path>>(sprite: &Sprite, dir: P) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = dir.as_ref().join(format!("screenshot-{}.png", timestamp));
+
+    let file = File::create(&path)?;
+    encode_png(sprite, BufWriter::new(file))?;
+
+    Ok(path)
+}
+
+pub fn encode_png<W: Write>(sprite: &Sprite, w: W) -> Result<()> {
+    let mut encoder = png::Encoder::new(w, sprite.width() as u32, sprite.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&sprite.to_rgba())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::renderer::Color;
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = vec![
+            Color(255, 0, 0),
+            Color(0, 255, 0),
+            Color(0, 0, 255),
+            Color::WHITE,
+        ];
+        let sprite = Sprite::new(pixels, 2, 2).unwrap();
+
+        let mut buf = Vec::new();
+        encode_png(&sprite, &mut buf).unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(buf))
+            .read_info()
+            .unwrap();
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut data).unwrap();
+
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(&data[..8], &[255, 0, 0, 255, 0, 255, 0, 255]);
+        assert_eq!(&data[12..16], &[255, 255, 255, 255]);
+    }
+}